@@ -12,24 +12,19 @@ use futures::Future;
 use mail_internals::MailType;
 
 // In the facade this is the `headers` module.
-use mail_headers::{
-    headers::*,
-    header_components::Domain
-};
+use mail_headers::headers::*;
 
 // In the facade this types (and the default_impl module)
 // are also exposed at top level
 use mail_core::{
     Mail,
+    utils::parse_domain,
     default_impl::simple_context,
     error::MailError
 };
 
 fn print_some_mail() -> Result<(), MailError> {
-    // Domain will implement `from_str` in the future,
-    // currently it doesn't have a validator/parser.
-    // So this will become `"example.com".parse()`
-    let domain = Domain::from_unchecked("example.com".to_owned());
+    let domain = parse_domain("example.com")?;
     // Normally you create this _once per application_.
     let ctx = simple_context::new(domain, "xqi93".parse().expect("we know it's ascii"))
         .expect("this is basically: failed to get cwd from env");
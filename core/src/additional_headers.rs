@@ -0,0 +1,24 @@
+//! Module containing headers not provided by `mail-headers`.
+//!
+//! The headers defined here are mainly used by the convenience
+//! setters on `Mail` but can be used like any other header.
+use headers::header_components as components;
+
+def_headers! {
+    test_name: validate_additional_header_names,
+    scope: components,
+    /// (rfc3834) marks a mail as automatically submitted
+    AutoSubmitted, unchecked { "Auto-Submitted" }, Unstructured, maxOne, None,
+    /// (rfc2557) the URI the content of a body part is associated with
+    ContentLocation, unchecked { "Content-Location" }, Unstructured, maxOne, None,
+    /// (rfc3803) the duration of a audio/video body part in seconds
+    ContentDuration, unchecked { "Content-Duration" }, Unstructured, maxOne, None,
+    /// (rfc1864) base64 encoded MD5 digest of the (decoded) body
+    ContentMd5, unchecked { "Content-MD5" }, Unstructured, maxOne, None,
+    /// (non-standard) priority of the mail from 1 (highest) to 5 (lowest)
+    XPriority, unchecked { "X-Priority" }, Unstructured, maxOne, None,
+    /// (rfc2156) importance of the mail (`high`, `normal` or `low`)
+    Importance, unchecked { "Importance" }, Unstructured, maxOne, None,
+    /// (non-standard) priority of the mail as used by Outlook
+    XMsMailPriority, unchecked { "X-MSMail-Priority" }, Unstructured, maxOne, None
+}
@@ -26,19 +26,129 @@ use serde::{Serialize, Deserialize};
 use headers::{
     HeaderKind,
     headers,
+    error::ComponentCreationError,
     header_components::{
         ContentId,
         Disposition,
         DispositionKind,
-        MediaType
+        FileMeta,
+        MediaType,
+        Mailbox,
+        MailboxList,
+        OptMailboxList,
+        Phrase
     }
 };
 
 use ::mail::Mail;
-use ::context::Context;
+use ::context::{Context, MailIdScope};
 use ::resource::Resource;
+use ::error::{MailError, OtherValidationError};
 
 
+/// The sender, recipients and subject of a composed mail.
+///
+/// This is used by `compose_mail` to create the `From`, `To`,
+/// `Cc`, `Bcc` and `Subject` headers of the composed mail.
+#[derive(Debug, Clone)]
+pub struct MailSendContext {
+    /// The mailbox used for the `From` header.
+    pub from: Mailbox,
+
+    /// The mailboxes used for the `To` header.
+    pub to: MailboxList,
+
+    /// The mailboxes used for the `Cc` header.
+    ///
+    /// If empty no `Cc` header is added.
+    pub cc: Vec<Mailbox>,
+
+    /// The mailboxes used for the `Bcc` header.
+    ///
+    /// If empty no `Bcc` header is added. Note that the `Bcc` header
+    /// is never encoded, it's only used for the SMTP envelope.
+    pub bcc: Vec<Mailbox>,
+
+    /// The text used for the `Subject` header.
+    pub subject: String,
+
+    /// If set it's used to derive display names for mailboxes without one.
+    ///
+    /// The names are derived for the `From`, `To` and `Cc` mailboxes,
+    /// existing display names are never changed. Defaults to `None`,
+    /// i.e. mailboxes are used as given.
+    pub name_composer: Option<DefaultNameComposer>
+}
+
+impl MailSendContext {
+
+    /// Create a new `MailSendContext` instance without `Cc` and `Bcc` mailboxes.
+    pub fn new(from: Mailbox, to: MailboxList, subject: impl Into<String>) -> Self {
+        MailSendContext {
+            from,
+            to,
+            cc: Vec::new(),
+            bcc: Vec::new(),
+            subject: subject.into(),
+            name_composer: None
+        }
+    }
+}
+
+/// Derives display names from the local-part of email addresses.
+///
+/// This is meant for mailboxes which have no display name, e.g.
+/// `john.doe@example.com` has the local-part `john.doe` from which
+/// the display name `John Doe` is derived. The local-part is split
+/// at `'.'`, `'_'` and `'-'` and the first char of each word is
+/// capitalized.
+///
+/// It can be used by `compose_mail` for all mailboxes without display name
+/// (see `MailSendContext::name_composer`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultNameComposer;
+
+impl DefaultNameComposer {
+
+    /// Derive a display name from given email local-part.
+    pub fn compose_name(&self, local_part: &str) -> String {
+        let mut name = String::with_capacity(local_part.len());
+        let words = local_part
+            .split(|ch| ch == '.' || ch == '_' || ch == '-')
+            .filter(|word| !word.is_empty());
+
+        for word in words {
+            if !name.is_empty() {
+                name.push(' ');
+            }
+            let mut chars = word.chars();
+            if let Some(first) = chars.next() {
+                name.extend(first.to_uppercase());
+                name.push_str(chars.as_str());
+            }
+        }
+        name
+    }
+
+    /// Sets the display name of given mailbox if it has none.
+    ///
+    /// The display name is derived from the local-part of the mailbox
+    /// using `compose_name`. If no name can be derived (e.g. for the
+    /// local-part `"..."`) the mailbox is not changed.
+    pub fn complete_mailbox(&self, mailbox: &mut Mailbox)
+        -> Result<(), ComponentCreationError>
+    {
+        if mailbox.display_name.is_some() {
+            return Ok(());
+        }
+        let name = self.compose_name(mailbox.email.local_part.as_str());
+        if !name.is_empty() {
+            mailbox.display_name = Some(Phrase::new(name)?);
+        }
+        Ok(())
+    }
+}
+
 /// Parts used to create a mail body (in a multipart mail).
 ///
 /// This type contains a `Resource` which is normally used
@@ -120,6 +230,7 @@ pub struct Embedded {
     content_id: Option<ContentId>,
     resource: Resource,
     disposition: DispositionKind,
+    file_name: Option<String>
 }
 
 impl Embedded {
@@ -139,7 +250,8 @@ impl Embedded {
         Embedded {
             content_id: None,
             resource,
-            disposition
+            disposition,
+            file_name: None
         }
     }
 
@@ -148,7 +260,8 @@ impl Embedded {
         Embedded {
             content_id: Some(content_id),
             resource,
-            disposition
+            disposition,
+            file_name: None
         }
     }
 
@@ -172,6 +285,19 @@ impl Embedded {
         self.disposition
     }
 
+    /// Return the file name used in the `Content-Disposition` header, if any.
+    ///
+    /// If no file name is set the file name of the loaded resource
+    /// is used (if it has one).
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_ref().map(|name| &**name)
+    }
+
+    /// Set the file name used in the `Content-Disposition` header.
+    pub fn set_file_name(&mut self, file_name: Option<String>) {
+        self.file_name = file_name;
+    }
+
     /// Generate and set a new content id if this embedding doesn't have a content id.
     pub fn assure_content_id(&mut self, ctx: &impl Context) -> &ContentId {
         if self.content_id.is_none() {
@@ -204,11 +330,58 @@ impl MailParts {
         }
     }
 
+    /// Generating content ids derived from the scopes message id for all `Embedded` instances which don't have a cid.
+    ///
+    /// See `MailIdScope::next_content_id`.
+    pub fn generate_content_ids_in_scope(&mut self, scope: &mut MailIdScope, ctx: &impl Context) {
+        let embeddings = self.alternative_bodies.iter_mut()
+            .flat_map(|body| body.embeddings.iter_mut())
+            .chain(self.embeddings.iter_mut());
+
+        for embedding in embeddings {
+            if embedding.content_id.is_none() {
+                embedding.content_id = Some(scope.next_content_id(ctx));
+            }
+        }
+    }
+
+    /// Adds the resource both as inline embedding and as attachment.
+    ///
+    /// This is useful if e.g. an image should be shown in the mail
+    /// but also be offered as an attachment for downloading it. The
+    /// inline embedding will use the given content id while the
+    /// attachment will use the given file name.
+    ///
+    /// Both embeddings use a clone of the same resource, which for
+    /// `Resource::Data` means they share the same underlying buffer.
+    /// When turning the mail into a encodable mail the resource is only
+    /// loaded/transfer encoded once and the resulting `EncData` is used
+    /// for both bodies.
+    pub fn embed_and_attach(
+        &mut self,
+        resource: Resource,
+        content_id: ContentId,
+        file_name: String
+    ) {
+        let inline = Embedded::with_content_id(
+            resource.clone(), DispositionKind::Inline, content_id);
+
+        let mut attachment = Embedded::attachment(resource);
+        attachment.set_file_name(Some(file_name));
+
+        self.embeddings.push(inline);
+        self.embeddings.push(attachment);
+    }
+
 
     /// Create a `Mail` instance based on this `MailParts` instance.
     ///
-    /// This will first generate content ids for all contained
-    /// `Embedded` instances.
+    /// This will first start a id scope for the mail (`Context::begin_mail`)
+    /// and generate content ids for all contained `Embedded` instances
+    /// which don't have one from it. The message id of the scope is kept
+    /// in the returned mail and used for the `Message-Id` header if no
+    /// `Message-Id` header is set when it's encoded (or `Mail::ensure_message_id`
+    /// is called), so all ids of the mail are derived from it.
     ///
     /// If this instance contains any attachments then the
     /// returned mail will be a `multipart/mixed` mail with
@@ -234,8 +407,11 @@ impl MailParts {
     pub fn compose_mail(mut self, ctx: &impl Context)
         -> Mail
     {
-        self.generate_content_ids(ctx);
-        self.compose_without_generating_content_ids()
+        let mut scope = ctx.begin_mail();
+        self.generate_content_ids_in_scope(&mut scope, ctx);
+        let mut mail = self.compose_without_generating_content_ids();
+        mail.set_default_message_id(scope.message_id().clone());
+        mail
     }
 
     /// This function works like `compose_mail` but does not generate
@@ -263,7 +439,7 @@ impl MailParts {
             })
             .collect::<Vec<_>>();
 
-        //UNWRAP_SAFE: bodies is Vec1, i.e. we have at last one
+        //UNWRAP_SAFE: bodies is Vec1, i.e. we have at least one
         let mail = alternatives.pop().unwrap();
         let mail =
             if alternatives.is_empty() {
@@ -292,6 +468,25 @@ impl MailParts {
 
 impl BodyPart {
 
+    /// Adds a image as inline embedding returning the content id generated for it.
+    ///
+    /// The content id is generated with `Context::generate_content_id` and
+    /// can be used to refer to the image from the body, e.g. in html with
+    /// `<img src="cid:...">`. When creating the mail the image is placed
+    /// in a `multipart/related` body (together with the body) and has
+    /// a `Content-Disposition: inline` and a `Content-ID` header.
+    ///
+    /// Note that the `multipart/related` body is only created if the
+    /// body has at least one inline embedding.
+    pub fn with_inline_image(mut self, resource: Resource, ctx: &impl Context)
+        -> (ContentId, Self)
+    {
+        let content_id = ctx.generate_content_id();
+        self.embeddings.push(Embedded::with_content_id(
+            resource, DispositionKind::Inline, content_id.clone()));
+        (content_id, self)
+    }
+
     /// Creates a `Mail` instance from this `BodyPart` instance.
     ///
     /// All embeddings in `BodyPart.embeddings` which have a
@@ -344,14 +539,19 @@ impl Embedded {
         let Embedded {
             content_id,
             resource,
-            disposition:disposition_kind
+            disposition:disposition_kind,
+            file_name
         } = self;
 
         let mut mail = resource.create_mail();
         if let Some(content_id) = content_id {
             mail.insert_header(headers::ContentId::body(content_id));
         }
-        let disposition = Disposition::new(disposition_kind, Default::default());
+        let file_meta = FileMeta {
+            file_name,
+            ..Default::default()
+        };
+        let disposition = Disposition::new(disposition_kind, file_meta);
         mail.insert_header(headers::ContentDisposition::body(disposition));
         mail
     }
@@ -373,6 +573,18 @@ impl Resource {
 
 impl Mail {
 
+    /// Create a `multipart/alternative` `Mail` instance with the given bodies.
+    ///
+    /// The bodies have to be ordered from the least preferred to the most
+    /// preferred body (e.g. first the `text/plain` then the `text/html` body)
+    /// as specified by `multipart/alternative`. The order is kept as is.
+    ///
+    /// Like with any multipart mail the boundary is generated when the mail
+    /// is turned into an encodable mail.
+    pub fn new_alternative_mail(bodies: Vec1<Mail>) -> Mail {
+        new_multipart(&ALTERNATIVE, bodies.into_vec())
+    }
+
     /// Create a `multipart/mixed` `Mail` instance containing this mail as
     /// first body and one additional body for each attachment.
     ///
@@ -415,6 +627,75 @@ impl Mail {
 
 }
 
+/// Compose a mail from alternative bodies and attachments.
+///
+/// The `bodies` have to be ordered like in `Mail::new_alternative_mail`,
+/// i.e. from the least preferred to the most preferred body. If there is
+/// more then one body they are placed in a `multipart/alternative` body.
+/// If there are any attachments the (potentially alternative) body is
+/// wrapped into a `multipart/mixed` body as the first sub-body followed
+/// by one sub-body for each attachment (in the given order).
+///
+/// The `From`, `To`, `Cc`, `Bcc` and `Subject` headers are set based on
+/// the given `MailSendContext` (`Cc`/`Bcc` are only set if there are any
+/// mailboxes for them). If the `MailSendContext` has a `name_composer`
+/// it's used to derive display names for the `From`, `To` and `Cc`
+/// mailboxes which have none.
+///
+/// # Error
+///
+/// Fails if `bodies` is empty or if creating the `Subject` header or
+/// a display name fails.
+pub fn compose_mail(
+    send_context: MailSendContext,
+    bodies: Vec<Mail>,
+    attachments: Vec<Resource>
+) -> Result<Mail, MailError> {
+    let MailSendContext { mut from, mut to, mut cc, bcc, subject, name_composer } = send_context;
+
+    if let Some(name_composer) = name_composer {
+        name_composer.complete_mailbox(&mut from)?;
+        for mailbox in to.0.iter_mut().chain(cc.iter_mut()) {
+            name_composer.complete_mailbox(mailbox)?;
+        }
+    }
+
+    let bodies = Vec1::from_vec(bodies)
+        .map_err(|_| OtherValidationError::NoBody)?;
+
+    let mail =
+        if bodies.len() == 1 {
+            //UNWRAP_SAFE: bodies is Vec1, i.e. we have at least one
+            bodies.into_vec().pop().unwrap()
+        } else {
+            Mail::new_alternative_mail(bodies)
+        };
+
+    let mut mail =
+        if attachments.is_empty() {
+            mail
+        } else {
+            let mut mixed_bodies = vec![mail];
+            for resource in attachments {
+                let mut attachment = resource.create_mail();
+                attachment.set_attachment(None)?;
+                mixed_bodies.push(attachment);
+            }
+            new_multipart(&MIXED, mixed_bodies)
+        };
+
+    mail.insert_header(headers::_From::body(MailboxList(Vec1::new(from))));
+    mail.insert_header(headers::_To::body(to));
+    if let Ok(cc) = Vec1::from_vec(cc) {
+        mail.insert_header(headers::Cc::body(MailboxList(cc)));
+    }
+    if !bcc.is_empty() {
+        mail.insert_header(headers::Bcc::body(OptMailboxList(bcc)));
+    }
+    mail.insert_header(headers::Subject::auto_body(subject)?);
+    Ok(mail)
+}
+
 /// Creates a `multipart/<sub_type>` mail with given bodies.
 ///
 /// # Panic
@@ -427,4 +708,442 @@ fn new_multipart(sub_type: &'static str, bodies: Vec<Mail>)
     let content_type = MediaType::new(MULTIPART, sub_type)
         .unwrap();
     Mail::new_multipart_mail(content_type, bodies)
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod test {
+    #![allow(non_snake_case)]
+    use std::sync::Arc;
+
+    use headers::{
+        HeaderKind,
+        headers::{ContentDisposition, ContentType, ContentId as ContentIdHeader}
+    };
+    use context::Context;
+    use default_impl::test_context;
+    use super::*;
+
+    fn collect_leaf_mails<'a>(mail: &'a Mail, out: &mut Vec<&'a Mail>) {
+        use mail::MailBody::*;
+        match *mail.body() {
+            SingleBody { .. } => out.push(mail),
+            MultipleBodies { ref bodies, .. } => {
+                for body in bodies {
+                    collect_leaf_mails(body, out);
+                }
+            }
+        }
+    }
+
+    fn get_data(mail: &Mail) -> &::resource::Data {
+        use mail::MailBody::*;
+        match *mail.body() {
+            SingleBody { body: Resource::Data(ref data) } => data,
+            _ => panic!("unexpected body: {:?}", mail.body())
+        }
+    }
+
+    #[test]
+    fn embed_and_attach_adds_both_parts_sharing_the_resource() {
+        let ctx = test_context();
+        let content_id = ctx.generate_content_id();
+        let mut parts = MailParts {
+            alternative_bodies: Vec1::new(BodyPart {
+                resource: Resource::plain_text("the body", &ctx),
+                embeddings: Vec::new()
+            }),
+            embeddings: Vec::new()
+        };
+
+        parts.embed_and_attach(
+            Resource::plain_text("the image", &ctx),
+            content_id.clone(),
+            "image.png".to_owned()
+        );
+
+        let mail = parts.compose_mail(&ctx);
+        let mut leafs = Vec::new();
+        collect_leaf_mails(&mail, &mut leafs);
+        assert_eq!(leafs.len(), 3);
+
+        let inline = leafs.iter()
+            .find(|leaf| leaf.headers().contains(ContentIdHeader))
+            .expect("inline embedding is missing");
+
+        let disposition = inline.headers().get_single(ContentDisposition).unwrap().unwrap();
+        assert_eq!(disposition.kind(), DispositionKind::Inline);
+        assert_eq!(&**inline.headers().get_single(ContentIdHeader).unwrap().unwrap().body(), &content_id);
+
+        let attachment = leafs.iter()
+            .find(|leaf| leaf.headers().get_single(ContentDisposition)
+                .map(|disp| disp.unwrap().kind() == DispositionKind::Attachment)
+                .unwrap_or(false))
+            .expect("attachment is missing");
+
+        let disposition = attachment.headers().get_single(ContentDisposition).unwrap().unwrap();
+        assert_eq!(disposition.file_meta().file_name, Some("image.png".to_owned()));
+
+        assert!(Arc::ptr_eq(get_data(inline).buffer(), get_data(attachment).buffer()));
+    }
+
+    #[test]
+    fn compose_mail_derives_content_ids_from_the_message_id() {
+        use headers::headers::MessageId as MessageIdHeader;
+        use headers::header_components::MessageId;
+
+        let ctx = test_context();
+        let mut parts = MailParts {
+            alternative_bodies: Vec1::new(BodyPart {
+                resource: Resource::plain_text("the body", &ctx),
+                embeddings: vec![ Embedded::inline(Resource::plain_text("logo", &ctx)) ]
+            }),
+            embeddings: vec![ Embedded::inline(Resource::plain_text("photo", &ctx)) ]
+        };
+        let preset_content_id = ctx.generate_content_id();
+        parts.embeddings.push(Embedded::with_content_id(
+            Resource::plain_text("preset", &ctx), DispositionKind::Inline, preset_content_id.clone()));
+
+        let mut mail = parts.compose_mail(&ctx);
+        assert!(!mail.headers().contains(MessageIdHeader));
+        mail.ensure_message_id(&ctx);
+
+        let message_id = mail.headers().get_single(MessageIdHeader).unwrap().unwrap();
+        let message_id = message_id.as_str().to_owned();
+        let at = message_id.rfind('@').unwrap();
+        let expected = (0..2)
+            .map(|idx| {
+                let content_id = format!("{}.{}@{}", &message_id[..at], idx, &message_id[at+1..]);
+                MessageId::from_unchecked(content_id).into()
+            })
+            .chain(Some(preset_content_id))
+            .collect::<Vec<ContentId>>();
+
+        let mut leafs = Vec::new();
+        collect_leaf_mails(&mail, &mut leafs);
+        let content_ids = leafs.iter()
+            .filter_map(|leaf| leaf.headers().get_single(ContentIdHeader))
+            .map(|header| (**header.unwrap()).clone())
+            .collect::<Vec<_>>();
+        assert_eq!(content_ids, expected);
+    }
+
+    #[test]
+    fn compose_mail_does_not_override_a_set_message_id() {
+        use headers::headers::MessageId as MessageIdHeader;
+        use headers::header_components::MessageId;
+
+        let ctx = test_context();
+        let parts = MailParts {
+            alternative_bodies: Vec1::new(BodyPart {
+                resource: Resource::plain_text("the body", &ctx),
+                embeddings: Vec::new()
+            }),
+            embeddings: Vec::new()
+        };
+
+        let mut mail = parts.compose_mail(&ctx);
+        let message_id = MessageId::from_unchecked("preset@example.com".to_owned());
+        mail.insert_header(MessageIdHeader::body(message_id));
+        mail.ensure_message_id(&ctx);
+
+        let header = mail.headers().get_single(MessageIdHeader).unwrap().unwrap();
+        assert_eq!(header.as_str(), "preset@example.com");
+    }
+
+    #[test]
+    fn embed_and_attach_loads_the_resource_only_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use futures::{future, Future};
+        use headers::headers::_From;
+        use context::ResourceLoaderComponent;
+        use default_impl::{test_context_with_loader, test_source};
+        use error::ResourceLoadingError;
+        use resource::{Data, EncData, Metadata, Source};
+        use utils::SendBoxFuture;
+
+        #[derive(Debug, Default)]
+        struct CountingLoader {
+            loads: Arc<AtomicUsize>
+        }
+
+        impl ResourceLoaderComponent for CountingLoader {
+            fn load_resource(&self, source: &Source, ctx: &impl Context)
+                -> SendBoxFuture<EncData, ResourceLoadingError>
+            {
+                self.loads.fetch_add(1, Ordering::SeqCst);
+                let data = Data::new(source.iri.tail().as_bytes().to_vec(), Metadata {
+                    file_meta: Default::default(),
+                    media_type: MediaType::parse("image/png").unwrap(),
+                    content_id: ctx.generate_content_id()
+                });
+                Box::new(future::ok(data.transfer_encode(Default::default())))
+            }
+        }
+
+        let loader = CountingLoader::default();
+        let loads = loader.loads.clone();
+        let ctx = test_context_with_loader(loader);
+
+        let mut parts = MailParts {
+            alternative_bodies: Vec1::new(BodyPart {
+                resource: Resource::plain_text("the body", &ctx),
+                embeddings: Vec::new()
+            }),
+            embeddings: Vec::new()
+        };
+        let image = Resource::Source(test_source("img:logo"));
+        parts.embed_and_attach(image, ctx.generate_content_id(), "logo.png".to_owned());
+
+        let mut mail = parts.compose_mail(&ctx);
+        mail.insert_headers(headers! {
+            _From: ["from@example.com"]
+        }.unwrap());
+        let enc_mail = mail.into_encodable_mail(ctx).wait().unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        let mut leafs = Vec::new();
+        collect_leaf_mails(&enc_mail, &mut leafs);
+        let images = leafs.iter()
+            .filter(|leaf| leaf.headers().contains(ContentDisposition))
+            .count();
+        assert_eq!(images, 2);
+    }
+
+    mod compose_mail {
+        use headers::{
+            headers::{_From, _To, Cc, Bcc, Subject, ContentType, ContentDisposition},
+            header_components::{Email, Mailbox, MailboxList, DispositionKind, Phrase}
+        };
+        use vec1::Vec1;
+        use default_impl::test_context;
+        use error::MailError;
+        use mail::Mail;
+        use resource::Resource;
+        use super::get_data;
+        use super::super::{compose_mail, MailSendContext, DefaultNameComposer};
+
+        fn send_context() -> Result<MailSendContext, MailError> {
+            let from = Mailbox::from(Email::new("from@example.com")?);
+            let to = MailboxList(Vec1::new(Mailbox::from(Email::new("to@example.com")?)));
+            Ok(MailSendContext::new(from, to, "the subject"))
+        }
+
+        fn sub_type(mail: &Mail) -> String {
+            let content_type = mail.headers().get_single(ContentType).unwrap().unwrap();
+            content_type.subtype().as_str().to_owned()
+        }
+
+        fn sub_bodies(mail: &Mail) -> &[Mail] {
+            use mail::MailBody::*;
+            match *mail.body() {
+                MultipleBodies { ref bodies, .. } => bodies,
+                _ => panic!("unexpected singlepart body")
+            }
+        }
+
+        test!(wraps_alternative_bodies_in_mixed_with_attachments, {
+            let ctx = test_context();
+            let bodies = vec![
+                Mail::plain_text("plain", &ctx),
+                Mail::html_text("<p>html</p>", &ctx)
+            ];
+            let attachments = vec![
+                Resource::plain_text("attachment 1", &ctx),
+                Resource::plain_text("attachment 2", &ctx)
+            ];
+
+            let mail = compose_mail(send_context()?, bodies, attachments)?;
+
+            assert_eq!(sub_type(&mail), "mixed");
+            assert!(mail.headers().contains(_From));
+            assert!(mail.headers().contains(_To));
+            assert!(mail.headers().contains(Subject));
+
+            let mixed = sub_bodies(&mail);
+            assert_eq!(mixed.len(), 3);
+            assert_eq!(sub_type(&mixed[0]), "alternative");
+            assert_eq!(sub_bodies(&mixed[0]).len(), 2);
+
+            for (attachment, text) in mixed[1..].iter().zip(&["attachment 1", "attachment 2"]) {
+                let disposition = attachment.headers().get_single(ContentDisposition).unwrap()?;
+                assert_eq!(disposition.kind(), DispositionKind::Attachment);
+                assert_eq!(get_data(attachment).buffer().to_vec(), text.as_bytes().to_vec());
+            }
+        });
+
+        test!(sets_cc_and_bcc_only_if_given, {
+            let ctx = test_context();
+            let mail = compose_mail(send_context()?, vec![Mail::plain_text("plain", &ctx)], Vec::new())?;
+            assert!(!mail.headers().contains(Cc));
+            assert!(!mail.headers().contains(Bcc));
+
+            let mut send_context = send_context()?;
+            send_context.cc.push(Mailbox::from(Email::new("cc@example.com")?));
+            send_context.bcc.push(Mailbox::from(Email::new("bcc@example.com")?));
+            let mail = compose_mail(send_context, vec![Mail::plain_text("plain", &ctx)], Vec::new())?;
+            assert!(mail.headers().contains(Cc));
+            assert!(mail.headers().contains(Bcc));
+        });
+
+        test!(does_not_wrap_a_single_body_without_attachments, {
+            let ctx = test_context();
+            let bodies = vec![Mail::plain_text("plain", &ctx)];
+
+            let mail = compose_mail(send_context()?, bodies, Vec::new())?;
+
+            assert_eq!(get_data(&mail).buffer().to_vec(), b"plain".to_vec());
+            assert!(mail.headers().contains(_From));
+            assert!(mail.headers().contains(Subject));
+        });
+
+        test!(derives_display_names_for_mailboxes_without_one, {
+            let ctx = test_context();
+            let from = Mailbox::from(Email::new("john.doe@example.com")?);
+            let to = MailboxList(Vec1::from_vec(vec![
+                Mailbox::from(Email::new("jane_mary_doe@example.com")?),
+                Mailbox {
+                    display_name: Some(Phrase::new("The Admin")?),
+                    email: Email::new("admin@example.com")?
+                }
+            ]).unwrap());
+            let mut send_context = MailSendContext::new(from, to, "the subject");
+            send_context.bcc.push(Mailbox::from(Email::new("bcc@example.com")?));
+            send_context.name_composer = Some(DefaultNameComposer);
+
+            let mail = compose_mail(send_context, vec![Mail::plain_text("plain", &ctx)], Vec::new())?;
+
+            let from = mail.headers().get_single(_From).unwrap()?;
+            assert_eq!(from.0.len(), 1);
+            assert_eq!(from.0[0].display_name, Some(Phrase::new("John Doe")?));
+
+            let bcc = mail.headers().get_single(Bcc).unwrap()?;
+            assert_eq!(bcc.0[0].display_name, None);
+
+            let to = mail.headers().get_single(_To).unwrap()?;
+            let names = to.0.iter()
+                .map(|mailbox| mailbox.display_name.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec![
+                Some(Phrase::new("Jane Mary Doe")?),
+                Some(Phrase::new("The Admin")?)
+            ]);
+        });
+
+        test!(does_not_derive_display_names_by_default, {
+            let ctx = test_context();
+            let mail = compose_mail(send_context()?, vec![Mail::plain_text("plain", &ctx)], Vec::new())?;
+
+            let from = mail.headers().get_single(_From).unwrap()?;
+            assert_eq!(from.0[0].display_name, None);
+            let to = mail.headers().get_single(_To).unwrap()?;
+            assert_eq!(to.0[0].display_name, None);
+        });
+
+        #[test]
+        fn fails_without_bodies() {
+            let ctx = test_context();
+            let attachments = vec![Resource::plain_text("attachment", &ctx)];
+            assert_err!(compose_mail(send_context().unwrap(), Vec::new(), attachments));
+        }
+    }
+
+    mod DefaultNameComposer {
+        use super::super::DefaultNameComposer;
+
+        #[test]
+        fn dotted_local_part() {
+            assert_eq!(DefaultNameComposer.compose_name("john.doe"), "John Doe");
+        }
+
+        #[test]
+        fn underscored_local_part() {
+            assert_eq!(DefaultNameComposer.compose_name("jane_mary_doe"), "Jane Mary Doe");
+        }
+
+        #[test]
+        fn single_token_local_part() {
+            assert_eq!(DefaultNameComposer.compose_name("admin"), "Admin");
+        }
+
+        #[test]
+        fn empty_words_are_skipped() {
+            assert_eq!(DefaultNameComposer.compose_name(".john..doe_"), "John Doe");
+            assert_eq!(DefaultNameComposer.compose_name("émile-zola"), "Émile Zola");
+        }
+    }
+
+    mod BodyPart {
+        use headers::headers::{ContentType, ContentDisposition, ContentId as ContentIdHeader};
+        use default_impl::test_context;
+        use resource::Resource;
+        use super::get_data;
+        use super::super::{BodyPart, DispositionKind};
+
+        #[test]
+        fn with_inline_image_wraps_the_body_in_related() {
+            let ctx = test_context();
+            let body = BodyPart {
+                resource: Resource::plain_text("the body", &ctx),
+                embeddings: Vec::new()
+            };
+
+            let (logo_cid, body) = body.with_inline_image(Resource::plain_text("logo", &ctx), &ctx);
+            let (photo_cid, body) = body.with_inline_image(Resource::plain_text("photo", &ctx), &ctx);
+            assert_ne!(logo_cid, photo_cid);
+
+            let mut attachments = Vec::new();
+            let mail = body.create_mail(&mut attachments);
+            assert!(attachments.is_empty());
+
+            let content_type = mail.headers().get_single(ContentType).unwrap().unwrap();
+            assert_eq!(content_type.subtype().as_str(), "related");
+
+            let bodies = match *mail.body() {
+                ::mail::MailBody::MultipleBodies { ref bodies, .. } => bodies,
+                _ => panic!("expected multipart body")
+            };
+            assert_eq!(bodies.len(), 3);
+            assert_eq!(get_data(&bodies[0]).buffer().to_vec(), b"the body".to_vec());
+
+            for (image, cid) in bodies[1..].iter().zip(&[logo_cid, photo_cid]) {
+                let disposition = image.headers().get_single(ContentDisposition).unwrap().unwrap();
+                assert_eq!(disposition.kind(), DispositionKind::Inline);
+                let content_id = image.headers().get_single(ContentIdHeader).unwrap().unwrap();
+                assert_eq!(&**content_id.body(), cid);
+            }
+        }
+
+        #[test]
+        fn body_without_inline_images_is_not_wrapped() {
+            let ctx = test_context();
+            let body = BodyPart {
+                resource: Resource::plain_text("the body", &ctx),
+                embeddings: Vec::new()
+            };
+
+            let mail = body.create_mail(&mut Vec::new());
+            assert_eq!(get_data(&mail).buffer().to_vec(), b"the body".to_vec());
+        }
+    }
+
+    #[test]
+    fn new_alternative_mail_keeps_the_order_of_bodies() {
+        let ctx = test_context();
+        let mut bodies = Vec1::new(Mail::plain_text("plain", &ctx));
+        bodies.push(Mail::html_text("<p>html</p>", &ctx));
+
+        let mail = Mail::new_alternative_mail(bodies);
+
+        let content_type = mail.headers().get_single(ContentType).unwrap().unwrap();
+        assert!(content_type.is_multipart());
+        assert_eq!(content_type.subtype().as_str(), "alternative");
+
+        let mut leafs = Vec::new();
+        collect_leaf_mails(&mail, &mut leafs);
+        let texts = leafs.iter()
+            .map(|leaf| get_data(leaf).buffer().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec![b"plain".to_vec(), b"<p>html</p>".to_vec()]);
+    }
+}
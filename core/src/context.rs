@@ -3,14 +3,16 @@ use std::sync::Arc;
 use std::fmt::Debug;
 
 use futures::{ future, Future, IntoFuture };
+use soft_ascii_string::SoftAsciiString;
 use utils::SendBoxFuture;
 
 use headers::header_components::{
-    MessageId, ContentId
+    MessageId, ContentId, Domain, DateTime
 };
 
 use ::error::ResourceLoadingError;
 use ::resource::{Source, Data, EncData};
+use ::mime::create_structured_random_boundary;
 
 /// This library needs a context for creating/encoding mails.
 ///
@@ -71,7 +73,7 @@ pub trait Context: Debug + Clone + Send + Sync + 'static {
     /// a encodable mail an a `Resource::Data` instance is found.
     ///
     /// The default impl. of this function just calls
-    /// `data.transfer_encode(data, Default::default())` but a more
+    /// `data.transfer_encode(data.transfer_encoding_hint())` but a more
     /// sophisticated implementation could use the `Data`s content id
     /// for some caching scheme e.g. a LRU cache. Which can safe the
     /// encoding step for commonly used resources like e.g. a logo.
@@ -85,7 +87,10 @@ pub trait Context: Debug + Clone + Send + Sync + 'static {
         -> SendBoxFuture<EncData, ResourceLoadingError>
     {
         let data = data.clone();
-        self.offload_fn(move || Ok(data.transfer_encode(Default::default())))
+        self.offload_fn(move || {
+            let hint = data.transfer_encoding_hint();
+            Ok(data.transfer_encode(hint))
+        })
     }
 
     /// generate a unique content id
@@ -104,6 +109,21 @@ pub trait Context: Debug + Clone + Send + Sync + 'static {
     ///
     fn generate_message_id(&self) -> MessageId;
 
+    /// generate a unique message id using the given domain
+    ///
+    /// This is used for mails with a `From` header, passing in the domain
+    /// of the first `From` mailbox. This allows contexts used for sending
+    /// mails on behalf of multiple domains to generate message ids which
+    /// right hand side matches the sending domain (which some receivers
+    /// check). Implementations should only use the domain if it's one
+    /// they are allowed to use (i.e. one "you" own).
+    ///
+    /// The default impl. ignores the domain and calls `generate_message_id`.
+    fn generate_message_id_for(&self, domain: &Domain) -> MessageId {
+        let _ = domain;
+        self.generate_message_id()
+    }
+
     /// generate a unique content id
     ///
     /// Rfc 2045 states that content id's have to be world unique,
@@ -116,6 +136,84 @@ pub trait Context: Debug + Clone + Send + Sync + 'static {
     /// in terms of calling `generate_message_id`.
     fn generate_content_id(&self) -> ContentId;
 
+    /// returns the domain used on the right hand side of generated message ids
+    ///
+    /// This is mainly meant for logging and cross-checking generated
+    /// ids, it's not guaranteed to be known (e.g. if the id generator
+    /// uses multiple domains) in which case `None` is returned.
+    ///
+    /// The default impl. always returns `None`.
+    fn message_id_domain(&self) -> Option<&Domain> {
+        None
+    }
+
+    /// returns the domains allowed to be used in the `From` header
+    ///
+    /// If `Some` list of domains is returned turning a mail into a
+    /// encodable mail will fail with a validation error if the `From`
+    /// header contains a mailbox with a domain not in the list. Domains
+    /// are compared ASCII case-insensitive (but are not punycode encoded,
+    /// so a internationalized domain has to be given in the same form as
+    /// it's used in the `From` header).
+    ///
+    /// The default impl. returns `None`, i.e. there is no restriction.
+    fn allowed_from_domains(&self) -> Option<&[Domain]> {
+        None
+    }
+
+    /// returns true if a `Sender` header should be auto-generated
+    ///
+    /// If this returns true and a mail has a `From` header with multiple
+    /// mailboxes but no `Sender` header, the first mailbox of the `From`
+    /// header is used as `Sender` when turning the mail into a encodable
+    /// mail (without it such a mail fails validation).
+    ///
+    /// The default impl. returns `false`.
+    fn auto_insert_sender(&self) -> bool {
+        false
+    }
+
+    /// starts a scope for generating all ids of a single mail
+    ///
+    /// The scope generates the message id of the mail when created and
+    /// derives all content ids handed out by it from that message id.
+    /// This means the ids of one mail are grouped together (and are not
+    /// interleaved with ids generated for other mails). It's used by
+    /// `MailParts::compose_mail`.
+    ///
+    /// The default impl. uses `generate_message_id` to create the message id.
+    fn begin_mail(&self) -> MailIdScope {
+        MailIdScope::new(self.generate_message_id())
+    }
+
+    /// generates the boundary for a multipart body
+    ///
+    /// This is called when turning a mail into a encodable mail for every
+    /// multipart body which doesn't have a preset boundary. The `count` is
+    /// unique for every multipart body in the mail, so including it in the
+    /// boundary makes sure nested multipart bodies don't share a boundary.
+    ///
+    /// Overriding this allows e.g. deterministic boundaries in tests. The
+    /// returned boundary has to be valid wrt. rfc2046 (see
+    /// `mime::is_valid_boundary`) else turning the mail into a encodable
+    /// mail fails.
+    ///
+    /// The default impl. uses `mime::create_structured_random_boundary`.
+    fn generate_boundary(&self, count: usize) -> SoftAsciiString {
+        SoftAsciiString::from_unchecked(create_structured_random_boundary(count))
+    }
+
+    /// returns the current date time
+    ///
+    /// This is used for the auto-generated `Date` header when turning a
+    /// mail into a encodable mail. Overriding this allows e.g. a fixed
+    /// date in tests, which makes fully encoded mails reproducible.
+    ///
+    /// The default impl. uses `DateTime::now()`.
+    fn now(&self) -> DateTime {
+        DateTime::now()
+    }
+
     //TODO[futures/v>=0.2]: integrate this with Context
     /// offloads the execution of the future `fut` to somewhere else e.g. a cpu pool
     fn offload<F>(&self, fut: F) -> SendBoxFuture<F::Item, F::Error>
@@ -155,7 +253,10 @@ pub trait ResourceLoaderComponent: Debug + Send + Sync + 'static {
         -> SendBoxFuture<EncData, ResourceLoadingError>
     {
         let data = data.clone();
-        ctx.offload_fn(move || Ok(data.transfer_encode(Default::default())))
+        ctx.offload_fn(move || {
+            let hint = data.transfer_encoding_hint();
+            Ok(data.transfer_encode(hint))
+        })
     }
 }
 
@@ -183,6 +284,21 @@ pub trait MailIdGenComponent: Debug + Send + Sync + 'static {
 
     /// Calls to `Context::generate_content_id` will be forwarded to this method.
     fn generate_content_id(&self) -> ContentId;
+
+    /// Calls to `Context::generate_message_id_for` will be forwarded to this method.
+    ///
+    /// The default impl. ignores the domain and calls `generate_message_id`.
+    fn generate_message_id_for(&self, domain: &Domain) -> MessageId {
+        let _ = domain;
+        self.generate_message_id()
+    }
+
+    /// Calls to `Context::message_id_domain` will be forwarded to this method.
+    ///
+    /// The default impl. returns `None`.
+    fn domain(&self) -> Option<&Domain> {
+        None
+    }
 }
 
 /// The `CompositeContext` is the simplest way to get an `Context` implementation.
@@ -231,6 +347,26 @@ impl<R, O, M> CompositeContext<R, O, M>
         }
     }
 
+    /// Create a new context from already shared components.
+    ///
+    /// Contexts created from the same `Arc` share the same component
+    /// instances, which e.g. is needed if the id generation relies on
+    /// state like a counter which should not be duplicated. Note that
+    /// cloning a context also shares the components.
+    pub fn from_parts(parts: Arc<(R, O, M)>) -> Self {
+        CompositeContext {
+            inner: parts,
+        }
+    }
+
+    /// Returns a reference to the shared components.
+    ///
+    /// This can be used to create another context sharing the
+    /// components with `from_parts`.
+    pub fn parts(&self) -> &Arc<(R, O, M)> {
+        &self.inner
+    }
+
     /// Returns a reference to the resource loader component.
     pub fn resource_loader(&self) -> &R {
         &self.inner.0
@@ -281,6 +417,14 @@ impl<R, O, M> Context for CompositeContext<R, O, M>
         self.id_gen().generate_message_id()
     }
 
+    fn generate_message_id_for(&self, domain: &Domain) -> MessageId {
+        self.id_gen().generate_message_id_for(domain)
+    }
+
+    fn message_id_domain(&self) -> Option<&Domain> {
+        self.id_gen().domain()
+    }
+
 }
 
 /// Allows using a part of an context as an component.
@@ -294,6 +438,14 @@ impl<C> MailIdGenComponent for C
     fn generate_content_id(&self) -> ContentId {
         <Self as Context>::generate_content_id(self)
     }
+
+    fn generate_message_id_for(&self, domain: &Domain) -> MessageId {
+        <Self as Context>::generate_message_id_for(self, domain)
+    }
+
+    fn domain(&self) -> Option<&Domain> {
+        <Self as Context>::message_id_domain(self)
+    }
 }
 
 /// Allows using a part of an context as an component.
@@ -325,4 +477,111 @@ impl<C> ResourceLoaderComponent for C
     {
         <Self as Context>::transfer_encode_resource(self, data)
     }
-}
\ No newline at end of file
+}
+
+/// A scope for generating the ids of a single mail, see `Context::begin_mail`.
+///
+/// Content ids are created by appending a counter to the left hand side of
+/// the message id, e.g. for the message id `<abc.12@example.com>` the content
+/// ids `<abc.12.0@example.com>`, `<abc.12.1@example.com>`, ... are generated.
+///
+/// If the message id has no `'@'` (which can happen with a custom id
+/// generator) no content ids can be derived from it and content ids
+/// generated by the context are used instead.
+#[derive(Debug, Clone)]
+pub struct MailIdScope {
+    message_id: MessageId,
+    next_content_id: usize
+}
+
+impl MailIdScope {
+
+    /// Create a new scope for given message id.
+    pub fn new(message_id: MessageId) -> Self {
+        MailIdScope {
+            message_id,
+            next_content_id: 0
+        }
+    }
+
+    /// Returns the message id of the mail.
+    pub fn message_id(&self) -> &MessageId {
+        &self.message_id
+    }
+
+    /// Generates a new content id, derived from the message id.
+    ///
+    /// Falls back to `ctx.generate_content_id()` if the message id
+    /// contains no `'@'`.
+    pub fn next_content_id(&mut self, ctx: &impl Context) -> ContentId {
+        let content_id = {
+            let message_id = self.message_id.as_str();
+            let split_at = match message_id.rfind('@') {
+                Some(split_at) => split_at,
+                None => return ctx.generate_content_id()
+            };
+            let (left, right) = (&message_id[..split_at], &message_id[split_at+1..]);
+            format!("{}.{}@{}", left, self.next_content_id, right)
+        };
+        self.next_content_id += 1;
+        MessageId::from_unchecked(content_id).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    mod CompositeContext {
+        #![allow(non_snake_case)]
+
+        use std::{ptr, sync::Arc};
+        use default_impl::{NullResourceLoader, simple_cpu_pool, test_id_gen};
+        use super::super::*;
+
+        #[test]
+        fn contexts_from_the_same_parts_share_components() {
+            let parts = Arc::new((NullResourceLoader, simple_cpu_pool(), test_id_gen()));
+
+            let ctx1 = CompositeContext::from_parts(parts.clone());
+            let ctx2 = CompositeContext::from_parts(parts);
+
+            assert!(ptr::eq(ctx1.id_gen(), ctx2.id_gen()));
+            assert!(Arc::ptr_eq(ctx1.parts(), ctx2.parts()));
+            assert_ne!(ctx1.generate_content_id(), ctx2.generate_content_id());
+        }
+    }
+
+    mod MailIdScope {
+        #![allow(non_snake_case)]
+
+        use headers::header_components::MessageId;
+        use default_impl::test_context;
+        use super::super::*;
+
+        #[test]
+        fn content_ids_are_derived_from_the_message_id() {
+            let ctx = test_context();
+            let mut scope = ctx.begin_mail();
+            let message_id = scope.message_id().as_str().to_owned();
+            let at = message_id.rfind('@').unwrap();
+
+            for idx in 0..3 {
+                let expected = format!("{}.{}@{}", &message_id[..at], idx, &message_id[at+1..]);
+                let expected: ContentId = MessageId::from_unchecked(expected).into();
+                assert_eq!(scope.next_content_id(&ctx), expected);
+            }
+        }
+
+        #[test]
+        fn falls_back_to_the_context_if_message_id_has_no_at() {
+            let ctx = test_context();
+            let mut scope = MailIdScope::new(MessageId::from_unchecked("no-at-sign".to_owned()));
+
+            let first = scope.next_content_id(&ctx);
+            let second = scope.next_content_id(&ctx);
+
+            assert!(first.as_str().contains('@'));
+            assert_ne!(first, second);
+        }
+    }
+}
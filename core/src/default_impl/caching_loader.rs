@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex}
+};
+
+use futures::{future, Future};
+
+use ::IRI;
+use utils::SendBoxFuture;
+use context::{Context, ResourceLoaderComponent};
+use error::ResourceLoadingError;
+use resource::{Source, Data, EncData};
+
+/// A resource loader wrapper caching loaded resources by IRI.
+///
+/// On a cache miss the loading is delegated to the inner loader and the
+/// resulting `EncData` is cached, failures are never cached. On a cache
+/// hit a clone of the cached `EncData` with a newly generated content id
+/// is returned (which is cheap as the buffer is shared through an `Arc`),
+/// so content ids stay unique even if the resource is used in many mails.
+///
+/// The cache is a LRU cache with a fixed capacity, i.e. if the cache
+/// is full the least recently used entry is removed.
+///
+/// A cached resource is only used for a `Source` which is equal to the
+/// one it was loaded with (i.e. including `use_media_type`, `use_file_name`
+/// and `use_disposition`). Only one resource is cached per IRI, loading
+/// a `Source` with the same IRI but e.g. a different file name replaces
+/// the cached resource.
+#[derive(Debug)]
+pub struct CachingResourceLoader<R: ResourceLoaderComponent> {
+    inner: R,
+    cache: Arc<Mutex<LruCache>>
+}
+
+impl<R> CachingResourceLoader<R>
+    where R: ResourceLoaderComponent
+{
+    /// Create a new caching loader wrapping `inner` caching at most `capacity` resources.
+    ///
+    /// # Panics
+    ///
+    /// If capacity is 0.
+    pub fn new(inner: R, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity of CachingResourceLoader has to be at least 1");
+        CachingResourceLoader {
+            inner,
+            cache: Arc::new(Mutex::new(LruCache::new(capacity)))
+        }
+    }
+
+    /// Returns a reference to the inner resource loader.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the number of currently cached resources.
+    pub fn cached_count(&self) -> usize {
+        self.cache.lock().expect("[BUG] lock poisoned").entries.len()
+    }
+
+    /// Removes all cached resources.
+    ///
+    /// Resources which are currently loading will not be cached
+    /// once they are loaded.
+    pub fn clear(&self) {
+        let mut cache = self.cache.lock().expect("[BUG] lock poisoned");
+        cache.entries.clear();
+        cache.make_all_pending_stale();
+    }
+
+    /// Removes the cached resource for given IRI, returns true if there was one.
+    ///
+    /// The next time a source with the IRI is loaded it will be loaded
+    /// using the inner loader. If the resource is currently loading it
+    /// will not be cached once it is loaded.
+    pub fn invalidate(&self, iri: &IRI) -> bool {
+        let mut cache = self.cache.lock().expect("[BUG] lock poisoned");
+        cache.make_pending_stale(iri.as_str());
+        cache.entries.remove(iri.as_str()).is_some()
+    }
+
+    /// Loads the source using the inner loader even if it is cached.
+    ///
+    /// This forces a fresh load, e.g. because the underlying file changed.
+    /// If loading succeeds the cached resource is replaced with the newly
+    /// loaded one, if it fails the cached resource is removed.
+    ///
+    /// As cached resources are shared through an `Arc` this doesn't
+    /// affect mails which already use the previously loaded resource,
+    /// they keep using the old data. Loads of the resource which were
+    /// started before the reload and are still in flight are not cached
+    /// once they complete, so they can't override the reloaded resource.
+    pub fn reload_resource(&self, source: &Source, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        self.invalidate(&source.iri);
+        self.load_and_cache(source, ctx)
+    }
+
+    fn load_and_cache(&self, source: &Source, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        let key = source.iri.as_str().to_owned();
+        let generation = self.cache.lock().expect("[BUG] lock poisoned")
+            .begin_load(&key);
+        let pending = PendingLoad {
+            cache: self.cache.clone(),
+            source: source.clone(),
+            key, generation
+        };
+        let fut = self.inner.load_resource(source, ctx)
+            .map(move |enc_data| {
+                pending.insert(enc_data.clone());
+                enc_data
+            });
+
+        Box::new(fut)
+    }
+}
+
+impl<R> ResourceLoaderComponent for CachingResourceLoader<R>
+    where R: ResourceLoaderComponent
+{
+    fn load_resource(&self, source: &Source, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        let cached = self.cache.lock().expect("[BUG] lock poisoned").get(source);
+        if let Some(enc_data) = cached {
+            return Box::new(future::ok(enc_data.with_content_id(ctx.generate_content_id())));
+        }
+
+        self.load_and_cache(source, ctx)
+    }
+
+    fn transfer_encode_resource(&self, data: &Data, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        self.inner.transfer_encode_resource(data, ctx)
+    }
+}
+
+#[derive(Debug)]
+struct LruCache {
+    capacity: usize,
+    /// incremented on every access, used to find the least recently used entry
+    tick: u64,
+    /// the source the data was loaded with, the data and the tick of the last access by IRI
+    entries: HashMap<String, (Source, EncData, u64)>,
+    /// generation and number of loads in flight for every key with loads in flight
+    pending: HashMap<String, (u64, usize)>
+}
+
+impl LruCache {
+
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            pending: HashMap::new()
+        }
+    }
+
+    /// Registers a load for the key returning the generation to use for inserting it.
+    fn begin_load(&mut self, key: &str) -> u64 {
+        let entry = self.pending.entry(key.to_owned()).or_insert((0, 0));
+        entry.1 += 1;
+        entry.0
+    }
+
+    /// Unregisters a load for the key (after it completed, failed or was dropped).
+    fn end_load(&mut self, key: &str) {
+        let is_last = match self.pending.get_mut(key) {
+            Some(entry) => {
+                entry.1 -= 1;
+                entry.1 == 0
+            },
+            None => false
+        };
+        if is_last {
+            self.pending.remove(key);
+        }
+    }
+
+    /// Makes all loads for the key which are currently in flight stale.
+    fn make_pending_stale(&mut self, key: &str) {
+        if let Some(entry) = self.pending.get_mut(key) {
+            entry.0 += 1;
+        }
+    }
+
+    /// Makes all loads which are currently in flight stale.
+    fn make_all_pending_stale(&mut self) {
+        for entry in self.pending.values_mut() {
+            entry.0 += 1;
+        }
+    }
+
+    fn is_current_generation(&self, key: &str, generation: u64) -> bool {
+        self.pending.get(key)
+            .map(|entry| entry.0 == generation)
+            .unwrap_or(false)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, source: &Source) -> Option<EncData> {
+        let tick = self.next_tick();
+        match self.entries.get_mut(source.iri.as_str()) {
+            Some(entry) if entry.0 == *source => {
+                entry.2 = tick;
+                Some(entry.1.clone())
+            },
+            _ => None
+        }
+    }
+
+    fn insert(&mut self, key: String, source: Source, enc_data: EncData) {
+        let tick = self.next_tick();
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let lru_key = self.entries.iter()
+                .min_by_key(|&(_, &(_, _, tick))| tick)
+                .map(|(key, _)| key.clone());
+
+            if let Some(lru_key) = lru_key {
+                self.entries.remove(&lru_key);
+            }
+        }
+        self.entries.insert(key, (source, enc_data, tick));
+    }
+}
+
+/// A load in flight, see `LruCache::begin_load`.
+///
+/// The load is unregistered when this is dropped.
+struct PendingLoad {
+    cache: Arc<Mutex<LruCache>>,
+    source: Source,
+    key: String,
+    generation: u64
+}
+
+impl PendingLoad {
+
+    /// Caches the loaded resource if the load didn't become stale.
+    fn insert(&self, enc_data: EncData) {
+        let mut cache = self.cache.lock().expect("[BUG] lock poisoned");
+        if cache.is_current_generation(&self.key, self.generation) {
+            cache.insert(self.key.clone(), self.source.clone(), enc_data);
+        }
+    }
+}
+
+impl Drop for PendingLoad {
+    fn drop(&mut self) {
+        // don't panic in drop, a poisoned cache is unusable anyway
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.end_load(&self.key);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{future, Future};
+    use futures::sync::oneshot;
+    use headers::header_components::MediaType;
+
+    use ::IRI;
+    use context::{Context, ResourceLoaderComponent};
+    use default_impl::{test_context, test_source};
+    use error::{ResourceLoadingError, ResourceLoadingErrorKind};
+    use resource::{Source, Data, EncData, Metadata};
+    use utils::SendBoxFuture;
+    use super::CachingResourceLoader;
+
+    /// Loader counting loads, loading fails for the `fail` scheme.
+    #[derive(Debug, Default)]
+    struct CountingLoader {
+        loads: AtomicUsize
+    }
+
+    impl CountingLoader {
+        fn loads(&self) -> usize {
+            self.loads.load(Ordering::SeqCst)
+        }
+    }
+
+    impl ResourceLoaderComponent for CountingLoader {
+        fn load_resource(&self, source: &Source, ctx: &impl Context)
+            -> SendBoxFuture<EncData, ResourceLoadingError>
+        {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            if source.iri.scheme() == "fail" {
+                return Box::new(future::err(ResourceLoadingErrorKind::NotFound.into()));
+            }
+            Box::new(future::ok(tail_data(source, ctx)))
+        }
+    }
+
+    /// Loader which delays the first load until `finish_first_load` is called.
+    #[derive(Debug, Default)]
+    struct DelayFirstLoader {
+        loads: AtomicUsize,
+        first: Mutex<Option<(oneshot::Sender<EncData>, EncData)>>
+    }
+
+    impl DelayFirstLoader {
+        fn finish_first_load(&self) {
+            let (sender, enc_data) = self.first.lock().unwrap().take().unwrap();
+            sender.send(enc_data).unwrap();
+        }
+    }
+
+    impl ResourceLoaderComponent for DelayFirstLoader {
+        fn load_resource(&self, source: &Source, ctx: &impl Context)
+            -> SendBoxFuture<EncData, ResourceLoadingError>
+        {
+            let enc_data = tail_data(source, ctx);
+            if self.loads.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Box::new(future::ok(enc_data));
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            *self.first.lock().unwrap() = Some((sender, enc_data));
+            Box::new(receiver.map_err(|_| {
+                ResourceLoadingError::from(ResourceLoadingErrorKind::LoadingFailed)
+            }))
+        }
+    }
+
+    fn tail_data(source: &Source, ctx: &impl Context) -> EncData {
+        let data = Data::new(source.iri.tail().as_bytes().to_vec(), Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse("text/plain; charset=us-ascii").unwrap(),
+            content_id: ctx.generate_content_id()
+        });
+        let enc_data = data.transfer_encode(Default::default());
+        match source.use_file_name {
+            Some(ref name) => enc_data.with_file_name(name.clone()),
+            None => enc_data
+        }
+    }
+
+    fn is_same_buffer(left: &EncData, right: &EncData) -> bool {
+        Arc::ptr_eq(left.transfer_encoded_buffer(), right.transfer_encoded_buffer())
+    }
+
+    #[test]
+    fn loads_resources_only_once() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(CountingLoader::default(), 10);
+
+        let first = assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        let second = assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+
+        assert_eq!(loader.inner().loads(), 1);
+        assert!(is_same_buffer(&first, &second));
+        assert_ne!(first.content_id(), second.content_id());
+    }
+
+    #[test]
+    fn does_not_use_cached_resource_for_different_source() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(CountingLoader::default(), 10);
+
+        let mut named = test_source("mem:logo");
+        named.use_file_name = Some("logo.txt".to_owned());
+
+        assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        let loaded = assert_ok!(loader.load_resource(&named, &ctx).wait());
+        assert_eq!(loader.inner().loads(), 2);
+        assert_eq!(loader.cached_count(), 1);
+
+        let cached = assert_ok!(loader.load_resource(&named, &ctx).wait());
+        assert_eq!(loader.inner().loads(), 2);
+        assert!(is_same_buffer(&loaded, &cached));
+        assert_eq!(cached.file_meta().file_name, Some("logo.txt".to_owned()));
+    }
+
+    #[test]
+    fn does_not_cache_failures() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(CountingLoader::default(), 10);
+
+        assert_err!(loader.load_resource(&test_source("fail:logo"), &ctx).wait());
+        assert_err!(loader.load_resource(&test_source("fail:logo"), &ctx).wait());
+
+        assert_eq!(loader.inner().loads(), 2);
+        assert_eq!(loader.cached_count(), 0);
+    }
+
+    #[test]
+    fn reload_resource_bypasses_and_updates_the_cache() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(CountingLoader::default(), 10);
+
+        let first = assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        let reloaded = assert_ok!(loader.reload_resource(&test_source("mem:logo"), &ctx).wait());
+        assert_eq!(loader.inner().loads(), 2);
+        assert!(!is_same_buffer(&first, &reloaded));
+
+        let cached = assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        assert_eq!(loader.inner().loads(), 2);
+        assert!(is_same_buffer(&cached, &reloaded));
+    }
+
+    #[test]
+    fn loads_in_flight_during_reload_do_not_override_the_cache() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(DelayFirstLoader::default(), 10);
+
+        let old_load = loader.load_resource(&test_source("mem:logo"), &ctx);
+        let reloaded = assert_ok!(loader.reload_resource(&test_source("mem:logo"), &ctx).wait());
+
+        loader.inner().finish_first_load();
+        let old = assert_ok!(old_load.wait());
+        assert!(!is_same_buffer(&old, &reloaded));
+
+        let cached = assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        assert!(is_same_buffer(&cached, &reloaded));
+        assert_eq!(loader.inner().loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn loads_in_flight_during_invalidate_are_not_cached() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(DelayFirstLoader::default(), 10);
+
+        let old_load = loader.load_resource(&test_source("mem:logo"), &ctx);
+        loader.invalidate(&IRI::new("mem:logo").unwrap());
+        loader.inner().finish_first_load();
+        assert_ok!(old_load.wait());
+
+        assert_eq!(loader.cached_count(), 0);
+    }
+
+    #[test]
+    fn invalidate_removes_cached_resource() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(CountingLoader::default(), 10);
+
+        assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        assert!(loader.invalidate(&IRI::new("mem:logo").unwrap()));
+        assert!(!loader.invalidate(&IRI::new("mem:logo").unwrap()));
+        assert_eq!(loader.cached_count(), 0);
+
+        assert_ok!(loader.load_resource(&test_source("mem:logo"), &ctx).wait());
+        assert_eq!(loader.inner().loads(), 2);
+    }
+
+    #[test]
+    fn removes_least_recently_used_resource() {
+        let ctx = test_context();
+        let loader = CachingResourceLoader::new(CountingLoader::default(), 2);
+
+        assert_ok!(loader.load_resource(&test_source("mem:a"), &ctx).wait());
+        assert_ok!(loader.load_resource(&test_source("mem:b"), &ctx).wait());
+        // makes b the least recently used
+        assert_ok!(loader.load_resource(&test_source("mem:a"), &ctx).wait());
+        assert_ok!(loader.load_resource(&test_source("mem:c"), &ctx).wait());
+        assert_eq!(loader.inner().loads(), 3);
+        assert_eq!(loader.cached_count(), 2);
+
+        assert_ok!(loader.load_resource(&test_source("mem:a"), &ctx).wait());
+        assert_eq!(loader.inner().loads(), 3);
+        assert_ok!(loader.load_resource(&test_source("mem:b"), &ctx).wait());
+        assert_eq!(loader.inner().loads(), 4);
+    }
+}
@@ -6,10 +6,10 @@ use std::{
     marker::PhantomData,
 };
 
-use checked_command::CheckedCommand;
 use failure::Fail;
-use futures::IntoFuture;
+use futures::{Future, IntoFuture};
 
+use media_type::TEXT;
 use headers::header_components::{
     MediaType,
     FileMeta
@@ -19,7 +19,7 @@ use ::{
     iri::IRI,
     utils::{
         SendBoxFuture,
-        ConstSwitch, Enabled
+        ConstSwitch, Enabled, Disabled
     },
     error::{
         ResourceLoadingError,
@@ -40,24 +40,37 @@ use ::{
 
 // have a scheme ignoring variant for Mux as the scheme is preset
 // allow a setup with different scheme path/file etc. the behavior stays the same!
-// do not handle sandboxing/security as such do not handle "file" only "path" ~use open_at if available?~
+// sandboxing is opt-in (Sandbox switch), as such do not handle "file" only "path" ~use open_at if available?~
 
 //TODO more doc
 /// By setting SchemeValidation to Disabled the FsResourceLoader can be used to simple
 /// load a resource from a file based on a scheme tail as path independent of the rest,
-/// so e.g. it it is used in a `Mux` which selects a `ResourceLoader` impl based on a scheme
+/// so e.g. it it is used in a `MuxResourceLoader` which selects a `ResourceLoader` impl based on a scheme
 /// the scheme would not be double validated.
+///
+/// By setting FixNewlines to Enabled the line endings of all loaded `text/*`
+/// resources are normalized to `"\r\n"` (see `fix_newlines`), which is useful
+/// for loading text files authored with unix line endings.
+///
+/// By setting Sandbox to Enabled only files within the root can be loaded,
+/// i.e. paths escaping the root (e.g. `path:../../etc/passwd`) fail to load
+/// with `ResourceLoadingErrorKind::NotFound` (so that the existence of files
+/// outside of the root is not leaked). The check is done on the canonicalized
+/// paths, i.e. `..` and symlinks are resolved. This should be enabled if the
+/// IRIs are (even partially) build from not fully trusted input.
 #[derive( Debug, Clone, PartialEq, Default )]
 pub struct FsResourceLoader<
     SchemeValidation: ConstSwitch = Enabled,
+    FixNewlines: ConstSwitch = Disabled,
+    Sandbox: ConstSwitch = Disabled
 > {
     root: PathBuf,
     scheme: &'static str,
-    _marker: PhantomData<SchemeValidation>
+    _marker: PhantomData<(SchemeValidation, FixNewlines, Sandbox)>
 }
 
-impl<SVSw> FsResourceLoader<SVSw>
-    where SVSw: ConstSwitch
+impl<SVSw, FNSw, SBSw> FsResourceLoader<SVSw, FNSw, SBSw>
+    where SVSw: ConstSwitch, FNSw: ConstSwitch, SBSw: ConstSwitch
 {
 
     const DEFAULT_SCHEME: &'static str = "path";
@@ -89,14 +102,23 @@ impl<SVSw> FsResourceLoader<SVSw>
         SVSw::ENABLED
     }
 
+    pub fn does_fix_newlines(&self) -> bool {
+        FNSw::ENABLED
+    }
+
+    pub fn does_sandbox(&self) -> bool {
+        SBSw::ENABLED
+    }
+
     pub fn iri_has_compatible_scheme(&self, iri: &IRI) -> bool {
         iri.scheme() == self.scheme
     }
 }
 
 
-impl<ValidateScheme> ResourceLoaderComponent for FsResourceLoader<ValidateScheme>
-    where ValidateScheme: ConstSwitch
+impl<ValidateScheme, FixNewlines, Sandbox> ResourceLoaderComponent
+    for FsResourceLoader<ValidateScheme, FixNewlines, Sandbox>
+    where ValidateScheme: ConstSwitch, FixNewlines: ConstSwitch, Sandbox: ConstSwitch
 {
 
     fn load_resource(&self, source: &Source, ctx: &impl Context)
@@ -114,12 +136,48 @@ impl<ValidateScheme> ResourceLoaderComponent for FsResourceLoader<ValidateScheme
         let use_media_type = source.use_media_type.clone();
         let use_file_name = source.use_file_name.clone();
 
+        if Sandbox::ENABLED {
+            let root = self.root().to_owned();
+            let iri = source.iri.clone();
+            let ctx2 = ctx.clone();
+            let fut = ctx
+                .offload_fn(move || check_path_in_root(&root, &path))
+                .map_err(move |err| err.with_source_iri_or_else(|| Some(iri)))
+                .and_then(move |path| Self::load_path(path, use_media_type, use_file_name, &ctx2));
+
+            return Box::new(fut);
+        }
+
+        Self::load_path(path, use_media_type, use_file_name, ctx)
+    }
+}
+
+impl<ValidateScheme, FixNewlines, Sandbox> FsResourceLoader<ValidateScheme, FixNewlines, Sandbox>
+    where ValidateScheme: ConstSwitch, FixNewlines: ConstSwitch, Sandbox: ConstSwitch
+{
+    fn load_path(
+        path: PathBuf,
+        use_media_type: UseMediaType,
+        use_file_name: Option<String>,
+        ctx: &impl Context
+    ) -> SendBoxFuture<EncData, ResourceLoadingError> {
         load_data(
             path,
             use_media_type,
             use_file_name,
             ctx,
-            |data| Ok(data.transfer_encode(Default::default()))
+            |data| {
+                let data =
+                    if FixNewlines::ENABLED && data.media_type().type_() == TEXT {
+                        let buffer = fix_newlines(data.buffer().to_vec());
+                        Data::new(buffer, data.metadata().clone())
+                    } else {
+                        data
+                    };
+                #[cfg(feature="charset-transcode")]
+                let data = data.transcode_to_utf8()?;
+                Ok(data.transfer_encode(Default::default()))
+            }
         )
     }
 }
@@ -129,7 +187,7 @@ impl<ValidateScheme> ResourceLoaderComponent for FsResourceLoader<ValidateScheme
 // FixNewline, SniffMediaType and custom postprocessing
 // now this has new responsibilities
 // 2. get and create File Meta
-// 3. if source.media_type.is_none() do cautious mime sniffing
+// 3. if source.media_type.is_none() do cautious media type sniffing
 pub fn load_data<R, F>(
     path: PathBuf,
     use_media_type: UseMediaType,
@@ -143,13 +201,7 @@ pub fn load_data<R, F>(
     let content_id = ctx.generate_content_id();
     ctx.offload_fn(move || {
         let mut fd = File::open(&path)
-            .map_err(|err| {
-                if err.kind() == io::ErrorKind::NotFound {
-                    err.context(ResourceLoadingErrorKind::NotFound)
-                } else {
-                    err.context(ResourceLoadingErrorKind::LoadingFailed)
-                }
-            })?;
+            .map_err(io_error_to_loading_error)?;
 
         let mut file_meta = file_meta_from_metadata(fd.metadata()?);
 
@@ -166,7 +218,7 @@ pub fn load_data<R, F>(
         let media_type =
             match use_media_type {
                 UseMediaType::Auto => {
-                    sniff_media_type(&path)?
+                    sniff_media_type(&buffer)?
                 },
                 UseMediaType::Default(media_type) => {
                     media_type
@@ -184,26 +236,119 @@ pub fn load_data<R, F>(
 
 }
 
-fn sniff_media_type(path: impl AsRef<Path>) -> Result<MediaType, ResourceLoadingError> {
-    //TODO replace current  impl with conservative sniffing
-    let output = CheckedCommand
-        ::new("file")
-        .args(&["--brief", "--mime"])
-        .arg(path.as_ref())
-        .output()
-        .map_err(|err| err.context(ResourceLoadingErrorKind::MediaTypeDetectionFailed))?;
+/// Maps an io error to a `ResourceLoadingError` with kind `NotFound` or `LoadingFailed`.
+fn io_error_to_loading_error(err: io::Error) -> ResourceLoadingError {
+    if err.kind() == io::ErrorKind::NotFound {
+        err.context(ResourceLoadingErrorKind::NotFound).into()
+    } else {
+        err.context(ResourceLoadingErrorKind::LoadingFailed).into()
+    }
+}
 
-    let raw_media_type = String
-        ::from_utf8(output.stdout)
-        .map_err(|err| err.context(ResourceLoadingErrorKind::MediaTypeDetectionFailed))?;
+/// Checks that the path (after resolving `..` and symlinks) is within the root.
+///
+/// Returns the canonicalized path, which has to be used to open the file
+/// (instead of the original one) so that it can't be swapped out after the check.
+///
+/// Fails with `NotFound` if it isn't within the root or can not be resolved
+/// for any reason, so that the existence of files outside of the root is
+/// not leaked.
+fn check_path_in_root(root: &Path, path: &Path) -> Result<PathBuf, ResourceLoadingError> {
+    let root = root.canonicalize()
+        .map_err(io_error_to_loading_error)?;
+    let path = path.canonicalize()
+        .map_err(|err| err.context(ResourceLoadingErrorKind::NotFound))?;
+    if path.starts_with(&root) {
+        Ok(path)
+    } else {
+        Err(ResourceLoadingErrorKind::NotFound.into())
+    }
+}
+
+/// Magic numbers (file signatures) and the media type they indicate.
+const MAGIC_NUMBERS: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"PK\x07\x08", "application/zip"),
+];
+
+/// Conservatively sniffs the media type of given data.
+///
+/// This detects some common binary formats by their magic number
+/// (png, jpeg, gif, pdf and zip). Sniffing text is inherently unreliable,
+/// so data is only considered to be `text/plain` if it's valid utf-8 and
+/// doesn't contain any control characters except `'\t'`, `'\r'` and `'\n'`.
+/// The charset is `us-ascii` if the data is ascii and `utf-8` else wise.
+/// Any other data is `application/octet-stream`.
+fn sniff_media_type(buffer: &[u8]) -> Result<MediaType, ResourceLoadingError> {
+    let raw_media_type = MAGIC_NUMBERS.iter()
+        .find(|&&(magic, _)| buffer.starts_with(magic))
+        .map(|&(_, media_type)| media_type)
+        .unwrap_or_else(|| sniff_text_media_type(buffer));
 
     let media_type = MediaType
-        ::parse(raw_media_type.trim())
+        ::parse(raw_media_type)
         .map_err(|err| err.context(ResourceLoadingErrorKind::MediaTypeDetectionFailed))?;
 
     Ok(media_type)
 }
 
+fn sniff_text_media_type(buffer: &[u8]) -> &'static str {
+    let text = match ::std::str::from_utf8(buffer) {
+        Ok(text) => text,
+        Err(_) => return "application/octet-stream"
+    };
+
+    let has_control_chars = text.chars()
+        .any(|ch| ch.is_control() && ch != '\t' && ch != '\r' && ch != '\n');
+
+    if has_control_chars {
+        "application/octet-stream"
+    } else if text.is_ascii() {
+        "text/plain; charset=us-ascii"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+/// Normalizes all line endings to `"\r\n"`.
+///
+/// Orphan `'\n'` and orphan `'\r'` are replaced with `"\r\n"`,
+/// existing `"\r\n"` are left untouched.
+pub fn fix_newlines(buffer: Vec<u8>) -> Vec<u8> {
+    let needs_fixing = buffer.iter().enumerate()
+        .any(|(idx, &bch)| match bch {
+            b'\r' => buffer.get(idx + 1) != Some(&b'\n'),
+            b'\n' => idx == 0 || buffer[idx - 1] != b'\r',
+            _ => false
+        });
+
+    if !needs_fixing {
+        return buffer;
+    }
+
+    let mut out = Vec::with_capacity(buffer.len() + buffer.len() / 16);
+    let mut iter = buffer.into_iter().peekable();
+    while let Some(bch) = iter.next() {
+        match bch {
+            b'\r' => {
+                out.extend_from_slice(b"\r\n");
+                if iter.peek() == Some(&b'\n') {
+                    iter.next();
+                }
+            },
+            b'\n' => out.extend_from_slice(b"\r\n"),
+            bch => out.push(bch)
+        }
+    }
+    out
+}
+
 //TODO implement From<MetaDate> for FileMeta instead of this
 fn file_meta_from_metadata(meta: fs::Metadata) -> FileMeta {
     FileMeta {
@@ -245,18 +390,75 @@ fn path_from_tail(path_iri: &IRI) -> &Path {
 #[cfg(test)]
 mod tests {
 
+    mod fix_newlines {
+        use super::super::*;
+
+        #[test]
+        fn fixes_mixed_line_endings() {
+            let fixed = fix_newlines(b"a\nb\r\nc\rd\n\re".to_vec());
+            assert_eq!(fixed, b"a\r\nb\r\nc\r\nd\r\n\r\ne".to_vec());
+        }
+
+        #[test]
+        fn fixes_trailing_orphan_cr() {
+            let fixed = fix_newlines(b"abc\r\ndef\r".to_vec());
+            assert_eq!(fixed, b"abc\r\ndef\r\n".to_vec());
+        }
+
+        #[test]
+        fn keeps_data_without_line_endings() {
+            assert_eq!(fix_newlines(b"abc def".to_vec()), b"abc def".to_vec());
+            assert_eq!(fix_newlines(Vec::new()), Vec::<u8>::new());
+        }
+
+        #[test]
+        fn keeps_crlf() {
+            assert_eq!(fix_newlines(b"\r\nabc\r\n".to_vec()), b"\r\nabc\r\n".to_vec());
+        }
+    }
+
 
     mod sniff_media_type {
         use super::super::*;
 
+        fn sniff(buffer: &[u8]) -> String {
+            sniff_media_type(buffer).unwrap().as_str_repr().to_owned()
+        }
+
         #[test]
         fn works_reasonable_for_cargo_files() {
-            let res = sniff_media_type("./Cargo.lock")
+            let buffer = fs::read("./Cargo.toml").unwrap();
+            let res = sniff_media_type(&buffer)
                 .unwrap();
 
             // it currently doesn't take advantage of file endings so
             // all pure "text" will be text/plain
             assert_eq!(res.as_str_repr(), "text/plain; charset=us-ascii");
         }
+
+        #[test]
+        fn detects_common_magic_numbers() {
+            assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+            assert_eq!(sniff(b"\xff\xd8\xff\xe0\0\x10JFIF"), "image/jpeg");
+            assert_eq!(sniff(b"GIF89a\x01\0\x01\0"), "image/gif");
+            assert_eq!(sniff(b"%PDF-1.4\n%\xe2\xe3"), "application/pdf");
+            assert_eq!(sniff(b"PK\x03\x04\x14\0\0\0"), "application/zip");
+        }
+
+        #[test]
+        fn detects_utf8_text() {
+            assert_eq!(sniff("Grüße\r\n\tda".as_bytes()), "text/plain; charset=utf-8");
+        }
+
+        #[test]
+        fn does_not_treat_text_with_control_chars_as_text() {
+            assert_eq!(sniff(b"abc\x00def"), "application/octet-stream");
+            assert_eq!(sniff(b"abc\x1bdef"), "application/octet-stream");
+        }
+
+        #[test]
+        fn falls_back_to_octet_stream() {
+            assert_eq!(sniff(b"\xfe\xff\x00"), "application/octet-stream");
+        }
     }
 }
\ No newline at end of file
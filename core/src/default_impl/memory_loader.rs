@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    sync::Arc
+};
+
+use futures::future;
+use headers::header_components::{MediaType, FileMeta};
+
+use ::IRI;
+use utils::SendBoxFuture;
+use context::{Context, ResourceLoaderComponent};
+use error::{ResourceLoadingError, ResourceLoadingErrorKind};
+use resource::{Source, Data, EncData, Metadata};
+
+/// A resource loader loading resources from a in-memory map.
+///
+/// Resources are registered with a IRI, a media type and the data.
+/// Loading a `Source` looks up it's IRI, if no resource was registered
+/// for it loading fails with `ResourceLoadingErrorKind::NotFound`. The
+/// registered media type is always used (independent of the sources
+/// `use_media_type` field) as it's "the" media type of the resource.
+///
+/// This is mainly meant for tests as it allows creating mails with
+/// fixed resources without accessing the file system.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResourceLoader {
+    resources: HashMap<String, (MediaType, Arc<[u8]>)>
+}
+
+impl MemoryResourceLoader {
+
+    /// Create a new loader without any registered resources.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers a resource, replacing any resource previously registered with the same IRI.
+    pub fn register(&mut self, iri: IRI, media_type: MediaType, data: impl Into<Vec<u8>>) {
+        let data: Arc<[u8]> = data.into().into();
+        self.resources.insert(iri.as_str().to_owned(), (media_type, data));
+    }
+
+    /// Returns true if a resource was registered for the IRI.
+    pub fn contains(&self, iri: &IRI) -> bool {
+        self.resources.contains_key(iri.as_str())
+    }
+}
+
+impl ResourceLoaderComponent for MemoryResourceLoader {
+
+    fn load_resource(&self, source: &Source, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        let (media_type, buffer) = match self.resources.get(source.iri.as_str()) {
+            Some(&(ref media_type, ref buffer)) => (media_type.clone(), buffer.clone()),
+            None => {
+                let err = ResourceLoadingError
+                    ::from((source.iri.clone(), ResourceLoadingErrorKind::NotFound));
+                return Box::new(future::err(err));
+            }
+        };
+
+        let data = Data::new(buffer, Metadata {
+            file_meta: FileMeta {
+                file_name: source.use_file_name.clone(),
+                ..Default::default()
+            },
+            media_type,
+            content_id: ctx.generate_content_id()
+        });
+
+        ctx.offload_fn(move || Ok(data.transfer_encode(Default::default())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use headers::header_components::MediaType;
+
+    use ::IRI;
+    use context::ResourceLoaderComponent;
+    use default_impl::{test_context, test_source};
+    use resource::Source;
+    use super::MemoryResourceLoader;
+
+    fn source(iri: &str, file_name: Option<&str>) -> Source {
+        Source {
+            use_file_name: file_name.map(|name| name.to_owned()),
+            ..test_source(iri)
+        }
+    }
+
+    #[test]
+    fn loads_registered_resources() {
+        let ctx = test_context();
+        let mut loader = MemoryResourceLoader::new();
+        let media_type = MediaType::parse("image/png").unwrap();
+        loader.register(IRI::new("mem:logo").unwrap(), media_type, b"not really a png".to_vec());
+
+        let enc_data = assert_ok!(
+            loader.load_resource(&source("mem:logo", Some("logo.png")), &ctx).wait());
+
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"not really a png".to_vec());
+        assert_eq!(enc_data.file_meta().file_name, Some("logo.png".to_owned()));
+        assert_eq!(enc_data.media_type().type_().as_str(), "image");
+    }
+
+    #[test]
+    fn fails_for_unknown_resources() {
+        let ctx = test_context();
+        let loader = MemoryResourceLoader::new();
+        assert_err!(loader.load_resource(&source("mem:logo", None), &ctx).wait());
+    }
+}
@@ -1,6 +1,7 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hasher;
+use std::fmt;
 
 use rand;
 use soft_ascii_string::SoftAsciiString;
@@ -16,23 +17,32 @@ fn counter_next() -> usize {
     MAIL_COUNTER.fetch_add(1, Ordering::AcqRel)
 }
 
-fn anonymize_through_random_hash(num: usize) -> u64 {
+fn anonymize_through_random_hash(salted_hasher: &DefaultHasher, num: usize) -> u64 {
     let rnum = rand::random::<u32>();
-    let mut hasher = DefaultHasher::new();
+    hash_number(salted_hasher, num, rnum)
+}
+
+fn hash_number(salted_hasher: &DefaultHasher, num: usize, rnum: u32) -> u64 {
+    let mut hasher = salted_hasher.clone();
     hasher.write_usize(num);
     hasher.write_u32(rnum);
     hasher.finish()
 }
 
-fn gen_next_program_unique_number() -> u64 {
-    anonymize_through_random_hash(counter_next())
+fn gen_next_program_unique_number(salted_hasher: &DefaultHasher) -> u64 {
+    anonymize_through_random_hash(salted_hasher, counter_next())
 }
 
 /// a id gen implementation using hash-ing to generate part of it's left hand side
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct HashedIdGen {
-    domain: SoftAsciiString,
-    part_unique_in_domain: SoftAsciiString
+    domain: Domain,
+    ascii_domain: SoftAsciiString,
+    /// additional (puny code encoded) domains usable with `generate_message_id_for`
+    allowed_ascii_domains: Vec<SoftAsciiString>,
+    part_unique_in_domain: SoftAsciiString,
+    /// hasher which already was feed the salt
+    salted_hasher: DefaultHasher
 }
 
 impl HashedIdGen {
@@ -78,33 +88,201 @@ impl HashedIdGen {
     pub fn new(domain: Domain, part_unique_in_domain: SoftAsciiString)
         -> Result<Self, EncodingError>
     {
-        let domain = domain.into_ascii_string()?;
+        Self::new_with_salt(domain, part_unique_in_domain, &[])
+    }
+
+    /// create a new id gen from a `Domain`, a unique part and a salt.
+    ///
+    /// This works like `new` but the `salt` is feed into the hasher before
+    /// the counter and random number are hashed. This makes the hashes of
+    /// deployments using different (secret) salts unrelated, even if they
+    /// use the same `part_unique_in_domain`. `new` is the same as using an
+    /// empty salt.
+    ///
+    /// # Error
+    ///
+    /// If the domain is not ascii and puny code encoding it fails
+    pub fn new_with_salt(domain: Domain, part_unique_in_domain: SoftAsciiString, salt: &[u8])
+        -> Result<Self, EncodingError>
+    {
+        let ascii_domain = domain.clone().into_ascii_string()?;
+        let mut salted_hasher = DefaultHasher::new();
+        salted_hasher.write(salt);
         Ok(HashedIdGen {
             domain,
-            part_unique_in_domain
+            ascii_domain,
+            allowed_ascii_domains: Vec::new(),
+            part_unique_in_domain,
+            salted_hasher
         })
     }
+
+    /// allow generating message ids for an additional domain
+    ///
+    /// `generate_message_id_for` only uses the given domain if it's the
+    /// domain passed to the constructor or one allowed through this method,
+    /// for any other domain it falls back to the constructor domain. Only
+    /// allow domains "you" own (and for which `part_unique_in_domain` is unique).
+    ///
+    /// # Error
+    ///
+    /// If the domain is not ascii and puny code encoding it fails
+    pub fn allow_domain(&mut self, domain: Domain) -> Result<(), EncodingError> {
+        let ascii_domain = domain.into_ascii_string()?;
+        self.allowed_ascii_domains.push(ascii_domain);
+        Ok(())
+    }
+
+    fn is_allowed_domain(&self, ascii_domain: &SoftAsciiString) -> bool {
+        self.ascii_domain.as_str().eq_ignore_ascii_case(ascii_domain.as_str())
+            || self.allowed_ascii_domains.iter()
+                .any(|allowed| allowed.as_str().eq_ignore_ascii_case(ascii_domain.as_str()))
+    }
 }
 
-impl MailIdGenComponent for HashedIdGen {
+impl fmt::Debug for HashedIdGen {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        // the salted hasher is intentionally not included
+        write!(fter, "HashedIdGen {{ domain: {:?}, .. }}", self.domain)
+    }
+}
 
-    fn generate_message_id(&self) -> MessageId {
+impl HashedIdGen {
+    fn message_id_with_domain(&self, ascii_domain: &SoftAsciiString) -> MessageId {
+        let hash = gen_next_program_unique_number(&self.salted_hasher);
+        self.message_id_from_hash(hash, ascii_domain)
+    }
+
+    fn message_id_from_hash(&self, hash: u64, ascii_domain: &SoftAsciiString) -> MessageId {
         let msg_id = format!("{unique}.{hash:x}@{domain}",
             unique=self.part_unique_in_domain,
-            hash=gen_next_program_unique_number(),
-            domain=self.domain);
+            hash=hash,
+            domain=ascii_domain);
         MessageId::from_unchecked(msg_id)
     }
+}
+
+impl MailIdGenComponent for HashedIdGen {
+
+    fn generate_message_id(&self) -> MessageId {
+        self.message_id_with_domain(&self.ascii_domain)
+    }
+
+    /// Generates a message id with the given domain as right hand side.
+    ///
+    /// Falls back to the domain passed to the constructor if the given
+    /// domain is neither it nor one added through `allow_domain` (compared
+    /// ascii case insensitive) or if puny code encoding it fails.
+    fn generate_message_id_for(&self, domain: &Domain) -> MessageId {
+        match domain.clone().into_ascii_string() {
+            Ok(ref ascii_domain) if self.is_allowed_domain(ascii_domain) =>
+                self.message_id_with_domain(ascii_domain),
+            _ => self.generate_message_id()
+        }
+    }
 
     fn generate_content_id(&self) -> ContentId {
        self.generate_message_id().into()
     }
 
+    fn domain(&self) -> Option<&Domain> {
+        Some(&self.domain)
+    }
+
+}
+
+/// A id gen implementation using closures to generate the ids.
+///
+/// This allows custom id formats (e.g. UUID based ids or ids with a
+/// custom prefix) without implementing `MailIdGenComponent` from scratch.
+/// The closures have to return ids which are world unique, just like with
+/// any other `MailIdGenComponent`.
+///
+/// ```
+/// # extern crate mail_core;
+/// # extern crate mail_headers as headers;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use headers::header_components::MessageId;
+/// use mail_core::default_impl::FnIdGen;
+///
+/// # fn main() {
+/// static COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// let next_id = || MessageId::from_unchecked(format!(
+///     "custom.{}@example.com", COUNTER.fetch_add(1, Ordering::SeqCst)));
+///
+/// let id_gen = FnIdGen::new(next_id, move || next_id().into());
+/// # let _ = id_gen;
+/// # }
+/// ```
+pub struct FnIdGen<MF, CF>
+    where MF: Fn() -> MessageId + Send + Sync + 'static,
+          CF: Fn() -> ContentId + Send + Sync + 'static
+{
+    message_id_fn: MF,
+    content_id_fn: CF
+}
+
+impl<MF, CF> FnIdGen<MF, CF>
+    where MF: Fn() -> MessageId + Send + Sync + 'static,
+          CF: Fn() -> ContentId + Send + Sync + 'static
+{
+    /// Create a new id gen using `message_id_fn` for message ids and `content_id_fn` for content ids.
+    pub fn new(message_id_fn: MF, content_id_fn: CF) -> Self {
+        FnIdGen { message_id_fn, content_id_fn }
+    }
+}
+
+impl<MF, CF> fmt::Debug for FnIdGen<MF, CF>
+    where MF: Fn() -> MessageId + Send + Sync + 'static,
+          CF: Fn() -> ContentId + Send + Sync + 'static
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fter, "FnIdGen {{ .. }}")
+    }
+}
+
+impl<MF, CF> MailIdGenComponent for FnIdGen<MF, CF>
+    where MF: Fn() -> MessageId + Send + Sync + 'static,
+          CF: Fn() -> ContentId + Send + Sync + 'static
+{
+    fn generate_message_id(&self) -> MessageId {
+        (self.message_id_fn)()
+    }
+
+    fn generate_content_id(&self) -> ContentId {
+        (self.content_id_fn)()
+    }
 }
 
 #[cfg(test)]
 mod test {
 
+    mod hash_number {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+        use super::super::hash_number;
+
+        fn salted(salt: &[u8]) -> DefaultHasher {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(salt);
+            hasher
+        }
+
+        #[test]
+        fn different_salts_yield_different_hashes() {
+            let hash1 = hash_number(&salted(b"salt one"), 12, 42);
+            let hash2 = hash_number(&salted(b"salt two"), 12, 42);
+            assert_ne!(hash1, hash2);
+        }
+
+        #[test]
+        fn same_salt_yields_same_hash() {
+            let hash1 = hash_number(&salted(b"salt one"), 12, 42);
+            let hash2 = hash_number(&salted(b"salt one"), 12, 42);
+            assert_eq!(hash1, hash2);
+        }
+    }
+
     mod HashedIdGen {
         #![allow(non_snake_case)]
 
@@ -138,6 +316,106 @@ mod test {
             }
         }
 
+        mod message_id_format {
+            use super::*;
+
+            #[test]
+            fn is_unique_part_dot_hex_hash_at_domain() {
+                let id_gen = setup();
+                let id = id_gen.generate_message_id();
+                let (left, right) = id.as_str().split_at(id.as_str().find('@').unwrap());
+                assert_eq!(right, "@fooblabar.test");
+                assert!(left.starts_with("bfr7tz4."));
+                let hash = &left["bfr7tz4.".len()..];
+                assert!(!hash.is_empty() && hash.len() <= 16);
+                assert!(hash.chars().all(|ch| ch.is_digit(16) && !ch.is_uppercase()));
+            }
+
+            #[test]
+            fn is_stable_for_the_same_hash_and_domain() {
+                let id_gen = setup();
+                let domain = SoftAsciiString::from_unchecked("fooblabar.test");
+                let id1 = id_gen.message_id_from_hash(0xbeef, &domain);
+                let id2 = id_gen.message_id_from_hash(0xbeef, &domain);
+                assert_eq!(id1.as_str(), "bfr7tz4.beef@fooblabar.test");
+                assert_eq!(id1, id2);
+            }
+
+            #[test]
+            fn ids_of_separate_id_gens_are_unique() {
+                let id_gen1 = setup();
+                let id_gen2 = setup();
+                let mut ids = HashSet::new();
+                for _ in 0..20 {
+                    assert!(ids.insert(id_gen1.generate_message_id()));
+                    assert!(ids.insert(id_gen2.generate_message_id()));
+                }
+            }
+        }
+
+        mod debug {
+            use super::*;
+
+            #[test]
+            fn does_not_include_the_salted_hasher() {
+                let unique_part = SoftAsciiString::from_unchecked("bfr7tz4");
+                let domain = Domain::try_from("fooblabar.test").unwrap();
+                let id_gen = HashedIdGen::new_with_salt(domain, unique_part, b"secret").unwrap();
+                let debug = format!("{:?}", id_gen);
+                assert!(debug.starts_with("HashedIdGen {"));
+                assert!(!debug.contains("hasher"));
+                assert!(!debug.contains("DefaultHasher"));
+            }
+        }
+
+        mod generate_message_id_for {
+            use super::*;
+
+            #[test]
+            fn uses_the_given_domain_if_allowed() {
+                let mut id_gen = HashedIdGen::clone(&setup());
+                let domain = Domain::try_from("other.test").unwrap();
+                id_gen.allow_domain(domain.clone()).unwrap();
+                let id = id_gen.generate_message_id_for(&domain);
+                assert!(id.as_str().starts_with("bfr7tz4."));
+                assert!(id.as_str().ends_with("@other.test"));
+            }
+
+            #[test]
+            fn falls_back_to_own_domain_on_mismatch() {
+                let id_gen = setup();
+                let domain = Domain::try_from("other.test").unwrap();
+                let id = id_gen.generate_message_id_for(&domain);
+                assert!(id.as_str().starts_with("bfr7tz4."));
+                assert!(id.as_str().ends_with("@fooblabar.test"));
+            }
+        }
+
+        mod new_with_salt {
+            use super::*;
+
+            #[test]
+            fn generates_ids_with_domain_and_unique_part() {
+                let unique_part = SoftAsciiString::from_unchecked("bfr7tz4");
+                let domain = Domain::try_from("fooblabar.test").unwrap();
+                let id_gen = HashedIdGen::new_with_salt(domain, unique_part, b"secret").unwrap();
+                let id = id_gen.generate_message_id();
+                assert!(id.as_str().starts_with("bfr7tz4."));
+                assert!(id.as_str().ends_with("@fooblabar.test"));
+            }
+        }
+
+        mod domain {
+            use super::*;
+
+            #[test]
+            fn returns_the_domain_passed_to_the_constructor() {
+                let id_gen = setup();
+                let expected = Domain::try_from("fooblabar.test").unwrap();
+                assert_eq!(id_gen.domain(), Some(&expected));
+            }
+        }
+
         mod generate_content_id {
             use super::*;
 
@@ -151,4 +429,46 @@ mod test {
             }
         }
     }
-}
\ No newline at end of file
+
+    mod FnIdGen {
+        #![allow(non_snake_case)]
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use headers::header_components::{MessageId, ContentId};
+
+        use ::context::MailIdGenComponent;
+        use super::super::FnIdGen;
+
+        fn uuid_like_id(counter: &AtomicUsize) -> MessageId {
+            let num = counter.fetch_add(1, Ordering::SeqCst);
+            MessageId::from_unchecked(format!(
+                "{:08x}-0000-4000-8000-{:012x}@uuid.test", num, num * 31))
+        }
+
+        #[test]
+        fn uses_the_closures_to_generate_ids() {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let id_gen = FnIdGen::new(
+                || uuid_like_id(&COUNTER),
+                || ContentId::from(MessageId::from_unchecked("cid.1@uuid.test".to_owned()))
+            );
+
+            let first = id_gen.generate_message_id();
+            let second = id_gen.generate_message_id();
+            assert_eq!(first.as_str(), "00000000-0000-4000-8000-000000000000@uuid.test");
+            assert_eq!(second.as_str(), "00000001-0000-4000-8000-00000000001f@uuid.test");
+            let expected: ContentId = MessageId::from_unchecked("cid.1@uuid.test".to_owned()).into();
+            assert_eq!(id_gen.generate_content_id(), expected);
+        }
+
+        #[test]
+        fn is_send_sync_and_static() {
+            fn assert_component<C: MailIdGenComponent>(_: &C) {}
+            let id_gen = FnIdGen::new(
+                || MessageId::from_unchecked("a@b.test".to_owned()),
+                || MessageId::from_unchecked("c@d.test".to_owned()).into()
+            );
+            assert_component(&id_gen);
+        }
+    }
+}
@@ -7,12 +7,34 @@ mod cpupool;
 #[cfg(feature="default_impl_cpupool")]
 pub use self::cpupool::*;
 
+#[cfg(feature="default_impl_tokio")]
+mod tokio_offloader;
+#[cfg(feature="default_impl_tokio")]
+pub use self::tokio_offloader::*;
+
+#[cfg(feature="default_impl_thread_pool")]
+mod thread_pool;
+#[cfg(feature="default_impl_thread_pool")]
+pub use self::thread_pool::*;
+
 mod fs;
 pub use self::fs::*;
 
 mod message_id_gen;
 pub use self::message_id_gen::*;
 
+mod null_loader;
+pub use self::null_loader::*;
+
+mod caching_loader;
+pub use self::caching_loader::*;
+
+mod memory_loader;
+pub use self::memory_loader::*;
+
+mod mux_loader;
+pub use self::mux_loader::*;
+
 
 #[cfg(all(feature="default_impl_cpupool"))]
 pub mod simple_context;
@@ -20,10 +42,26 @@ pub mod simple_context;
 #[cfg(all(test, not(feature="default_impl_cpupool")))]
 compile_error!("test need following (default) features: default_impl_cpupool, default_impl_fs, default_impl_message_id_gen");
 
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use futures::Future;
 #[cfg(test)]
 use soft_ascii_string::SoftAsciiString;
 #[cfg(test)]
-use headers::header_components::Domain;
+use headers::header_components::{Domain, DateTime, MessageId, ContentId};
+#[cfg(test)]
+use context::{Context, MailIdScope, CompositeContext, ResourceLoaderComponent};
+#[cfg(test)]
+use ::IRI;
+#[cfg(test)]
+use futures_cpupool::CpuPool;
+#[cfg(test)]
+use error::ResourceLoadingError;
+#[cfg(test)]
+use resource::{Source, Data, EncData};
+#[cfg(test)]
+use utils::SendBoxFuture;
 
 #[cfg(test)]
 pub type TestContext = simple_context::Context;
@@ -32,11 +70,147 @@ pub type TestContext = simple_context::Context;
 #[cfg(test)]
 pub fn test_context() -> TestContext {
     //TODO crate a test context which does not access the file system
+    let (domain, unique_part) = test_id_parts();
+    simple_context::new(domain, unique_part).unwrap()
+}
+
+/// the domain and unique part used for the ids of all test contexts
+#[cfg(test)]
+pub fn test_id_parts() -> (Domain, SoftAsciiString) {
     let domain = Domain::from_unchecked("fooblabar.test".to_owned());
     let unique_part = SoftAsciiString::from_unchecked("CM0U3c412");
-    simple_context::new(domain, unique_part).unwrap()
+    (domain, unique_part)
 }
 
+/// the id gen used by `test_context`
+#[cfg(test)]
+pub fn test_id_gen() -> HashedIdGen {
+    let (domain, unique_part) = test_id_parts();
+    HashedIdGen::new(domain, unique_part).unwrap()
+}
 
+/// like `test_context` but using the given resource loader
+#[cfg(test)]
+pub fn test_context_with_loader<R>(loader: R) -> CompositeContext<R, CpuPool, HashedIdGen>
+    where R: ResourceLoaderComponent
+{
+    CompositeContext::new(loader, simple_cpu_pool(), test_id_gen())
+}
 
+/// a `Source` for the given IRI using the default settings
+#[cfg(test)]
+pub fn test_source(iri: &str) -> Source {
+    Source {
+        iri: IRI::new(iri).unwrap(),
+        use_media_type: Default::default(),
+        use_file_name: None,
+        use_disposition: None
+    }
+}
+
+/// like `test_context` but with overridable `Context` hooks
+///
+/// Every hook which is not set (`None`/`false`) uses the default impl.
+/// of the `Context` trait, everything else is delegated to a `test_context`.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct ConfigurableTestContext {
+    inner: TestContext,
+    /// returned by `Context::allowed_from_domains`
+    pub allowed_from_domains: Option<Arc<Vec<Domain>>>,
+    /// used to implement `Context::generate_boundary`
+    pub generate_boundary: Option<fn(usize) -> SoftAsciiString>,
+    /// returned by `Context::now`
+    pub now: Option<DateTime>,
+    /// returned by `Context::auto_insert_sender`
+    pub auto_insert_sender: bool
+}
+
+#[cfg(test)]
+pub fn configurable_test_context() -> ConfigurableTestContext {
+    ConfigurableTestContext {
+        inner: test_context(),
+        allowed_from_domains: None,
+        generate_boundary: None,
+        now: None,
+        auto_insert_sender: false
+    }
+}
+
+#[cfg(test)]
+impl Context for ConfigurableTestContext {
+    fn load_resource(&self, source: &Source)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        self.inner.load_resource(source)
+    }
+
+    fn transfer_encode_resource(&self, data: &Data)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        self.inner.transfer_encode_resource(data)
+    }
+
+    fn generate_message_id(&self) -> MessageId {
+        self.inner.generate_message_id()
+    }
+
+    fn generate_message_id_for(&self, domain: &Domain) -> MessageId {
+        self.inner.generate_message_id_for(domain)
+    }
 
+    fn generate_content_id(&self) -> ContentId {
+        self.inner.generate_content_id()
+    }
+
+    fn message_id_domain(&self) -> Option<&Domain> {
+        self.inner.message_id_domain()
+    }
+
+    fn begin_mail(&self) -> MailIdScope {
+        self.inner.begin_mail()
+    }
+
+    fn allowed_from_domains(&self) -> Option<&[Domain]> {
+        match self.allowed_from_domains {
+            Some(ref domains) => Some(domains),
+            None => self.inner.allowed_from_domains()
+        }
+    }
+
+    fn generate_boundary(&self, count: usize) -> SoftAsciiString {
+        match self.generate_boundary {
+            Some(generate_boundary) => generate_boundary(count),
+            None => self.inner.generate_boundary(count)
+        }
+    }
+
+    fn now(&self) -> DateTime {
+        match self.now {
+            Some(ref now) => now.clone(),
+            None => self.inner.now()
+        }
+    }
+
+    fn auto_insert_sender(&self) -> bool {
+        self.auto_insert_sender || self.inner.auto_insert_sender()
+    }
+
+    fn offload<F>(&self, fut: F) -> SendBoxFuture<F::Item, F::Error>
+        where F: Future + Send + 'static,
+              F::Item: Send + 'static,
+              F::Error: Send + 'static
+    {
+        self.inner.offload(fut)
+    }
+}
+
+/// like `test_context` but the id gen is allowed to generate message ids for the given domains
+#[cfg(test)]
+pub fn test_context_allowing_domains(domains: &[&str]) -> TestContext {
+    let mut id_gen = test_id_gen();
+    for domain in domains {
+        id_gen.allow_domain(Domain::from_unchecked(domain.to_string())).unwrap();
+    }
+    CompositeContext::new(FsResourceLoader::with_cwd_root().unwrap(), simple_cpu_pool(), id_gen)
+}
@@ -0,0 +1,162 @@
+use context::{Context, ResourceLoaderComponent};
+use error::ResourceLoadingError;
+use resource::{Source, Data, EncData};
+use utils::SendBoxFuture;
+
+use super::NullResourceLoader;
+
+/// A resource loader selecting the loader to use based on the scheme of the IRI.
+///
+/// Sources with the given scheme are loaded with the `matching` loader, all
+/// other sources are loaded with the `other` loader. As `ResourceLoaderComponent`
+/// can not be used as a trait object multiple schemes are supported by
+/// nesting `MuxResourceLoader` instances, e.g. to load `path:` from the file
+/// system and `mem:` from memory:
+///
+/// ```
+/// # extern crate mail_core;
+/// use mail_core::default_impl::{
+///     MuxResourceLoader,
+///     FsResourceLoader,
+///     MemoryResourceLoader
+/// };
+/// use mail_core::utils::Disabled;
+///
+/// # fn main() {
+/// let fs_loader: FsResourceLoader<Disabled> = FsResourceLoader::new("./");
+/// let loader = MuxResourceLoader::new("path", fs_loader)
+///     .with_scheme("mem", MemoryResourceLoader::new());
+/// # let _ = loader;
+/// # }
+/// ```
+///
+/// As the scheme is already checked by the mux the inner `FsResourceLoader`
+/// should have scheme validation disabled.
+///
+/// If the scheme of a source doesn't match any scheme loading fails with
+/// `ResourceLoadingErrorKind::NotFound` (through the `NullResourceLoader`
+/// used as innermost `other` loader).
+///
+/// `Data` resources have no IRI, so they can't be routed by scheme and
+/// are transfer encoded by the `matching` loader of the outermost mux,
+/// i.e. the loader added last (`"mem"` in the example above).
+#[derive(Debug, Clone)]
+pub struct MuxResourceLoader<M, O = NullResourceLoader>
+    where M: ResourceLoaderComponent, O: ResourceLoaderComponent
+{
+    scheme: String,
+    matching: M,
+    other: O
+}
+
+impl<M> MuxResourceLoader<M, NullResourceLoader>
+    where M: ResourceLoaderComponent
+{
+    /// Create a new mux loading sources with given scheme using `matching`.
+    ///
+    /// Loading sources with any other scheme fails with `NotFound`.
+    pub fn new(scheme: impl Into<String>, matching: M) -> Self {
+        MuxResourceLoader::new_with_other(scheme, matching, NullResourceLoader)
+    }
+}
+
+impl<M, O> MuxResourceLoader<M, O>
+    where M: ResourceLoaderComponent, O: ResourceLoaderComponent
+{
+    /// Create a new mux loading sources with given scheme using `matching` and all other using `other`.
+    pub fn new_with_other(scheme: impl Into<String>, matching: M, other: O) -> Self {
+        MuxResourceLoader {
+            scheme: scheme.into(),
+            matching,
+            other
+        }
+    }
+
+    /// Returns a new mux which first checks for given scheme and falls back to this mux.
+    pub fn with_scheme<L>(self, scheme: impl Into<String>, loader: L) -> MuxResourceLoader<L, Self>
+        where L: ResourceLoaderComponent
+    {
+        MuxResourceLoader::new_with_other(scheme, loader, self)
+    }
+
+    /// Returns the scheme for which the `matching` loader is used.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Returns a reference to the loader used for the scheme.
+    pub fn matching(&self) -> &M {
+        &self.matching
+    }
+
+    /// Returns a reference to the loader used for all other schemes.
+    pub fn other(&self) -> &O {
+        &self.other
+    }
+}
+
+impl<M, O> ResourceLoaderComponent for MuxResourceLoader<M, O>
+    where M: ResourceLoaderComponent, O: ResourceLoaderComponent
+{
+    fn load_resource(&self, source: &Source, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        if source.iri.scheme() == self.scheme {
+            self.matching.load_resource(source, ctx)
+        } else {
+            self.other.load_resource(source, ctx)
+        }
+    }
+
+    /// Transfer encodes the data using the `matching` loader.
+    ///
+    /// `Data` has no IRI to select a loader by, so this always uses the
+    /// `matching` loader, which for nested muxes is the loader added last.
+    fn transfer_encode_resource(&self, data: &Data, ctx: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        self.matching.transfer_encode_resource(data, ctx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use headers::header_components::MediaType;
+
+    use ::IRI;
+    use context::ResourceLoaderComponent;
+    use default_impl::{test_context, test_source, MemoryResourceLoader};
+    use error::ResourceLoadingErrorKind;
+    use super::MuxResourceLoader;
+
+    fn memory_loader(iri: &str, data: &[u8]) -> MemoryResourceLoader {
+        let mut loader = MemoryResourceLoader::new();
+        let media_type = MediaType::parse("text/plain; charset=us-ascii").unwrap();
+        loader.register(IRI::new(iri).unwrap(), media_type, data.to_vec());
+        loader
+    }
+
+    #[test]
+    fn selects_the_loader_by_scheme() {
+        let ctx = test_context();
+        let loader = MuxResourceLoader::new("mem", memory_loader("mem:a", b"from mem"))
+            .with_scheme("other", memory_loader("other:a", b"from other"));
+
+        let enc_data = assert_ok!(loader.load_resource(&test_source("mem:a"), &ctx).wait());
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"from mem".to_vec());
+
+        let enc_data = assert_ok!(loader.load_resource(&test_source("other:a"), &ctx).wait());
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"from other".to_vec());
+    }
+
+    #[test]
+    fn unknown_schemes_are_not_found() {
+        let ctx = test_context();
+        let loader = MuxResourceLoader::new("mem", memory_loader("mem:a", b"from mem"));
+
+        let err = assert_err!(loader.load_resource(&test_source("path:a"), &ctx).wait());
+        assert_eq!(err.kind(), ResourceLoadingErrorKind::NotFound);
+        assert_eq!(err.source_iri().map(|iri| iri.as_str()), Some("path:a"));
+    }
+}
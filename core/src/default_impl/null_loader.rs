@@ -0,0 +1,74 @@
+use futures::future;
+
+use utils::SendBoxFuture;
+use context::{Context, ResourceLoaderComponent};
+use error::{ResourceLoadingError, ResourceLoadingErrorKind};
+use resource::{Source, EncData};
+
+/// A resource loader which can not load any resource.
+///
+/// This is meant for `CompositeContext`s used for mails which only
+/// contain in-memory resources (`Resource::Data`/`Resource::EncData`),
+/// i.e. it allows creating a context without a file system loader.
+///
+/// Loading any `Source` fails with `ResourceLoadingErrorKind::NotFound`.
+/// Transfer encoding `Data` instances still works (it uses the offloader
+/// of the context).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct NullResourceLoader;
+
+impl ResourceLoaderComponent for NullResourceLoader {
+
+    fn load_resource(&self, source: &Source, _: &impl Context)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+    {
+        let err = ResourceLoadingError
+            ::from((source.iri.clone(), ResourceLoadingErrorKind::NotFound));
+        Box::new(future::err(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use internals::MailType;
+    use headers::{
+        headers::_From,
+        header_components::MediaType
+    };
+
+    use default_impl::{test_context_with_loader, test_source};
+    use resource::Resource;
+    use mail::Mail;
+    use super::*;
+
+    #[test]
+    fn mails_without_sources_can_be_encoded() {
+        let ctx = test_context_with_loader(NullResourceLoader);
+        let mut mail = Mail::new_multipart_mail(
+            MediaType::parse("multipart/alternative").unwrap(),
+            vec![
+                Mail::plain_text("r1", &ctx),
+                Mail::plain_text("r2", &ctx)
+            ]
+        );
+        mail.insert_headers(headers! {
+            _From: ["random@this.is.no.mail"]
+        }.unwrap());
+
+        let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+        assert_ok!(enc_mail.encode_into_bytes(MailType::Ascii));
+    }
+
+    #[test]
+    fn loading_sources_fails() {
+        let ctx = test_context_with_loader(NullResourceLoader);
+        let source = test_source("path:./Cargo.toml");
+        let mut mail = Mail::new_singlepart_mail(Resource::Source(source));
+        mail.insert_headers(headers! {
+            _From: ["random@this.is.no.mail"]
+        }.unwrap());
+
+        assert_err!(mail.into_encodable_mail(ctx).wait());
+    }
+}
@@ -9,16 +9,12 @@
 //!
 //! ```
 //! # extern crate mail_core as mail;
-//! # extern crate mail_headers as headers;
-//! # use headers::header_components::Domain;
 //! # // It's re-exported in the facade under `default_impl`.
-//! # use std::str::FromStr;
 //! use mail::default_impl::simple_context;
+//! use mail::utils::parse_domain;
 //!
 //! # fn main() {
-//! //TODO[FEAT]: use parse once the `Domain` component implements `FromStr`.
-//! //let domain = "example.com".parse().unwrap();
-//! let domain = Domain::from_unchecked("example.com".to_owned());
+//! let domain = parse_domain("example.com").unwrap();
 //! // This normally should be world unique for any usage with the same domain.
 //! // This is necessary to generate `Content-Id` and `Message-Id` correctly.
 //! let ascii_unique_part = "xm3r2u".parse().unwrap();
@@ -34,7 +30,7 @@ use futures_cpupool::{Builder, CpuPool};
 use internals::error::EncodingError;
 use headers::header_components::Domain;
 
-use ::context::CompositeContext;
+use ::context::{CompositeContext, ResourceLoaderComponent};
 use ::default_impl::{FsResourceLoader, HashedIdGen};
 
 /// Error returned when creating a "simple_context" fails.
@@ -52,7 +48,10 @@ pub enum ContextSetupError {
 }
 
 /// Type Alias for a the type returned by `simple_context::new`.
-pub type Context = CompositeContext<FsResourceLoader, CpuPool, HashedIdGen>;
+pub type Context = ContextWithLoader<FsResourceLoader>;
+
+/// Type Alias for a the type returned by `simple_context::new_with_loader`.
+pub type ContextWithLoader<R> = CompositeContext<R, CpuPool, HashedIdGen>;
 
 /// create a new CompositeContext<FsResourceLoader, CpuPool, HashedIdGen>
 ///
@@ -71,6 +70,21 @@ pub fn new(domain: Domain, unique_part: SoftAsciiString) -> Result<Context, Cont
         ::with_cwd_root()
         .map_err(|err| ContextSetupError::ReadingEnv(err))?;
 
+    new_with_loader(domain, unique_part, resource_loader)
+}
+
+/// create a new CompositeContext<R, CpuPool, HashedIdGen>
+///
+/// This is like `new` but uses the given resource loader instead of
+/// a `FsResourceLoader`, e.g. a `MemoryResourceLoader` or a
+/// `CachingResourceLoader`.
+pub fn new_with_loader<R>(
+    domain: Domain,
+    unique_part: SoftAsciiString,
+    resource_loader: R
+) -> Result<ContextWithLoader<R>, ContextSetupError>
+    where R: ResourceLoaderComponent
+{
     let cpu_pool = Builder::new().create();
 
     let id_gen = HashedIdGen
@@ -82,4 +96,35 @@ pub fn new(domain: Domain, unique_part: SoftAsciiString) -> Result<Context, Cont
         cpu_pool,
         id_gen,
     ))
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use headers::header_components::MediaType;
+
+    use ::IRI;
+    use context::Context;
+    use default_impl::{MemoryResourceLoader, test_id_parts, test_source};
+    use super::new_with_loader;
+
+    #[test]
+    fn new_with_loader_uses_the_given_loader() {
+        let mut loader = MemoryResourceLoader::new();
+        loader.register(
+            IRI::new("mem:text").unwrap(),
+            MediaType::parse("text/plain; charset=us-ascii").unwrap(),
+            b"abc".to_vec()
+        );
+        let (domain, unique_part) = test_id_parts();
+        let ctx = new_with_loader(domain, unique_part, loader).unwrap();
+
+        let source = test_source("mem:text");
+        let enc_data = assert_ok!(ctx.load_resource(&source).wait());
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"abc".to_vec());
+
+        // the context is still cheap to clone
+        let _ = ctx.clone();
+    }
+}
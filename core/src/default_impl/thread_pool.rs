@@ -0,0 +1,274 @@
+use std::{
+    fmt,
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, mpsc},
+    thread::{self, JoinHandle}
+};
+
+use futures::{Future, sync::oneshot};
+
+use utils::SendBoxFuture;
+use context::OffloaderComponent;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A `OffloaderComponent` using a fixed size pool of worker threads.
+///
+/// Offloaded futures are send to the workers through a channel and
+/// are driven to completion on a worker by blocking on them. This is
+/// an alternative to `futures_cpupool::CpuPool` which doesn't need any
+/// additional dependency.
+///
+/// This is only available if the `default_impl_thread_pool` feature is enabled.
+///
+/// # Panics in offloaded futures
+///
+/// Panics of offloaded futures are caught on the worker, so the worker
+/// keeps running and the pool stays usable. The future returned by
+/// `try_offload` resolves to `OffloadError::Panicked` in that case. As
+/// `OffloaderComponent::offload` has to return the error type of the
+/// offloaded future, which can't represent a panic, the future returned
+/// by it resumes the panic when polled (like it is done by `futures_cpupool`).
+///
+/// # Drop
+///
+/// Dropping the pool closes the channel and joins all workers, i.e. it
+/// blocks until all already offloaded futures are completed. If the pool
+/// is dropped on one of it's own workers (e.g. by an offloaded future
+/// owning the last reference to it) that worker is not joined but stops
+/// after the current job.
+pub struct ThreadPoolOffloader {
+    sender: Mutex<Option<mpsc::Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>
+}
+
+impl ThreadPoolOffloader {
+
+    /// Create a new pool with `thread_count` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// If `thread_count` is 0 or spawning a thread fails.
+    pub fn new(thread_count: usize) -> Self {
+        assert!(thread_count > 0, "ThreadPoolOffloader needs at least one thread");
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..thread_count)
+            .map(|idx| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("mail-offloader-{}", idx))
+                    .spawn(move || run_worker(receiver))
+                    .expect("spawning offloader worker thread failed")
+            })
+            .collect();
+
+        ThreadPoolOffloader {
+            sender: Mutex::new(Some(sender)),
+            workers
+        }
+    }
+
+    /// Returns the number of worker threads.
+    pub fn thread_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Executes the future `fut` on one of the worker threads.
+    ///
+    /// This is like `offload` but panics of the future are returned as
+    /// `OffloadError::Panicked` instead of being resumed.
+    pub fn try_offload<F>(&self, fut: F) -> SendBoxFuture<F::Item, OffloadError<F::Error>>
+        where F: Future + Send + 'static,
+              F::Item: Send+'static,
+              F::Error: Send+'static
+    {
+        let (res_sender, res_receiver) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let res = panic::catch_unwind(AssertUnwindSafe(move || fut.wait()));
+            // if the receiver was dropped no one cares about the result anymore
+            let _ = res_sender.send(res);
+        });
+
+        self.sender.lock()
+            .expect("[BUG] sending a job does not panic")
+            .as_ref()
+            .expect("[BUG] sender is only removed on drop")
+            .send(job)
+            .expect("[BUG] workers only stop after the sender is dropped");
+
+        let fut = res_receiver
+            .then(|res| match res {
+                Ok(Ok(res)) => res.map_err(OffloadError::Failed),
+                Ok(Err(panic)) => Err(OffloadError::Panicked(panic)),
+                Err(_canceled) => panic!("[BUG] offloaded job was dropped without completing")
+            });
+
+        Box::new(fut)
+    }
+}
+
+/// Error of the future returned by `ThreadPoolOffloader::try_offload`.
+#[derive(Debug)]
+pub enum OffloadError<E> {
+    /// The offloaded future resolved to an error.
+    Failed(E),
+
+    /// The offloaded future panicked, contains the panic payload.
+    Panicked(Box<dyn Any + Send + 'static>)
+}
+
+impl<E> fmt::Display for OffloadError<E>
+    where E: fmt::Display
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OffloadError::Failed(ref err) => fmt::Display::fmt(err, fter),
+            OffloadError::Panicked(_) => write!(fter, "offloaded future panicked")
+        }
+    }
+}
+
+fn run_worker(receiver: Arc<Mutex<mpsc::Receiver<Job>>>) {
+    loop {
+        // the lock is only held while waiting for the next job
+        let job = {
+            let receiver = receiver.lock()
+                .expect("[BUG] jobs do not panic while holding the lock");
+            receiver.recv()
+        };
+        match job {
+            Ok(job) => job(),
+            // all senders are dropped, i.e. the pool is dropped
+            Err(_) => break
+        }
+    }
+}
+
+impl OffloaderComponent for ThreadPoolOffloader {
+    /// executes the futures `fut` "elswhere" i.e. on one of the worker threads
+    ///
+    /// Panics of `fut` are resumed when polling the returned future,
+    /// use `try_offload` to get them as error instead.
+    fn offload<F>(&self, fut: F) -> SendBoxFuture<F::Item, F::Error>
+        where F: Future + Send + 'static,
+              F::Item: Send+'static,
+              F::Error: Send+'static
+    {
+        let fut = self.try_offload(fut)
+            .map_err(|err| match err {
+                OffloadError::Failed(err) => err,
+                OffloadError::Panicked(panic) => panic::resume_unwind(panic)
+            });
+
+        Box::new(fut)
+    }
+}
+
+impl Drop for ThreadPoolOffloader {
+    fn drop(&mut self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+        let current = thread::current().id();
+        for worker in self.workers.drain(..) {
+            // joining the current thread would block forever, it stops
+            // on it's own as the sender was dropped
+            if worker.thread().id() == current {
+                continue;
+            }
+            // panics are caught in the jobs so this should not fail,
+            // but even if it does there is nothing we can do about it
+            let _ = worker.join();
+        }
+    }
+}
+
+impl fmt::Debug for ThreadPoolOffloader {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.debug_struct("ThreadPoolOffloader")
+            .field("thread_count", &self.thread_count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic,
+        sync::{Arc, atomic::{AtomicUsize, Ordering}}
+    };
+    use futures::{future, Future};
+    use super::*;
+
+    #[test]
+    fn check_if_it_works() {
+        let pool = ThreadPoolOffloader::new(2);
+        let res = pool.offload(future::lazy(||-> Result<u32,  ()> { Ok(33u32) } )).wait();
+        let val = assert_ok!( res );
+        assert_eq!( 33u32, val );
+    }
+
+    #[test]
+    fn panics_do_not_poison_the_pool() {
+        let pool = ThreadPoolOffloader::new(1);
+        let fut = pool.offload(future::lazy(||-> Result<u32, ()> { panic!("boom") }));
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(move || fut.wait()));
+        assert_err!(res);
+
+        let res = pool.offload(future::lazy(||-> Result<u32,  ()> { Ok(12u32) } )).wait();
+        assert_eq!(assert_ok!(res), 12);
+    }
+
+    #[test]
+    fn try_offload_returns_panics_as_error() {
+        let pool = ThreadPoolOffloader::new(1);
+        let res = pool.try_offload(future::lazy(||-> Result<u32, ()> { panic!("boom") })).wait();
+        match assert_err!(res) {
+            OffloadError::Panicked(_) => {},
+            other => panic!("unexpected error: {:?}", other)
+        }
+
+        let res = pool.try_offload(future::lazy(||-> Result<u32, ()> { Err(()) })).wait();
+        match assert_err!(res) {
+            OffloadError::Failed(()) => {},
+            other => panic!("unexpected error: {:?}", other)
+        }
+    }
+
+    #[test]
+    fn dropping_the_pool_on_a_worker_does_not_deadlock() {
+        let pool = Arc::new(ThreadPoolOffloader::new(1));
+        let pool_in_job = pool.clone();
+        let (dropped_sender, dropped_receiver) = mpsc::channel::<()>();
+        let fut = pool.offload(future::lazy(move || -> Result<(), ()> {
+            // wait until the other reference is dropped so that
+            // the pool is dropped on this worker
+            let _ = dropped_receiver.recv();
+            drop(pool_in_job);
+            Ok(())
+        }));
+        drop(pool);
+        dropped_sender.send(()).unwrap();
+        assert_ok!(fut.wait());
+    }
+
+    #[test]
+    fn drop_completes_offloaded_futures() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPoolOffloader::new(2);
+            for _ in 0..4 {
+                let counter = counter.clone();
+                // the returned future is dropped, the job still runs
+                let _ = pool.offload(future::lazy(move || -> Result<(), ()> {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }));
+            }
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 4);
+    }
+}
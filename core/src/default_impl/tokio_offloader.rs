@@ -0,0 +1,97 @@
+use std::panic;
+
+use futures::Future;
+use futures03::{FutureExt, TryFutureExt};
+use tokio::runtime::Handle;
+
+use utils::SendBoxFuture;
+use context::OffloaderComponent;
+
+/// A `OffloaderComponent` using the blocking thread pool of a tokio runtime.
+///
+/// Offloaded futures are run on the runtime using `spawn_blocking`, where
+/// they are driven to completion by blocking on them. This allows using
+/// this crate in applications build on tokio without needing to pull in
+/// `futures_cpupool`.
+///
+/// This is only available if the `default_impl_tokio` feature is enabled.
+///
+/// # Compatibility Caveats
+///
+/// This crate still uses futures v0.1, as such the returned `SendBoxFuture`
+/// is a futures v0.1 future bridged from tokio's `JoinHandle` (using the
+/// `compat` layer of futures v0.3). To `.await` it in a async context it has
+/// to be bridged back using the same `compat` layer.
+///
+/// As the offloaded future is driven by blocking a thread it can not rely
+/// on being polled inside of the tokio runtime, e.g. it should not use
+/// tokio timers or tokio I/O.
+///
+/// # Panics
+///
+/// If the offloaded future panics the panic is resumed when polling the
+/// returned future (like it is done by `futures_cpupool`).
+///
+/// **Polling the returned future also panics if the runtime was shut down
+/// before the offloaded future completed**, as tokio then cancels the
+/// blocking task. The error type of the offloaded future is chosen by the
+/// caller, so the cancellation can not be turned into an error of it. Make
+/// sure the runtime outlives all offloaded futures (e.g. by not dropping
+/// it before all mails were encoded).
+#[derive(Debug, Clone)]
+pub struct TokioOffloader {
+    handle: Handle
+}
+
+impl TokioOffloader {
+
+    /// Create a new offloader which uses the runtime referred to by `handle`.
+    pub fn new(handle: Handle) -> Self {
+        TokioOffloader { handle }
+    }
+
+    /// Create a new offloader which uses the runtime of the current context.
+    ///
+    /// # Panics
+    ///
+    /// This panics if it's not called from within a tokio runtime.
+    pub fn current() -> Self {
+        TokioOffloader::new(Handle::current())
+    }
+
+    /// Returns a reference to the handle of the used runtime.
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+impl OffloaderComponent for TokioOffloader {
+    /// executes the futures `fut` "elswhere" i.e. the blocking thread pool of a tokio runtime
+    ///
+    /// # Panics
+    ///
+    /// The returned future panics when polled if the runtime was shut down
+    /// before `fut` completed (see the type level documentation).
+    fn offload<F>(&self, fut: F) -> SendBoxFuture<F::Item, F::Error>
+        where F: Future + Send + 'static,
+              F::Item: Send+'static,
+              F::Error: Send+'static
+    {
+        let bridged = self.handle
+            .spawn_blocking(move || fut.wait())
+            .map(|res| match res {
+                Ok(res) => res,
+                Err(err) => {
+                    if err.is_panic() {
+                        panic::resume_unwind(err.into_panic())
+                    } else {
+                        panic!("offloaded future was canceled (the tokio runtime was shut down?): {}", err)
+                    }
+                }
+            })
+            .boxed()
+            .compat();
+
+        Box::new(bridged)
+    }
+}
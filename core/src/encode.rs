@@ -6,6 +6,7 @@ use soft_ascii_string::{
 use media_type::BOUNDARY;
 
 use internals::{
+    MailType,
     encoder::{
         EncodingBuffer, EncodingWriter,
     },
@@ -15,14 +16,18 @@ use headers::{
     HeaderName,
     HeaderObj, HeaderObjTrait,
     HeaderKind,
-    headers::{ContentTransferEncoding, ContentType}
+    headers::{ContentTransferEncoding, ContentType, Bcc},
+    header_components::{MediaType, TransferEncoding}
 };
 
 use ::{
-    error::MailError,
+    error::{MailError, OtherValidationError},
+    resource::MAX_LINE_LENGTH,
+    mime::is_token,
     mail::{
         Mail,
         EncodableMail,
+        EncodeOptions,
         assume_encoded
     }
 };
@@ -39,7 +44,29 @@ pub(crate) fn encode_mail(
     top: bool,
     encoder: &mut EncodingBuffer
 ) -> Result<(), MailError> {
-    _encode_mail(&*mail, top, encoder)
+    encode_mail_in_parts(mail, top, encoder, &mut |_| Ok(()))
+}
+
+/// Like `encode_mail` but calls `part_done` after each singlepart body.
+///
+/// This allows taking the already encoded data out of the encoder (e.g.
+/// to write it to a file), so that the whole encoded mail doesn't have
+/// to be kept in memory. At the point `part_done` is called the encoder
+/// is always at the begin of a line.
+///
+/// # Panics
+/// if the body is not yet resolved use `Body::poll_body` or `IntoFuture`
+/// on `Mail` to prevent this from happening
+///
+pub(crate) fn encode_mail_in_parts<F>(
+    mail: &EncodableMail,
+    top: bool,
+    encoder: &mut EncodingBuffer,
+    part_done: &mut F
+) -> Result<(), MailError>
+    where F: FnMut(&mut EncodingBuffer) -> Result<(), MailError>
+{
+    _encode_mail(&*mail, top, mail.encode_options(), encoder, part_done)
         .map_err(|err| {
             let mail_type = encoder.mail_type();
             use self::MailError::*;
@@ -51,17 +78,21 @@ pub(crate) fn encode_mail(
         })
 }
 
-fn _encode_mail(
+fn _encode_mail<F>(
     mail: &Mail,
     top: bool,
-    encoder: &mut EncodingBuffer
-) -> Result<(), MailError> {
-    encode_headers(&mail, top, encoder)?;
+    options: &EncodeOptions,
+    encoder: &mut EncodingBuffer,
+    part_done: &mut F
+) -> Result<(), MailError>
+    where F: FnMut(&mut EncodingBuffer) -> Result<(), MailError>
+{
+    encode_headers(&mail, top, options, encoder)?;
 
     //the empty line between the headers and the body
     encoder.write_blank_line();
 
-    encode_mail_part(&mail, encoder)?;
+    encode_mail_part(&mail, options, encoder, part_done)?;
 
     Ok(())
 }
@@ -74,19 +105,32 @@ fn _encode_mail(
 fn encode_headers(
     mail: &Mail,
     top: bool,
+    options: &EncodeOptions,
     encoder:  &mut EncodingBuffer
 ) -> Result<(), MailError> {
     use super::MailBody::*;
 
     let mut handle = encoder.writer();
     if top {
-        handle.write_str(SoftAsciiStr::from_unchecked(
-            "MIME-Version: 1.0"
-        ))?;
-        handle.finish_header();
+        if let Some(ref version) = options.mime_version {
+            let version: &str = &**version;
+            if !is_valid_mime_version(version) {
+                return Err(OtherValidationError::InvalidMimeVersion {
+                    version: version.to_owned()
+                }.into());
+            }
+            handle.write_str(SoftAsciiStr::from_unchecked("MIME-Version: "))?;
+            handle.write_str(SoftAsciiStr::from_unchecked(version))?;
+            handle.finish_header();
+        }
     }
 
     for (name, hbody) in mail.headers().iter() {
+        // `Bcc` is only used for the envelope and must never be transmitted
+        if name == Bcc::name() {
+            continue;
+        }
+
         let name_as_str = name.as_str();
         let ignored_header = !top &&
             !(name_as_str.starts_with("Content-")
@@ -96,7 +140,12 @@ fn encode_headers(
             warn!("non `Content-` header in MIME body: {:?}: {:?}", name, hbody);
         }
 
-        encode_header(&mut handle, name, hbody)?;
+        if mail.has_multipart_body() && name == ContentType::name() {
+            let media_type = multipart_content_type(mail)?;
+            encode_multipart_content_type(&mut handle, media_type, options)?;
+        } else {
+            encode_header(&mut handle, name, hbody)?;
+        }
     }
 
 
@@ -113,7 +162,7 @@ fn encode_headers(
     Ok(())
 }
 
-fn encode_header(
+pub(crate) fn encode_header(
     handle: &mut EncodingWriter,
     name: HeaderName,
     header: &HeaderObj
@@ -133,13 +182,104 @@ fn encode_header(
     })
 }
 
+/// Encodes the `Content-Type` header of a multipart body.
+///
+/// The `boundary` parameter is always written by this function
+/// (instead of the `MediaType` encoding) so that the quoting can
+/// be controlled through `EncodeOptions::always_quote_boundary`.
+fn encode_multipart_content_type(
+    handle: &mut EncodingWriter,
+    media_type: &MediaType,
+    options: &EncodeOptions
+) -> Result<(), MailError> {
+    let boundary = get_boundary(media_type)?;
+    let boundary_param = boundary_param(&boundary, options.always_quote_boundary);
+
+    let mut without_boundary = media_type.clone();
+    without_boundary.remove_param(BOUNDARY);
+    let header = ContentType::body(without_boundary);
+    let name = header.name();
+    let header: &HeaderObj = &header;
+
+    //FIXME[rust/catch] use catch block
+    let res = (|| -> Result<(), EncodingError> {
+        handle.write_str(name.as_ascii_str())?;
+        handle.write_char(SoftAsciiChar::from_unchecked(':'))?;
+        handle.write_fws();
+        header.encode(handle)?;
+        handle.write_char(SoftAsciiChar::from_unchecked(';'))?;
+        handle.write_fws();
+        handle.write_str(&*boundary_param)?;
+        handle.finish_header();
+        Ok(())
+    })();
+
+    res.map_err(|err| {
+        err.with_place_or_else(|| Some(Place::Header { name: name.as_str() })).into()
+    })
+}
+
+/// Returns the `boundary=...` parameter, quoting the boundary if needed or forced.
+fn boundary_param(boundary: &SoftAsciiStr, always_quote: bool) -> SoftAsciiString {
+    let param = if always_quote || boundary_needs_quoting(boundary.as_str()) {
+        format!("boundary=\"{}\"", boundary)
+    } else {
+        format!("boundary={}", boundary)
+    };
+    SoftAsciiString::from_unchecked(param)
+}
+
+/// Returns true if the boundary is not a valid `token` (rfc2045).
+fn boundary_needs_quoting(boundary: &str) -> bool {
+    !is_token(boundary)
+}
+
+/// Returns the media type of the `Content-Type` header of a multipart mail.
+///
+/// Mails are validated when turned into a `EncodableMail` so this
+/// should not fail, but a missing header should not cause a panic.
+fn multipart_content_type(mail: &Mail) -> Result<&MediaType, MailError> {
+    match mail.headers().get_single(ContentType) {
+        Some(header) => {
+            let header = header?;
+            Ok(&**header)
+        },
+        None => Err(OtherValidationError::MissingContentTypeHeader.into())
+    }
+}
+
+fn get_boundary(media_type: &MediaType) -> Result<SoftAsciiString, MailError> {
+    let boundary = media_type
+        .get_param(BOUNDARY)
+        .ok_or(OtherValidationError::MissingBoundary)?
+        .to_content();
+
+    let boundary = SoftAsciiString
+        ::from_string(boundary)
+        .map_err(|orig_string| EncodingError
+            ::from(EncodingErrorKind::InvalidTextEncoding {
+                got_encoding: UTF_8,
+                expected_encoding: US_ASCII
+            })
+            .with_place_or_else(|| Some(Place::Header { name: "Content-Type" }))
+            .with_str_context(orig_string.into_source())
+        )?;
+
+    Ok(boundary)
+}
+
 ///
 /// # Panics
 /// if the body is not yet resolved use `Body::poll_body` or `IntoFuture`
 /// on `Mail` to prevent this from happening
 ///
-fn encode_mail_part(mail: &Mail, encoder:  &mut EncodingBuffer )
-    -> Result<(), MailError>
+fn encode_mail_part<F>(
+    mail: &Mail,
+    options: &EncodeOptions,
+    encoder: &mut EncodingBuffer,
+    part_done: &mut F
+) -> Result<(), MailError>
+    where F: FnMut(&mut EncodingBuffer) -> Result<(), MailError>
 {
     use super::MailBody::*;
 
@@ -149,43 +289,33 @@ fn encode_mail_part(mail: &Mail, encoder:  &mut EncodingBuffer )
         SingleBody { ref body } => {
             let data = assume_encoded(body);
             let buffer = data.transfer_encoded_buffer();
+            check_transfer_encoding(data.encoding(), encoder.mail_type())?;
+            match data.encoding() {
+                TransferEncoding::_7Bit | TransferEncoding::_8Bit => {
+                    check_body_line_length(buffer)?;
+                },
+                _ => {}
+            }
             encoder.write_body_unchecked(buffer);
+            part_done(encoder)?;
         },
         MultipleBodies { ref hidden_text, ref bodies } => {
+            let boundary = get_boundary(multipart_content_type(mail)?)?;
+
             if hidden_text.len() > 0 {
-                //TODO find out if there is any source using the hidden text
-                // (e.g. for some form of validation, prove of senders validity etc.)
-                // if not drop the "hidden_text" field
-                warn!("\"hidden text\" in multipart bodies is dropped")
+                // the preamble, which is ignored by MIME aware clients
+                check_hidden_text(hidden_text.as_str(), boundary.as_str())?;
+                check_body_line_length(hidden_text.as_bytes())?;
+                encoder.write_body_unchecked(hidden_text.as_bytes());
             }
 
-            let mail_was_validated_err_msg = "[BUG] mail was already validated";
-            let boundary = mail.headers()
-                .get_single(ContentType)
-                .expect(mail_was_validated_err_msg)
-                .expect(mail_was_validated_err_msg)
-                .get_param(BOUNDARY)
-                .expect(mail_was_validated_err_msg)
-                .to_content();
-
-            let boundary = SoftAsciiString
-                ::from_string(boundary)
-                .map_err(|orig_string| EncodingError
-                    ::from(EncodingErrorKind::InvalidTextEncoding {
-                        got_encoding: UTF_8,
-                        expected_encoding: US_ASCII
-                    })
-                    .with_place_or_else(|| Some(Place::Header { name: "Content-Type" }))
-                    .with_str_context(orig_string.into_source())
-                )?;
-
             for mail in bodies.iter() {
                 encoder.write_header_line(|handle| {
                     handle.write_char(minus)?;
                     handle.write_char(minus)?;
                     handle.write_str(&*boundary)
                 })?;
-                _encode_mail(mail, false, encoder)?;
+                _encode_mail(mail, false, options, encoder, part_done)?;
             }
 
             if bodies.len() > 0 {
@@ -201,3 +331,188 @@ fn encode_mail_part(mail: &Mail, encoder:  &mut EncodingBuffer )
     }
     Ok(())
 }
+
+/// Checks that the hidden text (preamble) can not be mistaken for a part of the body.
+///
+/// It must neither contain the boundary delimiter (`"--<boundary>"`) nor
+/// orphan `'\r'`/`'\n'`, both could make clients split the body wrongly.
+fn check_hidden_text(hidden_text: &str, boundary: &str) -> Result<(), MailError> {
+    let has_orphan_newline = hidden_text.replace("\r\n", "")
+        .contains(|ch: char| ch == '\r' || ch == '\n');
+
+    if has_orphan_newline || hidden_text.contains(&format!("--{}", boundary)) {
+        Err(OtherValidationError::InvalidHiddenText.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that no line of a (not line wrapping) body breaks the hard line length limit.
+///
+/// The error has the place `Place::Body` and includes the length of the
+/// first too long line and the limit in it's context.
+///
+/// Header lines are not checked by this function, they are folded and
+/// checked by the `EncodingWriter` while encoding the header. Errors for
+/// them have a `Place::Header` with the header name but do not include
+/// the line length.
+fn check_body_line_length(buffer: &[u8]) -> Result<(), EncodingError> {
+    let too_long = buffer
+        .split(|&bch| bch == b'\n')
+        .map(|line| if line.ends_with(b"\r") { line.len() - 1 } else { line.len() })
+        .find(|&line_length| line_length > MAX_LINE_LENGTH);
+
+    match too_long {
+        None => Ok(()),
+        Some(line_length) => Err(EncodingError
+            ::from(EncodingErrorKind::HardLineLengthLimitBreached)
+            .with_place_or_else(|| Some(Place::Body))
+            .with_str_context(format!(
+                "line length: {}, limit: {}", line_length, MAX_LINE_LENGTH)))
+    }
+}
+
+/// Checks if the version has the form `1*DIGIT "." 1*DIGIT` (rfc2045).
+fn is_valid_mime_version(version: &str) -> bool {
+    let is_number = |part: &str| !part.is_empty() && part.bytes().all(|bch| bch.is_ascii_digit());
+    match version.find('.') {
+        Some(idx) => is_number(&version[..idx]) && is_number(&version[idx+1..]),
+        None => false
+    }
+}
+
+/// Checks that the transfer encoding of a body can be used with the mail type.
+///
+/// Bodies with a `8bit` or `binary` transfer encoding can not be used
+/// in a `MailType::Ascii` mail, as it's not known if the mail transfer
+/// supports 8bit data.
+fn check_transfer_encoding(encoding: TransferEncoding, mail_type: MailType)
+    -> Result<(), MailError>
+{
+    let encoding_name = match encoding {
+        TransferEncoding::_8Bit => "8bit",
+        TransferEncoding::Binary => "binary",
+        _ => return Ok(())
+    };
+
+    match mail_type {
+        MailType::Ascii => Err(OtherValidationError::TransferEncodingRequires8Bit {
+            encoding: encoding_name.to_owned()
+        }.into()),
+        _ => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use soft_ascii_string::SoftAsciiStr;
+    use internals::MailType;
+    use headers::header_components::TransferEncoding;
+    use internals::encoder::EncodingBuffer;
+    use headers::header_components::MediaType;
+    use default_impl::test_context;
+    use error::MailError;
+    use mail::{Mail, EncodeOptions};
+    use super::{
+        boundary_param,
+        check_hidden_text,
+        check_body_line_length, MAX_LINE_LENGTH,
+        check_transfer_encoding,
+        encode_mail_part,
+        get_boundary,
+        is_valid_mime_version
+    };
+
+    #[test]
+    fn mime_versions_have_to_be_two_dot_separated_numbers() {
+        assert!(is_valid_mime_version("1.0"));
+        assert!(is_valid_mime_version("12.345"));
+        assert_not!(is_valid_mime_version(""));
+        assert_not!(is_valid_mime_version("1"));
+        assert_not!(is_valid_mime_version("1."));
+        assert_not!(is_valid_mime_version(".0"));
+        assert_not!(is_valid_mime_version("1.0.1"));
+        assert_not!(is_valid_mime_version("1.0 (comment)"));
+        assert_not!(is_valid_mime_version("1.0\r\nX-Injected: yes"));
+        assert_not!(is_valid_mime_version("１.0"));
+    }
+
+    #[test]
+    fn binary_and_8bit_bodies_are_rejected_for_ascii_mails() {
+        assert_err!(check_transfer_encoding(TransferEncoding::Binary, MailType::Ascii));
+        assert_err!(check_transfer_encoding(TransferEncoding::_8Bit, MailType::Ascii));
+        assert_ok!(check_transfer_encoding(TransferEncoding::_7Bit, MailType::Ascii));
+        assert_ok!(check_transfer_encoding(TransferEncoding::Base64, MailType::Ascii));
+        assert_ok!(check_transfer_encoding(TransferEncoding::QuotedPrintable, MailType::Ascii));
+    }
+
+    #[test]
+    fn binary_and_8bit_bodies_are_fine_for_8bit_mails() {
+        assert_ok!(check_transfer_encoding(TransferEncoding::Binary, MailType::Mime8BitEnabled));
+        assert_ok!(check_transfer_encoding(TransferEncoding::_8Bit, MailType::Mime8BitEnabled));
+        assert_ok!(check_transfer_encoding(TransferEncoding::Binary, MailType::Internationalized));
+    }
+
+    #[test]
+    fn body_lines_up_to_the_limit_are_fine() {
+        let mut body = vec![b'a'; MAX_LINE_LENGTH];
+        body.extend_from_slice(b"\r\nshort\r\n");
+        assert_ok!(check_body_line_length(&body));
+    }
+
+    #[test]
+    fn too_long_body_lines_are_reported_with_their_length() {
+        let mut body = b"short\r\n".to_vec();
+        body.extend(vec![b'a'; MAX_LINE_LENGTH + 2]);
+        let err = assert_err!(check_body_line_length(&body));
+        let msg = format!("{:?}", err);
+        assert!(msg.contains("line length: 1000, limit: 998"));
+    }
+
+    #[test]
+    fn hidden_text_with_crlf_line_breaks_is_fine() {
+        assert_ok!(check_hidden_text("This is a MIME mail.\r\nUpgrade!\r\n", "xy"));
+    }
+
+    #[test]
+    fn hidden_text_with_orphan_newlines_is_rejected() {
+        assert_err!(check_hidden_text("This is a MIME mail.\nUpgrade!", "xy"));
+        assert_err!(check_hidden_text("This is a MIME mail.\rUpgrade!", "xy"));
+        assert_err!(check_hidden_text("This is a MIME mail.\r\r\nUpgrade!", "xy"));
+    }
+
+    #[test]
+    fn token_boundary_is_only_quoted_if_forced() {
+        let boundary = SoftAsciiStr::from_unchecked("simple.Boundary_42");
+        assert_eq!(boundary_param(boundary, false).as_str(), "boundary=simple.Boundary_42");
+        assert_eq!(boundary_param(boundary, true).as_str(), "boundary=\"simple.Boundary_42\"");
+    }
+
+    #[test]
+    fn boundary_with_special_chars_is_always_quoted() {
+        let boundary = SoftAsciiStr::from_unchecked("=_^0.a(b)c:d");
+        assert_eq!(boundary_param(boundary, false).as_str(), "boundary=\"=_^0.a(b)c:d\"");
+        assert_eq!(boundary_param(boundary, true).as_str(), "boundary=\"=_^0.a(b)c:d\"");
+    }
+
+    #[test]
+    fn missing_boundary_is_an_error() {
+        let media_type = MediaType::parse("multipart/mixed").unwrap();
+        let err = assert_err!(get_boundary(&media_type));
+        if let MailError::Validation(_) = err {
+        } else {
+            panic!("unexpected error: {:?}", err);
+        }
+    }
+
+    #[test]
+    fn encoding_multipart_body_without_boundary_fails_without_panic() {
+        let ctx = test_context();
+        let mail = Mail::new_multipart_mail(
+            MediaType::parse("multipart/mixed").unwrap(),
+            vec![ Mail::plain_text("r1", &ctx) ]
+        );
+        let mut encoder = EncodingBuffer::new(MailType::Ascii);
+        assert_err!(encode_mail_part(&mail, &EncodeOptions::default(), &mut encoder, &mut |_| Ok(())));
+    }
+}
@@ -58,7 +58,35 @@ pub enum ResourceLoadingErrorKind {
     LoadingFailed,
 
     #[fail(display = "automatically detecting the media type failed")]
-    MediaTypeDetectionFailed
+    MediaTypeDetectionFailed,
+
+    /// Transcoding the data of a text resource to utf-8 failed.
+    ///
+    /// E.g. because the charset is unknown or the data is not
+    /// valid for the given charset.
+    #[fail(display = "transcoding the resource to utf-8 failed")]
+    CharsetTranscodingFailed,
+
+    /// Loading the resource took longer than the given timeout.
+    ///
+    /// E.g. returned by `Resource::load_with_timeout`.
+    #[fail(display = "loading the resource timed out")]
+    TimedOut
+}
+
+impl ResourceLoadingErrorKind {
+
+    /// Returns true if retrying to load the resource later on might succeed.
+    ///
+    /// This is the case for `LoadingFailed` (e.g. an I/O-Error) and `TimedOut`,
+    /// but not for e.g. `NotFound` or a failed media type detection.
+    pub fn is_transient(&self) -> bool {
+        use self::ResourceLoadingErrorKind::*;
+        match *self {
+            LoadingFailed | TimedOut => true,
+            NotFound | MediaTypeDetectionFailed | CharsetTranscodingFailed => false
+        }
+    }
 }
 
 /// The loading of an Resource failed.
@@ -96,6 +124,21 @@ impl ResourceLoadingError {
         self.iri.as_ref()
     }
 
+    /// The kind of the I/O-Error which caused the loading to fail (if any).
+    ///
+    /// This searches the cause chain for an `io::Error`, which e.g. allows
+    /// differing between a missing permission and other loading failures.
+    pub fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        let mut cause = self.inner.cause();
+        while let Some(fail) = cause {
+            if let Some(io_error) = fail.downcast_ref::<io::Error>() {
+                return Some(io_error.kind());
+            }
+            cause = fail.cause();
+        }
+        None
+    }
+
     /// Sets the source IRI if not already set and returns self.
     pub fn with_source_iri_or_else<F>(mut self, func: F) -> Self
         where F: FnOnce() -> Option<IRI>
@@ -182,7 +225,69 @@ pub enum OtherValidationError {
 
     /// A mail (top level, not in multipart) requires a `From` header to be given.
     #[fail(display = "mail did not contain a From header")]
-    NoFrom
+    NoFrom,
+
+    /// The date passed to `Mail::set_date_checked` was out of bounds.
+    #[fail(display = "date is out of the accepted bounds")]
+    DateOutOfBounds,
+
+    /// The `From` header contains a mailbox with a domain not in `Context::allowed_from_domains`.
+    #[fail(display = "From header contains mailbox with not allowed domain: {}", domain)]
+    FromDomainNotAllowed {
+        domain: String
+    },
+
+    /// The boundary of a multipart body is not valid wrt. rfc2046.
+    #[fail(display = "invalid multipart boundary: {:?}", boundary)]
+    InvalidBoundary {
+        boundary: String
+    },
+
+    /// The `Content-Type` header of a multipart body has no boundary parameter.
+    #[fail(display = "multipart content type has no boundary parameter")]
+    MissingBoundary,
+
+    /// The hidden text (preamble) of a multipart body contains it's boundary
+    /// delimiter (`"--<boundary>"`) or orphan `'\r'`/`'\n'`.
+    #[fail(display = "invalid hidden text (preamble) of multipart body")]
+    InvalidHiddenText,
+
+    /// A body with a `8bit` or `binary` transfer encoding was encoded
+    /// using a mail type which does not support 8bit data (`MailType::Ascii`).
+    #[fail(display = "Content-Transfer-Encoding: {} requires a mail type supporting 8bit", encoding)]
+    TransferEncodingRequires8Bit {
+        encoding: String
+    },
+
+    /// `EncodeOptions.mime_version` is not of the form `1*DIGIT "." 1*DIGIT`.
+    #[fail(display = "invalid MIME-Version: {:?}", version)]
+    InvalidMimeVersion {
+        version: String
+    },
+
+    /// A mail was composed without any body.
+    #[fail(display = "can not compose a mail without any body")]
+    NoBody,
+
+    /// `Mail::set_reply_to` was called without any mailbox.
+    #[fail(display = "Reply-To header requires at least one mailbox")]
+    EmptyReplyTo,
+
+    /// `Mail::insert_raw_headers` was called with a header name it doesn't support.
+    #[fail(display = "unsupported raw header: {}", name)]
+    UnsupportedRawHeader {
+        name: String
+    },
+
+    /// A value passed to `Mail::insert_raw_headers` contains a `'\r'` or `'\n'`.
+    #[fail(display = "raw value of {} header contains a line break", name)]
+    RawHeaderValueWithLineBreak {
+        name: String
+    },
+
+    /// The mail has no recipients, i.e. no mailboxes in `To`, `Cc` or `Bcc`.
+    #[fail(display = "mail has no recipients")]
+    NoRecipients
 }
 
 impl From<OtherValidationError> for HeaderValidationError {
@@ -227,7 +332,37 @@ pub enum MailError {
     /// E.g. the file to attach or the image to embedded could not
     /// be found.
     #[fail(display = "{}", _0)]
-    ResourceLoading(ResourceLoadingError)
+    ResourceLoading(ResourceLoadingError),
+
+    /// A I/O operation failed.
+    ///
+    /// E.g. writing the parts of a mail with `EncodableMail::export_parts` failed.
+    ///
+    /// **Breaking change:** this variant was added in `0.6`, code matching
+    /// exhaustively on `MailError` has to handle it.
+    #[fail(display = "{}", _0)]
+    Io(io::Error)
+}
+
+impl MailError {
+
+    /// Returns true if retrying the failed operation later on might succeed.
+    ///
+    /// Failing to load a resource is transient depending on it's kind
+    /// (see `ResourceLoadingErrorKind::is_transient`) and I/O errors are
+    /// always considered transient. All other errors (e.g. validation,
+    /// component creation or encoding errors) are permanent, i.e. retrying
+    /// with the same mail will fail again.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            MailError::ResourceLoading(ref err) => err.kind().is_transient(),
+            MailError::Io(_) => true,
+            MailError::Encoding(_)
+            | MailError::Type(_)
+            | MailError::Component(_)
+            | MailError::Validation(_) => false
+        }
+    }
 }
 
 impl From<BuildInValidationError> for MailError {
@@ -270,6 +405,12 @@ impl From<ResourceError> for MailError {
     }
 }
 
+impl From<io::Error> for MailError {
+    fn from(err: io::Error) -> Self {
+        MailError::Io(err)
+    }
+}
+
 impl From<ComponentCreationError> for MailError {
     fn from(err: ComponentCreationError) -> Self {
         MailError::Component(err)
@@ -277,7 +418,27 @@ impl From<ComponentCreationError> for MailError {
 }
 
 
+/// Error returned when setting the preferred transfer encoding of a `Resource` fails.
+#[derive(Copy, Clone, Debug, Fail, PartialEq, Eq, Hash)]
+pub enum PreferredEncodingError {
+    /// The resource is already transfer encoded.
+    #[fail(display = "resource is already transfer encoded")]
+    AlreadyEncoded,
+
+    /// The resource is a `Source`, the encoding of it is up to the resource loader.
+    #[fail(display = "resource is not loaded, can't set preferred encoding")]
+    NotLoaded,
+
+    /// 7bit was requested but the data can not be represented as 7bit.
+    #[fail(display = "resource data is not 7bit compatible")]
+    Not7BitCompatible
+}
+
 /// Error returned when trying to _unload_ and `Resource` and it fails.
+///
+/// Note that resources can't be unloaded anymore (they are plain values),
+/// this error isn't returned by anything in this crate but is kept for
+/// compatibility.
 #[derive(Copy, Clone, Debug, Fail)]
 pub enum ResourceNotUnloadableError {
     /// The resource can not be unloaded because its in use.
@@ -290,4 +451,43 @@ pub enum ResourceNotUnloadableError {
     /// the deletion/dropping of `Resource` instances.
     #[fail(display = "resource has no source, can't unload it")]
     NoSource
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use super::*;
+
+    #[test]
+    fn resource_loading_errors_are_classified_by_kind() {
+        let err = MailError::from(ResourceLoadingError::from(ResourceLoadingErrorKind::LoadingFailed));
+        assert!(err.is_transient());
+        let err = MailError::from(ResourceLoadingError::from(ResourceLoadingErrorKind::TimedOut));
+        assert!(err.is_transient());
+        let err = MailError::from(ResourceLoadingError::from(ResourceLoadingErrorKind::NotFound));
+        assert!(!err.is_transient());
+    }
+
+    #[test]
+    fn io_error_kind_is_kept() {
+        let err = ResourceLoadingError::from(
+            io::Error::new(io::ErrorKind::PermissionDenied, "no access"));
+        assert_eq!(err.kind(), ResourceLoadingErrorKind::LoadingFailed);
+        assert_eq!(err.io_error_kind(), Some(io::ErrorKind::PermissionDenied));
+
+        let err = ResourceLoadingError::from(ResourceLoadingErrorKind::NotFound);
+        assert_eq!(err.io_error_kind(), None);
+    }
+
+    #[test]
+    fn io_errors_are_transient() {
+        let err = MailError::from(io::Error::new(io::ErrorKind::Other, "broken pipe"));
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn validation_errors_are_not_transient() {
+        let err = MailError::from(OtherValidationError::NoFrom);
+        assert!(!err.is_transient());
+    }
+}
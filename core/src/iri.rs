@@ -108,6 +108,13 @@ impl IRI {
             .unwrap()
     }
 
+    /// Creates a new IRI with the same tail but a different scheme.
+    ///
+    /// Like with `new` the scheme is validated and converted to lower case.
+    pub fn with_scheme(&self, new_scheme: &str) -> Result<Self, InvalidIRIScheme> {
+        IRI::from_parts(new_scheme, self.tail())
+    }
+
     /// The scheme part of the uri excluding the `:` seperator.
     ///
     /// The scheme is guaranteed to be lower case.
@@ -254,6 +261,17 @@ mod test {
         assert_eq!(iri.as_str(), "foo:bar/bazz");
     }
 
+    #[test]
+    fn replacing_scheme_does_that() {
+        let iri = IRI::new("file:///a").unwrap();
+        let new_iri = iri.with_scheme("PATH").unwrap();
+
+        assert_eq!(new_iri.as_str(), "path:///a");
+        assert_eq!(new_iri.scheme(), "path");
+        assert_eq!(iri.as_str(), "file:///a");
+        assert!(iri.with_scheme("g ap").is_err());
+    }
+
     #[cfg(feature="serde")]
     #[test]
     fn serde_works_for_str_iri() {
@@ -16,6 +16,7 @@ extern crate futures;
 extern crate rand;
 extern crate vec1;
 extern crate soft_ascii_string;
+extern crate md5;
 
 #[cfg(feature="serde")]
 extern crate serde;
@@ -25,10 +26,17 @@ extern crate serde_test;
 #[cfg(feature="default_impl_cpupool")]
 extern crate futures_cpupool;
 
+#[cfg(feature="default_impl_tokio")]
+extern crate tokio;
+#[cfg(feature="default_impl_tokio")]
+extern crate futures03;
+
+#[cfg(feature="charset-transcode")]
+extern crate encoding_rs;
+
 extern crate mail_internals as common;
-#[cfg_attr(test, macro_use)]
+#[macro_use]
 extern crate mail_headers as headers;
-extern crate checked_command;
 
 
 #[macro_use]
@@ -42,6 +50,7 @@ mod resource;
 mod encode;
 mod mail;
 pub mod compose;
+pub mod additional_headers;
 
 pub mod default_impl;
 
@@ -3,12 +3,21 @@
 
 
 use std::{
+    borrow::Cow,
     ops::Deref,
     fmt,
-    mem
+    mem,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    hash::{Hash, Hasher},
+    collections::{HashMap, hash_map::DefaultHasher}
 };
 
 use soft_ascii_string::SoftAsciiString;
+use chrono::{self, Utc, TimeZone};
+use md5;
 use futures::{
     future::{
         self,
@@ -18,24 +27,43 @@ use futures::{
     Async,
     Poll
 };
-use media_type::BOUNDARY;
+use media_type::{BOUNDARY, TEXT};
+
+use vec1::Vec1;
 
 use internals::{
     MailType,
-    encoder::EncodingBuffer
+    bind::base64,
+    encoder::EncodingBuffer,
+    error::{EncodingError, EncodingErrorKind, US_ASCII}
 };
 use headers::{
     Header, HeaderKind,
-    HeaderMap,
+    HeaderMap, HeaderName, HeaderObj,
     headers::{
-        ContentType, _From,
+        ContentType, _From, _To,
+        Cc, Bcc,
+        InReplyTo, References, ReplyTo,
+        Comments,
         ContentTransferEncoding,
         Date, MessageId,
-        ContentDisposition
+        ContentDisposition, ContentId,
+        Sender, Subject
     },
     header_components::{
         DateTime,
-        MediaType
+        MediaType,
+        Domain,
+        Mailbox,
+        MailboxList,
+        OptMailboxList,
+        Email,
+        MessageId as MessageIdComponent,
+        MessageIdList,
+        Disposition,
+        DispositionKind,
+        FileMeta,
+        TransferEncoding
     },
     error::{
         HeaderValidationError,
@@ -43,8 +71,10 @@ use headers::{
 };
 
 use ::{
-    utils::SendBoxFuture,
-    mime::create_structured_random_boundary,
+    iri::IRI,
+    utils::{self, SendBoxFuture},
+    mime::is_valid_boundary,
+    additional_headers,
     error::{
         MailError,
         OtherValidationError,
@@ -78,19 +108,15 @@ use ::{
 /// # use mail_internals::MailType;
 /// use std::str;
 /// // either from `mail::headers` or from `mail_header as headers`
-/// use headers::{
-///     headers::*,
-///     header_components::Domain
-/// };
+/// use headers::headers::*;
 /// use mail_core::{
 ///     Mail, Resource,
+///     utils::parse_domain,
 ///     default_impl::simple_context
 /// };
 ///
 /// # fn main() {
-/// // Domain will implement `from_str` in the future,
-/// // currently it doesn't have a validator/parser.
-/// let domain = Domain::from_unchecked("example.com".to_owned());
+/// let domain = parse_domain("example.com").unwrap();
 /// // Normally you create this _once per application_.
 /// let ctx = simple_context::new(domain, "xqi93".parse().unwrap())
 ///     .unwrap();
@@ -177,6 +203,8 @@ use ::{
 pub struct Mail {
     headers: HeaderMap,
     body: MailBody,
+    /// message id used if the mail has no `Message-Id` header (see `ensure_message_id`)
+    default_message_id: Option<MessageIdComponent>
 }
 
 /// A type which either represents a single body, or multiple modies.
@@ -185,7 +213,7 @@ pub struct Mail {
 /// single body _and_ it being semantically important to be this way,
 /// so we have to differ between both kinds (instead of just having
 /// a `Vec` of mails)
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum MailBody {
     SingleBody {
         body: Resource
@@ -193,15 +221,68 @@ pub enum MailBody {
     MultipleBodies {
         //TODO[now]: use Vec1
         bodies: Vec<Mail>,
-        /// This is part of the standard! But we won't
-        /// make it public available for now. Through
-        /// there is a chance that we need to do so
-        /// in the future as some mechanisms might
-        /// misuse this, well unusual think.
+        /// The preamble of the multipart body.
+        ///
+        /// It's written before the first boundary and ignored by
+        /// MIME aware clients, so it can be used as a fallback message
+        /// for non MIME aware clients (see `Mail::set_hidden_text`).
         hidden_text: SoftAsciiString
     }
 }
 
+/// Returns the number of headers for each header name in the map.
+fn header_counts(headers: &HeaderMap) -> HashMap<HeaderName, usize> {
+    let mut counts = HashMap::new();
+    for (name, _) in headers.iter() {
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The headers lost when inserting headers, see `Mail::insert_headers_reporting`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct InsertHeadersReport {
+    /// Names of headers already set on the mail which were replaced.
+    pub replaced: Vec<HeaderName>,
+
+    /// Names of inserted headers which were dropped, i.e. did not end up in the mail.
+    pub dropped: Vec<HeaderName>
+}
+
+/// Compares the headers and bodies of the mails.
+///
+/// This is mainly meant for testing. Header components do not share a
+/// common equality trait so headers are compared by their name and their
+/// encoded value (in order), headers which can not be encoded are never
+/// equal. Bodies are compared using the `PartialEq` implementation of
+/// `Resource`, i.e. by media type and data, or by `Source` for not
+/// loaded resources.
+impl PartialEq for Mail {
+    fn eq(&self, other: &Mail) -> bool {
+        self.headers.len() == other.headers.len()
+            && self.headers.iter().zip(other.headers.iter())
+                .all(|((lname, lbody), (rname, rbody))| {
+                    if lname != rname {
+                        return false;
+                    }
+                    match (encode_header_value(lname, lbody), encode_header_value(rname, rbody)) {
+                        (Ok(left), Ok(right)) => left == right,
+                        _ => false
+                    }
+                })
+            && self.body == other.body
+    }
+}
+
+fn encode_header_value(name: HeaderName, header: &HeaderObj) -> Result<Vec<u8>, EncodingError> {
+    let mut buffer = EncodingBuffer::new(MailType::Internationalized);
+    {
+        let mut handle = buffer.writer();
+        ::encode::encode_header(&mut handle, name, header)?;
+    }
+    Ok(buffer.into())
+}
+
 impl Mail {
 
     /// Create a new plain text mail.
@@ -219,6 +300,36 @@ impl Mail {
         Mail::new_singlepart_mail(resource)
     }
 
+    /// Create a new html mail.
+    ///
+    /// This works like `plain_text` except that the media type
+    /// is `text/html; charset=utf-8`. Like with any other body
+    /// the transfer encoding is chosen when the mail is turned
+    /// into an encodable mail.
+    pub fn html_text(html: impl Into<String>, ctx: &impl Context) -> Self {
+        let resource = Resource::html_text(html.into(), ctx);
+        Mail::new_singlepart_mail(resource)
+    }
+
+    /// Create a new non-multipart mail from bytes with given media type.
+    ///
+    /// This is a shortcut for creating a `Resource::Data` from the bytes
+    /// (with a new ContentId generated using the context) and passing it
+    /// to `new_singlepart_mail`. If the resource should be shared or
+    /// loaded from a `Source` use `new_singlepart_mail` directly.
+    pub fn singlepart_from_bytes(
+        media_type: MediaType,
+        data: Vec<u8>,
+        ctx: &impl Context
+    ) -> Self {
+        let resource = Resource::Data(Data::new(data, Metadata {
+            file_meta: Default::default(),
+            media_type,
+            content_id: ctx.generate_content_id()
+        }));
+        Mail::new_singlepart_mail(resource)
+    }
+
     /// Returns true if the body of the mail is a multipart body.
     pub fn has_multipart_body(&self) -> bool {
         self.body.is_multipart()
@@ -228,26 +339,33 @@ impl Mail {
     ///
     /// Note that while the given `content_type` has to be a `multipart` content
     /// type (when encoding the mail) it is not required nor expected to have the
-    /// boundary parameter. The boundary will always be automatically generated
-    /// independently of wether or not it was passed as media type.
+    /// boundary parameter. If there is no boundary parameter the boundary will be
+    /// automatically generated, if there is one it's kept (see `set_boundary`).
     pub fn new_multipart_mail(content_type: MediaType, bodies: Vec<Mail>) -> Self {
         let mut headers = HeaderMap::new();
         headers.insert(ContentType::body(content_type));
         Mail {
             headers,
-            body: MailBody::MultipleBodies {
-                bodies,
-                hidden_text: SoftAsciiString::new()
-            }
+            body: MailBody::multiple(bodies),
+            default_message_id: None
         }
     }
 
     /// Create a new non-multipart mail for given `Resource` as body.
+    ///
+    /// If the resource is a `Source` with a `use_disposition` a
+    /// `Content-Disposition` header with that disposition kind
+    /// is inserted.
     pub fn new_singlepart_mail(body: Resource) -> Self {
-        let headers = HeaderMap::new();
+        let mut headers = HeaderMap::new();
+        if let Resource::Source(Source { use_disposition: Some(kind), .. }) = body {
+            headers.insert(ContentDisposition::body(
+                Disposition::new(kind, FileMeta::default())));
+        }
         Mail {
             headers,
-            body: MailBody::SingleBody { body }
+            body: MailBody::single(body),
+            default_message_id: None
         }
     }
 
@@ -272,6 +390,410 @@ impl Mail {
         self.headers_mut().insert_all(headers);
     }
 
+    /// Inserts all headers, reporting which headers got lost in the process.
+    ///
+    /// This works like `insert_headers`, but reports the names of headers
+    /// which were replaced or dropped due to the "max one" behavior (of
+    /// e.g. `Subject` or `From`). A name is reported as `replaced` if a
+    /// header already set on the mail was replaced and as `dropped` if not
+    /// all inserted headers with that name ended up in the mail. This e.g.
+    /// allows detecting if a `Subject` was accidentally set twice. Each name
+    /// is only reported once per set, in the order the names first appear in
+    /// the inserted headers.
+    pub fn insert_headers_reporting(&mut self, headers: HeaderMap) -> InsertHeadersReport {
+        let mut names = Vec::new();
+        let mut added = HashMap::new();
+        for (name, _) in headers.iter() {
+            let count = added.entry(name).or_insert(0);
+            if *count == 0 {
+                names.push(name);
+            }
+            *count += 1;
+        }
+        let existing = header_counts(&self.headers);
+
+        self.insert_headers(headers);
+
+        let after = header_counts(&self.headers);
+        let mut report = InsertHeadersReport::default();
+        for name in names {
+            let existing = existing.get(&name).cloned().unwrap_or(0);
+            let expected = existing + added[&name];
+            let lost = expected - after.get(&name).cloned().unwrap_or(0).min(expected);
+            if lost > 0 && existing > 0 {
+                report.replaced.push(name);
+            }
+            if lost > existing {
+                report.dropped.push(name);
+            }
+        }
+        report
+    }
+
+    /// Inserts headers given as raw name/value pairs.
+    ///
+    /// This is meant for interop with systems handing over headers as plain
+    /// strings (e.g. HTTP like interfaces). Each pair is turned into the typed
+    /// header with the same name (compared case-insensitive), only following
+    /// headers are supported:
+    ///
+    /// - `Subject` and `Comments`, the value is used as unstructured text
+    /// - `From`, `To`, `Cc`, `Bcc` and `Reply-To`, the value is a `','`
+    ///   separated list of plain addresses (e.g. `"a@example.com, b@example.com"`)
+    /// - `Sender`, the value is a single plain address
+    ///
+    /// Display names (`"Name <a@example.com>"`) are not supported. Other
+    /// header names can't be used, as `HeaderMap` only stores typed headers
+    /// (there is no opaque header kind) and a header can't be validated or
+    /// encoded without knowing its type.
+    ///
+    /// # Error
+    ///
+    /// Fails with `OtherValidationError::UnsupportedRawHeader` for any other
+    /// header name and with `OtherValidationError::RawHeaderValueWithLineBreak`
+    /// if a value contains `'\r'`/`'\n'` (header injection). Fails if a value
+    /// can't be used for the header, e.g. an invalid address. If any pair fails
+    /// no header is inserted.
+    pub fn insert_raw_headers<I, N, V>(&mut self, headers: I) -> Result<(), MailError>
+        where I: IntoIterator<Item=(N, V)>, N: AsRef<str>, V: Into<String>
+    {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            let name = name.as_ref();
+            let value = value.into();
+            if value.contains(|ch: char| ch == '\r' || ch == '\n') {
+                return Err(OtherValidationError::RawHeaderValueWithLineBreak {
+                    name: name.to_owned()
+                }.into());
+            }
+
+            match &*name.to_ascii_lowercase() {
+                "subject" => map.insert(Subject::auto_body(value)?),
+                "comments" => map.insert(Comments::auto_body(value)?),
+                "from" => map.insert(_From::body(raw_mailbox_list(&value)?)),
+                "to" => map.insert(_To::body(raw_mailbox_list(&value)?)),
+                "cc" => map.insert(Cc::body(raw_mailbox_list(&value)?)),
+                "bcc" => {
+                    let mailboxes = raw_mailbox_list(&value)?.0.into_vec();
+                    map.insert(Bcc::body(OptMailboxList(mailboxes)))
+                },
+                "reply-to" => map.insert(ReplyTo::body(raw_mailbox_list(&value)?)),
+                "sender" => map.insert(Sender::body(Mailbox::from(Email::new(value.trim())?))),
+                _ => return Err(OtherValidationError::UnsupportedRawHeader {
+                    name: name.to_owned()
+                }.into())
+            };
+        }
+        self.insert_headers(map);
+        Ok(())
+    }
+
+    /// Sets the `Auto-Submitted` header (rfc3834).
+    ///
+    /// Mails which are automatically generated (e.g. notifications)
+    /// or are automatic responses should have this header to prevent
+    /// mail loops between automatic systems.
+    ///
+    /// As `Auto-Submitted` is a "max one" header this will replace any
+    /// previously set `Auto-Submitted` header.
+    pub fn set_auto_submitted(&mut self, kind: AutoSubmitted) {
+        let header = additional_headers::AutoSubmitted::auto_body(kind.as_str())
+            .expect("[BUG] auto submitted values are valid unstructured header bodies");
+        self.insert_header(header);
+    }
+
+    /// Sets the priority of the mail.
+    ///
+    /// There is no single standard header for the priority of a mail,
+    /// so this sets all headers commonly used by mail clients to the
+    /// same priority:
+    ///
+    /// - `X-Priority` (`1` high, `3` normal, `5` low)
+    /// - `Importance` (`high`, `normal`, `low`)
+    /// - `X-MSMail-Priority` (`High`, `Normal`, `Low`) used by Outlook
+    ///
+    /// Any previously set values of this headers are replaced.
+    pub fn set_priority(&mut self, priority: Priority) {
+        let err_msg = "[BUG] priority values are valid unstructured header bodies";
+        self.insert_header(additional_headers::XPriority::auto_body(priority.x_priority())
+            .expect(err_msg));
+        self.insert_header(additional_headers::Importance::auto_body(priority.importance())
+            .expect(err_msg));
+        self.insert_header(additional_headers::XMsMailPriority::auto_body(priority.ms_mail_priority())
+            .expect(err_msg));
+    }
+
+    /// Sets the `Content-Location` header (rfc2557).
+    ///
+    /// This associates the body with the given IRI, which e.g. allows
+    /// a html body to refer to embedded resources by a (relative) url.
+    /// As it is a `Content-` header it can be used in the bodies of
+    /// multipart mails.
+    pub fn set_content_location(&mut self, iri: &IRI) -> Result<(), MailError> {
+        let header = additional_headers::ContentLocation::auto_body(iri.as_str())?;
+        self.insert_header(header);
+        Ok(())
+    }
+
+    /// Sets the `Content-Duration` header (rfc3803) to given number of seconds.
+    pub fn set_content_duration(&mut self, seconds: u64) {
+        let header = additional_headers::ContentDuration::auto_body(seconds.to_string())
+            .expect("[BUG] numbers are valid unstructured header bodies");
+        self.insert_header(header);
+    }
+
+    /// Returns the domain of the first mailbox in the `From` header.
+    ///
+    /// Returns `None` if there is no `From` header (or the header
+    /// has a different type then the `_From` header).
+    pub fn from_domain(&self) -> Option<&Domain> {
+        match self.headers.get_single(_From) {
+            Some(Ok(from)) => Some(&from.0.first().email.domain),
+            _ => None
+        }
+    }
+
+    /// Returns the mailboxes of the `From` header.
+    ///
+    /// This reads the headers of the mail, i.e. it returns `None` if
+    /// there is no `From` header (or the header has a different type
+    /// then the `_From` header).
+    pub fn from_addresses(&self) -> Option<&MailboxList> {
+        match self.headers.get_single(_From) {
+            Some(Ok(from)) => Some(from.body()),
+            _ => None
+        }
+    }
+
+    /// Returns the mailboxes of the `To` header.
+    ///
+    /// This reads the headers of the mail, i.e. it returns `None` if
+    /// there is no `To` header (or the header has a different type
+    /// then the `_To` header).
+    pub fn to_addresses(&self) -> Option<&MailboxList> {
+        match self.headers.get_single(_To) {
+            Some(Ok(to)) => Some(to.body()),
+            _ => None
+        }
+    }
+
+    /// Returns the mailboxes of the `Reply-To` header.
+    ///
+    /// This reads the headers of the mail, i.e. it returns `None` if
+    /// there is no `Reply-To` header (or the header has a different type
+    /// then the `ReplyTo` header).
+    pub fn reply_to(&self) -> Option<&MailboxList> {
+        match self.headers.get_single(ReplyTo) {
+            Some(Ok(reply_to)) => Some(reply_to.body()),
+            _ => None
+        }
+    }
+
+    /// Sets the `Reply-To` header (replacing any existing `Reply-To` header).
+    ///
+    /// # Error
+    ///
+    /// Fails with `OtherValidationError::EmptyReplyTo` if no mailbox is
+    /// given, as the `Reply-To` header has to contain at least one mailbox.
+    pub fn set_reply_to<I>(&mut self, mailboxes: I) -> Result<(), MailError>
+        where I: IntoIterator<Item=Mailbox>
+    {
+        let mailboxes = Vec1::from_vec(mailboxes.into_iter().collect())
+            .map_err(|_| OtherValidationError::EmptyReplyTo)?;
+        self.insert_header(ReplyTo::body(MailboxList(mailboxes)));
+        Ok(())
+    }
+
+    /// Inserts a generated `Message-Id` header if there is no `Message-Id` header.
+    ///
+    /// This uses the same logic as the auto-generation of the header when
+    /// turning the mail into an encodable mail, i.e. the id is generated
+    /// for the domain of the first `From` mailbox if there is a `From`
+    /// header (see `Context::generate_message_id_for`). This allows setting
+    /// the message id before encoding, e.g. to store it for later reference.
+    ///
+    /// If the mail was created by `MailParts::compose_mail` the message id
+    /// of the id scope used to generate the content ids is inserted instead.
+    pub fn ensure_message_id(&mut self, ctx: &impl Context) {
+        if self.headers.contains(MessageId) {
+            return;
+        }
+
+        let message_id = match (self.default_message_id.take(), self.from_domain()) {
+            (Some(message_id), _) => message_id,
+            (None, Some(domain)) => ctx.generate_message_id_for(domain),
+            (None, None) => ctx.generate_message_id()
+        };
+        self.headers.insert(MessageId::body(message_id));
+    }
+
+    /// Sets the message id used by `ensure_message_id` if there is no `Message-Id` header.
+    pub(crate) fn set_default_message_id(&mut self, message_id: MessageIdComponent) {
+        self.default_message_id = Some(message_id);
+    }
+
+    /// Sets the `In-Reply-To` and `References` headers for replying to a mail.
+    ///
+    /// `parent` is the message id of the mail replied to and `references`
+    /// the content of its `References` header (empty if it has none). As
+    /// described in rfc5322 the `In-Reply-To` header is set to the parent
+    /// and the `References` header to the references followed by the
+    /// parent. Existing `In-Reply-To`/`References` headers are replaced.
+    pub fn set_reply_to_thread(
+        &mut self,
+        parent: &MessageIdComponent,
+        references: &[MessageIdComponent]
+    ) {
+        let mut all_references = references.to_vec();
+        all_references.push(parent.clone());
+        //UNWRAP_SAFE: contains at least the parent
+        let all_references = Vec1::from_vec(all_references).unwrap();
+
+        self.headers.insert(InReplyTo::body(MessageIdList(Vec1::new(parent.clone()))));
+        self.headers.insert(References::body(MessageIdList(all_references)));
+    }
+
+    /// Returns the text of the `Subject` header.
+    ///
+    /// Returns `None` if there is no `Subject` header (or the header
+    /// has a different type then the `Subject` header).
+    pub fn subject(&self) -> Option<&str> {
+        match self.headers.get_single(Subject) {
+            Some(Ok(subject)) => Some(subject.as_str()),
+            _ => None
+        }
+    }
+
+    /// Sets the `Subject` header (replacing any existing `Subject` header).
+    ///
+    /// # Error
+    ///
+    /// Fails if the text can not be used as `Subject`.
+    pub fn set_subject(&mut self, subject: impl Into<String>) -> Result<(), MailError> {
+        let header = Subject::auto_body(subject.into())?;
+        self.insert_header(header);
+        Ok(())
+    }
+
+    /// Sets the `Date` header (replacing any existing `Date` header).
+    ///
+    /// A date set this way will not be overridden by the auto-generated
+    /// `Date` header, which e.g. is useful for reproducible mails or for
+    /// mails which are queued before being send. Unlike `set_date_checked`
+    /// this doesn't check if the date is within sane bounds.
+    pub fn set_date(&mut self, date: DateTime) {
+        self.headers.insert(Date::body(date));
+    }
+
+    /// Sets the `Date` header after checking it's within the default `DateBounds`.
+    ///
+    /// This is meant for cases where the date is not just "now", e.g. mails
+    /// generated from historical data. A date set this way will not be
+    /// overridden by the auto-generated `Date` header.
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the date is out of bounds, in which case
+    /// no `Date` header is set.
+    pub fn set_date_checked(&mut self, date: DateTime) -> Result<(), MailError> {
+        self.set_date_checked_with_bounds(date, &Default::default())
+    }
+
+    /// Like `set_date_checked` but with custom `DateBounds`.
+    pub fn set_date_checked_with_bounds(&mut self, date: DateTime, bounds: &DateBounds)
+        -> Result<(), MailError>
+    {
+        if !bounds.contains(&date) {
+            return Err(OtherValidationError::DateOutOfBounds.into());
+        }
+        self.headers.insert(Date::body(date));
+        Ok(())
+    }
+
+    /// Sets the boundary of a multipart mail.
+    ///
+    /// Normally the boundary is generated automatically when turning the
+    /// mail into a encodable mail, but a preset boundary is kept which
+    /// e.g. allows creating reproducible mails for snapshot tests.
+    ///
+    /// **Be aware that it's the callers responsibility to make sure the
+    /// boundary does not appear in any of the (sub-)bodies.**
+    ///
+    /// # Error
+    ///
+    /// Fails if the boundary is not valid wrt. rfc2046 or if the mail
+    /// does not have a multipart body (with a `Content-Type` header).
+    pub fn set_boundary(&mut self, boundary: SoftAsciiString) -> Result<(), MailError> {
+        if !is_valid_boundary(boundary.as_str()) {
+            return Err(OtherValidationError::InvalidBoundary {
+                boundary: boundary.into()
+            }.into());
+        }
+        if !self.has_multipart_body() {
+            return Err(OtherValidationError::SingleMultipartMixup.into());
+        }
+        match self.headers.get_single_mut(ContentType) {
+            Some(content_type) => {
+                content_type?.set_param(BOUNDARY, boundary.as_str());
+                Ok(())
+            },
+            None => Err(OtherValidationError::MissingContentTypeHeader.into())
+        }
+    }
+
+    /// Sets the "hidden text" (preamble) of a multipart mail.
+    ///
+    /// The text is written between the headers and the first boundary and
+    /// is ignored by MIME aware clients, e.g. it can be used to tell users
+    /// of non MIME aware clients that the mail is a MIME mail. Line breaks
+    /// have to be `"\r\n"`.
+    ///
+    /// # Error
+    ///
+    /// Fails if the mail does not have a multipart body. Encoding the mail
+    /// fails with `OtherValidationError::InvalidHiddenText` if the text
+    /// contains the boundary delimiter (`"--<boundary>"`) or orphan
+    /// `'\r'`/`'\n'`.
+    pub fn set_hidden_text(&mut self, text: SoftAsciiString) -> Result<(), MailError> {
+        match self.body {
+            MailBody::MultipleBodies { ref mut hidden_text, .. } => {
+                *hidden_text = text;
+                Ok(())
+            },
+            MailBody::SingleBody { .. } =>
+                Err(OtherValidationError::SingleMultipartMixup.into())
+        }
+    }
+
+    /// Marks the (singlepart) mail as attachment.
+    ///
+    /// This inserts a `Content-Disposition` header of kind `attachment`
+    /// (replacing any existing `Content-Disposition` header). If `file_name`
+    /// is `None` the file name of the resource is used, i.e. the name in the
+    /// `FileMeta` of already loaded resources or the `use_file_name` of a
+    /// `Source`. If neither is known the file name is filled in once the
+    /// resource is loaded (when creating the encodable mail), like all other
+    /// empty fields of the disposition's `FileMeta`.
+    ///
+    /// Non us-ascii file names are fine, they are encoded as defined by
+    /// rfc2231 when encoding the header.
+    ///
+    /// # Error
+    ///
+    /// Fails if the mail has a multipart body.
+    pub fn set_attachment(&mut self, file_name: Option<String>) -> Result<(), MailError> {
+        let file_name = match self.body {
+            MailBody::SingleBody { ref body } => file_name.or_else(|| resource_file_name(body)),
+            MailBody::MultipleBodies { .. } =>
+                return Err(OtherValidationError::SingleMultipartMixup.into())
+        };
+
+        let file_meta = FileMeta { file_name, ..Default::default() };
+        self.headers.insert(ContentDisposition::body(
+            Disposition::new(DispositionKind::Attachment, file_meta)));
+        Ok(())
+    }
+
     /// Returns a reference to the currently set headers.
     ///
     /// Note that some headers namely `Content-Transfer-Encoding` as well
@@ -294,6 +816,16 @@ impl Mail {
     }
 
     /// Return a mutable reference to the body/bodies.
+    ///
+    /// This can be used to e.g. push additional sub-bodies into
+    /// a multipart body or to replace a resource.
+    ///
+    /// Note that changes done through this reference are not
+    /// validated, validation only happens when the mail is turned
+    /// into an encodable mail (see `into_encodable_mail`). E.g.
+    /// replacing a multipart body with a singlepart body without
+    /// removing the `Content-Type` header will make the mail fail
+    /// validation at that point.
     pub fn body_mut(&mut self) -> &mut MailBody {
         &mut self.body
     }
@@ -351,6 +883,9 @@ impl Mail {
     ///      replacing the old resource instances with the new loaded and
     ///      encoded ones once all of them had been loaded (and encoded)
     ///      successfully.
+    ///    - With the `charset-transcode` feature `text/*` bodies with a
+    ///      charset other than utf-8/us-ascii are transcoded to utf-8
+    ///      (see `Data::transcode_to_utf8`).
     ///
     /// 3. Insert all auto generated headers (like e.g. `Date`).
     ///
@@ -363,7 +898,134 @@ impl Mail {
     /// are never loaded from disk.
     ///
     pub fn into_encodable_mail<C: Context>(self, ctx: C) -> MailFuture<C> {
-        MailFuture::new(self, ctx)
+        self.into_encodable_mail_with_options(ctx, Default::default())
+    }
+
+    /// Like `into_encodable_mail` but allows passing in `EncodeOptions`.
+    ///
+    /// The options are kept in the resulting `EncodableMail` and
+    /// are also used when encoding it.
+    pub fn into_encodable_mail_with_options<C: Context>(
+        self,
+        ctx: C,
+        options: EncodeOptions
+    ) -> MailFuture<C> {
+        MailFuture::new(self, ctx, options)
+    }
+
+    /// Returns the header structure of the mail without any of the bodies data.
+    ///
+    /// The skeleton contains a copy of the header map of every (sub-)mail
+    /// as well as the kind of the body, for singlepart bodies the media
+    /// type is included if it is already known (i.e. the resource is loaded
+    /// or a media type was explicitly given in the `Source`). The resources
+    /// themself are not included at all, making it cheaper to create then
+    /// a clone of the mail (e.g. for logging).
+    pub fn header_skeleton(&self) -> HeaderSkeleton {
+        use self::MailBody::*;
+        let body = match self.body {
+            SingleBody { ref body } => {
+                SkeletonBody::Single { media_type: body.declared_media_type().cloned() }
+            },
+            MultipleBodies { ref bodies, .. } => {
+                let bodies = bodies.iter()
+                    .map(|mail| mail.header_skeleton())
+                    .collect();
+                SkeletonBody::Multiple { bodies }
+            }
+        };
+
+        HeaderSkeleton {
+            headers: self.headers.clone(),
+            body
+        }
+    }
+
+    /// Like `into_encodable_mail` but can tolerate some resources failing to load.
+    ///
+    /// Which failures are tolerated depends on the `FailurePolicy`:
+    ///
+    /// - `FailurePolicy::FailOnAny` behaves exactly like `into_encodable_mail`
+    /// - `FailurePolicy::SkipInline` will remove any singlepart sub-body with
+    ///   a `Content-Disposition` of `inline` (e.g. embedded images) if it's
+    ///   resource fails to load or transfer encode. Failures of any other
+    ///   body (e.g. attachments, the text bodies or the top level body) still
+    ///   make the future fail, as does e.g. failing validation.
+    ///
+    /// If any body was skipped `cid:` references to it's `Content-ID` are removed
+    /// from all `text/html` bodies (singlepart bodies without a `Content-Disposition`
+    /// header), e.g. a `<img src="cid:logo@example.com">` element is removed. Only
+    /// bodies with a known `text/html` media type are changed, i.e. loaded data or
+    /// `Source`s which declare the media type. Multipart bodies are kept even if
+    /// all their sub-bodies were skipped.
+    pub fn into_encodable_mail_lenient<C: Context>(self, ctx: C, policy: FailurePolicy)
+        -> SendBoxFuture<EncodableMail, MailError>
+    {
+        match policy {
+            FailurePolicy::FailOnAny => Box::new(self.into_encodable_mail(ctx)),
+            FailurePolicy::SkipInline => {
+                let mut this = self;
+                let default_message_id = this.default_message_id.take();
+                let ctx2 = ctx.clone();
+                let fut = load_inline_bodies_lenient(this, true, &ctx)
+                    .and_then(move |(mail, skipped_content_ids)| -> SendBoxFuture<Mail, MailError> {
+                        let mail = mail.expect("[BUG] the top level mail is never skipped");
+                        if skipped_content_ids.is_empty() {
+                            Box::new(future::ok(mail))
+                        } else {
+                            remove_cid_references(mail, Arc::new(skipped_content_ids), &ctx2)
+                        }
+                    })
+                    .and_then(move |mut mail| {
+                        mail.default_message_id = default_message_id;
+                        mail.into_encodable_mail(ctx)
+                    });
+                Box::new(fut)
+            }
+        }
+    }
+
+    /// Removes duplicated attachments from the mail.
+    ///
+    /// An attachment is a singlepart sub-body with a `Content-Disposition`
+    /// header of kind `attachment`. Two attachments are considered the same
+    /// if they have the same file name and the same content, i.e. the same
+    /// data, the same transfer encoded data or (if not loaded) the same IRI.
+    /// For each set of same attachments only the first one (in visiting
+    /// order) is kept.
+    ///
+    /// To not loose any information attachments with any other headers
+    /// but the `Content-Disposition` header are never removed.
+    pub fn dedup_attachments(&mut self) {
+        let mut seen = Vec::new();
+        dedup_attachments(self, &mut seen);
+    }
+
+    /// Returns a iterator over all resources of the mail.
+    ///
+    /// The resources are returned depth first in the same order
+    /// in which they appear in the encoded mail, without skipping
+    /// any singlepart body.
+    pub fn resources(&self) -> impl Iterator<Item=&Resource> {
+        let mut resources = Vec::new();
+        collect_resources(self, &mut resources);
+        resources.into_iter()
+    }
+
+    /// Returns the number of singlepart bodies with a `attachment` content disposition.
+    pub fn attachments_count(&self) -> usize {
+        use self::MailBody::*;
+        match self.body {
+            SingleBody { .. } => {
+                match self.headers.get_single(ContentDisposition) {
+                    Some(Ok(disposition)) if disposition.kind() == DispositionKind::Attachment => 1,
+                    _ => 0
+                }
+            },
+            MultipleBodies { ref bodies, .. } => {
+                bodies.iter().map(|body| body.attachments_count()).sum()
+            }
+        }
     }
 
     /// Visit all mail bodies, the visiting order is deterministic.
@@ -408,98 +1070,647 @@ impl Mail {
     }
 }
 
+/// Loads all inline singlepart bodies, skipping the ones which failed.
+///
+/// Resolves to the mail (`None` if it was skipped) and the
+/// content ids of all skipped bodies.
+fn load_inline_bodies_lenient<C: Context>(mail: Mail, is_top: bool, ctx: &C)
+    -> SendBoxFuture<(Option<Mail>, Vec<String>), MailError>
+{
+    let Mail { headers, body, .. } = mail;
+    match body {
+        MailBody::SingleBody { body } => {
+            if is_top || !is_inline(&headers) {
+                let mail = Mail { headers, body: MailBody::SingleBody { body }, default_message_id: None };
+                return Box::new(future::ok((Some(mail), Vec::new())));
+            }
 
-impl MailBody {
+            let loading = match body {
+                Resource::Source(ref source) => load_source(source, ctx),
+                Resource::Data(ref data) => ctx.transfer_encode_resource(data),
+                Resource::EncData(_) => {
+                    let mail = Mail { headers, body: MailBody::SingleBody { body }, default_message_id: None };
+                    return Box::new(future::ok((Some(mail), Vec::new())));
+                }
+            };
 
-    /// Returns `true` if it's an multipart body.
-    pub fn is_multipart(&self) -> bool {
-        use self::MailBody::*;
-        match *self {
-            SingleBody { .. } => false,
-            MultipleBodies { .. } => true
+            let content_id = headers.get_single(ContentId)
+                .and_then(|header| header.ok())
+                .map(|header| header.as_str().to_owned());
+
+            let fut = loading.then(move |result| match result {
+                Ok(enc_data) => {
+                    let body = MailBody::SingleBody { body: Resource::EncData(enc_data) };
+                    Ok((Some(Mail { headers, body, default_message_id: None }), Vec::new()))
+                },
+                Err(err) => {
+                    warn!("skipping inline body which failed to load: {}", err);
+                    Ok((None, content_id.into_iter().collect()))
+                }
+            });
+            Box::new(fut)
+        },
+        MailBody::MultipleBodies { bodies, hidden_text } => {
+            let pending = bodies.into_iter()
+                .map(|body| load_inline_bodies_lenient(body, false, ctx))
+                .collect::<Vec<_>>();
+
+            let fut = future::join_all(pending)
+                .map(move |results| {
+                    let mut skipped_content_ids = Vec::new();
+                    let bodies = results.into_iter()
+                        .filter_map(|(body, mut skipped)| {
+                            skipped_content_ids.append(&mut skipped);
+                            body
+                        })
+                        .collect();
+                    let mail = Mail { headers, body: MailBody::MultipleBodies { bodies, hidden_text }, default_message_id: None };
+                    (Some(mail), skipped_content_ids)
+                });
+            Box::new(fut)
         }
     }
 }
 
-/// A future resolving to an encodable mail.
-pub struct MailFuture<C: Context> {
-    inner: InnerMailFuture<C>
+/// Removes `cid:` references to the given content ids from all `text/html` bodies.
+///
+/// Only singlepart bodies without a `Content-Disposition` header whose media
+/// type is known to be `text/html` are considered. I.e. `Data`/`EncData`
+/// resources with a `text/html` media type and `Source` resources which
+/// declare a `text/html` media type, only the latter are loaded (which they
+/// would be anyway when the mail is encoded). Changed bodies are replaced
+/// with a `Resource::Data` instance which is transfer encoded when the mail
+/// is turned into an encodable mail.
+fn remove_cid_references<C: Context>(mail: Mail, content_ids: Arc<Vec<String>>, ctx: &C)
+    -> SendBoxFuture<Mail, MailError>
+{
+    let Mail { headers, body, .. } = mail;
+    match body {
+        MailBody::SingleBody { body } => {
+            let is_html = body.declared_media_type().map(is_html).unwrap_or(false);
+            if headers.contains(ContentDisposition) || !is_html {
+                let mail = Mail { headers, body: MailBody::SingleBody { body }, default_message_id: None };
+                return Box::new(future::ok(mail));
+            }
+
+            let loading: SendBoxFuture<Resource, MailError> = match body {
+                Resource::Source(ref source) => {
+                    Box::new(load_source(source, ctx).map(Resource::EncData).map_err(MailError::from))
+                },
+                body => Box::new(future::ok(body))
+            };
+
+            let fut = loading
+                .and_then(move |body| remove_cid_references_from_html(body, &content_ids))
+                .map(move |body| {
+                    Mail { headers, body: MailBody::SingleBody { body }, default_message_id: None }
+                });
+            Box::new(fut)
+        },
+        MailBody::MultipleBodies { bodies, hidden_text } => {
+            let pending = bodies.into_iter()
+                .map(|body| remove_cid_references(body, content_ids.clone(), ctx))
+                .collect::<Vec<_>>();
+
+            let fut = future::join_all(pending)
+                .map(move |bodies| {
+                    Mail { headers, body: MailBody::MultipleBodies { bodies, hidden_text }, default_message_id: None }
+                });
+            Box::new(fut)
+        }
+    }
 }
 
-enum InnerMailFuture<C: Context> {
-    New { mail: Mail, ctx: C },
-    Loading {
-        mail: Mail,
-        pending: future::JoinAll<Vec<Either<
-            SendBoxFuture<EncData, ResourceLoadingError>,
-            future::FutureResult<EncData, ResourceLoadingError>
-        >>>,
-        ctx: C
-    },
-    Poison
+fn is_html(media_type: &MediaType) -> bool {
+    media_type.type_() == TEXT && media_type.subtype().as_str() == "html"
 }
 
-impl<C> MailFuture<C>
-    where C: Context
+/// Removes the references from a loaded html body, returning it unchanged if there are none.
+fn remove_cid_references_from_html(body: Resource, content_ids: &[String])
+    -> Result<Resource, MailError>
 {
-    fn new(mail: Mail, ctx: C) -> Self {
-        MailFuture { inner: InnerMailFuture::New { mail, ctx } }
+    let (html, meta) = match body {
+        Resource::Data(ref data) => (data.buffer().to_vec(), data.metadata().clone()),
+        Resource::EncData(ref enc_data) => (enc_data.decoded_buffer()?, enc_data.metadata().clone()),
+        Resource::Source(_) => return Ok(body)
+    };
+
+    let cleaned = content_ids.iter()
+        .fold(html.clone(), |html, content_id| remove_cid_reference(&html, content_id));
+    if cleaned == html {
+        return Ok(body);
     }
+
+    let mut data = Data::new(cleaned, meta);
+    if let Resource::Data(ref old) = body {
+        data = data.with_transfer_encoding_hint(old.transfer_encoding_hint());
+    }
+    Ok(Resource::Data(data))
 }
 
-impl<T> Future for MailFuture<T>
-    where T: Context,
-{
-    type Item = EncodableMail;
-    type Error = MailError;
+/// Removes all `cid:<content_id>` references from the html.
+///
+/// `<img>` elements referring to the content id are removed completely,
+/// any other reference (e.g. `url(cid:...)` in a `style` attribute) is
+/// removed from the html.
+///
+/// A reference has to end with a quote, `')'`, `'>'`, whitespace or the
+/// end of the html so that e.g. `cid:a@b.test` doesn't match `cid:a@b.test2`.
+fn remove_cid_reference(html: &[u8], content_id: &str) -> Vec<u8> {
+    let reference = format!("cid:{}", content_id).into_bytes();
+
+    let mut without_images = Vec::with_capacity(html.len());
+    let mut idx = 0;
+    while idx < html.len() {
+        if is_img_tag_at(&html[idx..]) {
+            let end = html[idx..].iter()
+                .position(|&bch| bch == b'>')
+                .map(|pos| idx + pos + 1)
+                .unwrap_or(html.len());
+            let tag = &html[idx..end];
+            if (0..tag.len()).any(|pos| is_reference_at(&tag[pos..], &reference)) {
+                idx = end;
+                continue;
+            }
+        }
+        without_images.push(html[idx]);
+        idx += 1;
+    }
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        use self::InnerMailFuture::*;
-        loop {
-            let state = mem::replace(&mut self.inner, InnerMailFuture::Poison);
+    let mut out = Vec::with_capacity(without_images.len());
+    let mut idx = 0;
+    while idx < without_images.len() {
+        if is_reference_at(&without_images[idx..], &reference) {
+            idx += reference.len();
+            continue;
+        }
+        out.push(without_images[idx]);
+        idx += 1;
+    }
+    out
+}
+
+fn is_img_tag_at(rest: &[u8]) -> bool {
+    rest.len() > 4
+        && rest[..4].eq_ignore_ascii_case(b"<img")
+        && (rest[4] == b'/' || rest[4] == b'>' || rest[4].is_ascii_whitespace())
+}
+
+fn is_reference_at(rest: &[u8], reference: &[u8]) -> bool {
+    rest.starts_with(reference)
+        && rest.get(reference.len())
+            .map(|&bch| bch == b'"' || bch == b'\'' || bch == b')'
+                || bch == b'>' || bch.is_ascii_whitespace())
+            .unwrap_or(true)
+}
+
+fn collect_resources<'a>(mail: &'a Mail, out: &mut Vec<&'a Resource>) {
+    match mail.body {
+        MailBody::SingleBody { ref body } => out.push(body),
+        MailBody::MultipleBodies { ref bodies, .. } => {
+            for body in bodies {
+                collect_resources(body, out);
+            }
+        }
+    }
+}
+
+fn dedup_attachments(mail: &mut Mail, seen: &mut Vec<AttachmentKey>) {
+    if let MailBody::MultipleBodies { ref mut bodies, .. } = mail.body {
+        let mut idx = 0;
+        while idx < bodies.len() {
+            if let Some(key) = AttachmentKey::new(&bodies[idx]) {
+                if seen.contains(&key) {
+                    bodies.remove(idx);
+                    continue;
+                }
+                seen.push(key);
+            } else {
+                dedup_attachments(&mut bodies[idx], seen);
+            }
+            idx += 1;
+        }
+    }
+}
+
+/// Identifies the content of a attachment, used by `Mail::dedup_attachments`.
+#[derive(PartialEq)]
+struct AttachmentKey {
+    // compared first to speed up the comparison
+    hash: u64,
+    file_name: Option<String>,
+    content: AttachmentContent
+}
+
+#[derive(PartialEq, Hash)]
+enum AttachmentContent {
+    Source(String),
+    Data(Arc<[u8]>),
+    EncData(Arc<[u8]>, String)
+}
+
+impl AttachmentKey {
+
+    /// Returns the key if the mail is an attachment which can be deduplicated.
+    fn new(mail: &Mail) -> Option<Self> {
+        if mail.headers.len() != 1 {
+            return None;
+        }
+
+        let file_name = match mail.headers.get_single(ContentDisposition) {
+            Some(Ok(disposition)) if disposition.kind() == DispositionKind::Attachment =>
+                disposition.file_meta().file_name.clone(),
+            _ => return None
+        };
+
+        let content = match mail.body {
+            MailBody::SingleBody { body: Resource::Source(ref source) } =>
+                AttachmentContent::Source(source.iri.as_str().to_owned()),
+            MailBody::SingleBody { body: Resource::Data(ref data) } =>
+                AttachmentContent::Data(data.buffer().clone()),
+            MailBody::SingleBody { body: Resource::EncData(ref enc_data) } => {
+                let encoding = transfer_encoding_name(enc_data.encoding());
+                AttachmentContent::EncData(
+                    enc_data.transfer_encoded_buffer().clone(), encoding.to_owned())
+            },
+            MailBody::MultipleBodies { .. } => return None
+        };
+
+        let mut hasher = DefaultHasher::new();
+        file_name.hash(&mut hasher);
+        content.hash(&mut hasher);
+
+        Some(AttachmentKey { hash: hasher.finish(), file_name, content })
+    }
+}
+
+fn transfer_encoding_name(encoding: TransferEncoding) -> &'static str {
+    use self::TransferEncoding::*;
+    match encoding {
+        _7Bit => "7bit",
+        _8Bit => "8bit",
+        Binary => "binary",
+        QuotedPrintable => "quoted-printable",
+        Base64 => "base64"
+    }
+}
+
+fn is_inline(headers: &HeaderMap) -> bool {
+    match headers.get_single(ContentDisposition) {
+        Some(Ok(disposition)) => disposition.kind() == DispositionKind::Inline,
+        _ => false
+    }
+}
+
+fn resource_file_name(resource: &Resource) -> Option<String> {
+    match *resource {
+        Resource::Source(ref source) => source.use_file_name.clone(),
+        Resource::Data(ref data) => data.file_meta().file_name.clone(),
+        Resource::EncData(ref enc_data) => enc_data.file_meta().file_name.clone()
+    }
+}
+
+/// The headers of a mail and it's sub-bodies without any of the bodies data.
+///
+/// Created through `Mail::header_skeleton`.
+#[derive(Debug, Clone)]
+pub struct HeaderSkeleton {
+    /// The headers of the (sub-)mail.
+    pub headers: HeaderMap,
+    /// The kind of body the (sub-)mail has.
+    pub body: SkeletonBody
+}
+
+/// The body of a `HeaderSkeleton`.
+#[derive(Debug, Clone)]
+pub enum SkeletonBody {
+    /// A singlepart body, the media type is included if it is known.
+    Single {
+        media_type: Option<MediaType>
+    },
+    /// A multipart body with the skeletons of all sub-bodies.
+    Multiple {
+        bodies: Vec<HeaderSkeleton>
+    }
+}
+
+/// Specifies which resource loading failures `Mail::into_encodable_mail_lenient` tolerates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailurePolicy {
+    /// Fail if any resource fails to load (like `into_encodable_mail`).
+    FailOnAny,
+    /// Skip inline sub-bodies which fail to load, fail on any other failure.
+    SkipInline
+}
+
+/// The range of dates accepted by `Mail::set_date_checked`.
+///
+/// Dates far in the past or future are unlikely to be intended and
+/// tend to trip spam filters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateBounds {
+    /// The earliest accepted date.
+    pub earliest: chrono::DateTime<Utc>,
+    /// How far the date can be in the future (relative to the time of the check).
+    pub max_in_future: chrono::Duration
+}
+
+impl DateBounds {
+
+    /// Returns true if the date is in the bounds.
+    pub fn contains(&self, date: &DateTime) -> bool {
+        let date: &chrono::DateTime<Utc> = &**date;
+        let latest = utils::now() + self.max_in_future;
+        self.earliest <= *date && *date <= latest
+    }
+}
+
+impl Default for DateBounds {
+    /// The default bounds are from the unix epoch to one day in the future.
+    fn default() -> Self {
+        DateBounds {
+            earliest: Utc.timestamp(0, 0),
+            max_in_future: chrono::Duration::days(1)
+        }
+    }
+}
+
+/// The values the `Auto-Submitted` header can have (rfc3834).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AutoSubmitted {
+    /// The mail was not automatically submitted.
+    No,
+
+    /// The mail was automatically generated, e.g. a notification.
+    AutoGenerated,
+
+    /// The mail is an automatic reply to another mail.
+    AutoReplied,
+
+    /// The mail is an automatic notification about another mail.
+    AutoNotified
+}
+
+impl AutoSubmitted {
+
+    /// Returns the value used for the `Auto-Submitted` header.
+    pub fn as_str(&self) -> &'static str {
+        use self::AutoSubmitted::*;
+        match *self {
+            No => "no",
+            AutoGenerated => "auto-generated",
+            AutoReplied => "auto-replied",
+            AutoNotified => "auto-notified"
+        }
+    }
+}
+
+/// The priority of a mail, see `Mail::set_priority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+    High,
+    Normal,
+    Low
+}
+
+impl Priority {
+
+    /// Returns the value used for the `X-Priority` header.
+    pub fn x_priority(&self) -> &'static str {
+        use self::Priority::*;
+        match *self {
+            High => "1",
+            Normal => "3",
+            Low => "5"
+        }
+    }
+
+    /// Returns the value used for the `Importance` header.
+    pub fn importance(&self) -> &'static str {
+        use self::Priority::*;
+        match *self {
+            High => "high",
+            Normal => "normal",
+            Low => "low"
+        }
+    }
+
+    /// Returns the value used for the `X-MSMail-Priority` header.
+    pub fn ms_mail_priority(&self) -> &'static str {
+        use self::Priority::*;
+        match *self {
+            High => "High",
+            Normal => "Normal",
+            Low => "Low"
+        }
+    }
+}
+
+impl MailBody {
+
+    /// Creates a new single body for given `Resource`.
+    pub fn single(body: Resource) -> Self {
+        MailBody::SingleBody { body }
+    }
+
+    /// Creates a new multiple bodies body with given (sub-)mails.
+    ///
+    /// The hidden text is empty.
+    pub fn multiple(bodies: Vec<Mail>) -> Self {
+        MailBody::MultipleBodies {
+            bodies,
+            hidden_text: SoftAsciiString::new()
+        }
+    }
+
+    /// Returns `true` if it's an multipart body.
+    pub fn is_multipart(&self) -> bool {
+        use self::MailBody::*;
+        match *self {
+            SingleBody { .. } => false,
+            MultipleBodies { .. } => true
+        }
+    }
+}
+
+/// Options changing how a mail is turned into a `EncodableMail` and encoded.
+///
+/// Use `Mail::into_encodable_mail_with_options` to pass them in, the
+/// default options are used by `Mail::into_encodable_mail`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EncodeOptions {
+    /// Forces all bodies to be base64 transfer encoded.
+    ///
+    /// Some (broken) gateways/mail clients handle any encoding but base64
+    /// badly, this option allows to work around this. But be aware that
+    /// base64 encoding increases the size of a body by 1/3 (plus line
+    /// breaks) which is a noticeable overhead for text bodies (which
+    /// otherwise might not need any encoding at all).
+    ///
+    /// Bodies which are already transfer encoded with another encoding
+    /// will be decoded and re-encoded.
+    pub force_base64_bodies: bool,
+
+    /// Adds a `Content-MD5` header (rfc1864) to all singlepart bodies.
+    ///
+    /// The digest is computed over the decoded body, i.e. the data
+    /// before it was transfer encoded. Note that this is meant to detect
+    /// accidental modifications in transit, it does _not_ provide any
+    /// security as anyone modifying the body can just update the header.
+    pub emit_content_md5: bool,
+
+    /// Always quote the `boundary` parameter of multipart `Content-Type` headers.
+    ///
+    /// By default the boundary is only quoted if it is not a valid token
+    /// (which for auto-generated boundaries is always the case as they
+    /// contain `'='`). Some strict parsers prefer to always have it quoted.
+    pub always_quote_boundary: bool,
+
+    /// The value of the `MIME-Version` header, `None` omits the header.
+    ///
+    /// Defaults to `Some("1.0")` which is the only version defined so far,
+    /// changing it is mainly useful for testing/interop scenarios. The
+    /// value has to be of the form `1*DIGIT "." 1*DIGIT`, else encoding
+    /// the mail fails. It can be a `&'static str` or a (e.g. configured)
+    /// `String`, e.g. `Some("1.0".into())`.
+    pub mime_version: Option<Cow<'static, str>>
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            force_base64_bodies: false,
+            emit_content_md5: false,
+            always_quote_boundary: false,
+            mime_version: Some(Cow::Borrowed("1.0"))
+        }
+    }
+}
+
+/// A future resolving to an encodable mail.
+pub struct MailFuture<C: Context> {
+    inner: InnerMailFuture<C>
+}
+
+enum InnerMailFuture<C: Context> {
+    New { mail: Mail, ctx: C, options: EncodeOptions },
+    Loading {
+        mail: Mail,
+        pending: future::JoinAll<Vec<Either<
+            SendBoxFuture<EncData, ResourceLoadingError>,
+            future::FutureResult<EncData, ResourceLoadingError>
+        >>>,
+        /// index of the result in `pending` for each body (in visiting order)
+        result_indices: Vec<usize>,
+        ctx: C,
+        options: EncodeOptions,
+        generated: HeaderMap
+    },
+    Poison
+}
+
+impl<C> MailFuture<C>
+    where C: Context
+{
+    fn new(mail: Mail, ctx: C, options: EncodeOptions) -> Self {
+        MailFuture { inner: InnerMailFuture::New { mail, ctx, options } }
+    }
+}
+
+impl<T> Future for MailFuture<T>
+    where T: Context,
+{
+    type Item = EncodableMail;
+    type Error = MailError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        use self::InnerMailFuture::*;
+        loop {
+            let state = mem::replace(&mut self.inner, InnerMailFuture::Poison);
             match state {
-                New { mail, ctx } => {
+                New { mut mail, ctx, options } => {
+                    let mut generated = HeaderMap::new();
+                    if ctx.auto_insert_sender() && !mail.headers().contains(Sender) {
+                        insert_sender_if_needed(&mut mail)?;
+                        if let Some(Ok(sender)) = mail.headers().get_single(Sender) {
+                            generated.insert(sender.clone());
+                        }
+                    }
                     mail.generally_validate_mail()?;
                     top_level_validation(&mail)?;
+                    validate_from_domains(&mail, &ctx)?;
 
+                    // resources used multiple times (e.g. through `MailParts::embed_and_attach`)
+                    // are only loaded/transfer encoded once
                     let mut futures = Vec::new();
+                    let mut unique_resources: Vec<Resource> = Vec::new();
+                    let mut result_indices = Vec::new();
                     mail.visit_mail_bodies(&mut |resource: &Resource| {
+                        let shared = unique_resources.iter()
+                            .position(|other| is_same_resource(other, resource));
+                        if let Some(idx) = shared {
+                            result_indices.push(idx);
+                            return;
+                        }
+
                         let fut = match resource {
                             &Resource::Source(ref source) => {
-                                Either::A(ctx.load_resource(source))
+                                Either::A(transcode_loaded(load_source(source, &ctx), &ctx))
                             },
                             &Resource::Data(ref data) => {
-                                Either::A(ctx.transfer_encode_resource(data))
+                                let data =
+                                    if options.force_base64_bodies {
+                                        data.clone()
+                                            .with_transfer_encoding_hint(TransferEncodingHint::UseBase64)
+                                    } else {
+                                        data.clone()
+                                    };
+                                match transcode_data(data) {
+                                    Ok(data) => Either::A(ctx.transfer_encode_resource(&data)),
+                                    Err(err) => Either::B(future::err(err))
+                                }
                             },
                             &Resource::EncData(ref enc_data) => {
-                                Either::B(future::ok(enc_data.clone()))
+                                Either::A(transcode_loaded(Box::new(future::ok(enc_data.clone())), &ctx))
                             }
                         };
 
+                        result_indices.push(futures.len());
                         futures.push(fut);
+                        unique_resources.push(resource.clone());
                     });
 
                     mem::replace(
                         &mut self.inner,
                         InnerMailFuture::Loading {
-                            mail, ctx,
+                            mail, ctx, options, generated, result_indices,
                             pending: future::join_all(futures)
                         }
                     );
                 },
-                Loading { mut mail, mut pending, ctx } => {
+                Loading { mut mail, mut pending, result_indices, ctx, options, mut generated } => {
                     match pending.poll() {
                         Err(err) => return Err(err.into()),
                         Ok(Async::NotReady) => {
                             mem::replace(
                                 &mut self.inner,
-                                InnerMailFuture::Loading { mail, pending, ctx }
+                                InnerMailFuture::Loading {
+                                    mail, pending, result_indices, ctx, options, generated
+                                }
                             );
                             return Ok(Async::NotReady);
                         },
-                        Ok(Async::Ready(encoded_bodies)) => {
-                            auto_gen_headers(&mut mail, encoded_bodies, &ctx);
-                            return Ok(Async::Ready(EncodableMail(mail)));
+                        Ok(Async::Ready(results)) => {
+                            let mut encoded_bodies = result_indices.iter()
+                                .map(|&idx| results[idx].clone())
+                                .collect::<Vec<_>>();
+                            if options.force_base64_bodies {
+                                encoded_bodies = encoded_bodies.into_iter()
+                                    .map(EncData::into_base64)
+                                    .collect::<Result<Vec<_>, _>>()?;
+                            }
+                            auto_gen_headers(&mut mail, encoded_bodies, &ctx, &options, &mut generated)?;
+                            return Ok(Async::Ready(EncodableMail(mail, options, generated)));
                         }
                     }
                 },
@@ -509,18 +1720,101 @@ impl<T> Future for MailFuture<T>
     }
 }
 
+/// Returns true if both resources are the same and as such only need to be loaded once.
+///
+/// This is the case for clones of the same `Data`/`EncData` (see `Resource::ptr_eq`)
+/// and for equal `Source`s.
+fn is_same_resource(left: &Resource, right: &Resource) -> bool {
+    match (left, right) {
+        (&Resource::Source(ref left), &Resource::Source(ref right)) => left == right,
+        _ => left.ptr_eq(right)
+    }
+}
+
 /// a mail with all contained futures resolved, so that it can be encoded
 #[derive(Clone)]
-pub struct EncodableMail(Mail);
+pub struct EncodableMail(Mail, EncodeOptions, HeaderMap);
 
 impl EncodableMail {
 
+    /// Returns the options used to create this mail (and used when encoding it).
+    pub fn encode_options(&self) -> &EncodeOptions {
+        &self.1
+    }
+
+    /// Returns the top level headers which were generated when creating this mail.
+    ///
+    /// These are the headers which were not set on the mail but generated
+    /// when turning it into a encodable mail, i.e. `Date`, `Message-Id`
+    /// and `Sender` (see `Context::auto_insert_sender`) if they were not
+    /// set and the `Content-Type`/`Content-Transfer-Encoding` header of a
+    /// singlepart mail (which are always generated). This is meant for
+    /// logging/debugging what was added to the mail. Note that headers of
+    /// sub-bodies and parameters added to existing headers (like the
+    /// boundary of a multipart `Content-Type`) are not included.
+    pub fn generated_headers(&self) -> &HeaderMap {
+        &self.2
+    }
+
+    /// Returns the SMTP envelope of the mail, i.e. the reverse-path and the forward-paths.
+    ///
+    /// The reverse-path is the `Sender` mailbox if there is a `Sender` header
+    /// and the first `From` mailbox else wise. The forward-paths are all
+    /// mailboxes from the `To`, `Cc` and `Bcc` headers (in that order, with
+    /// duplicates removed).
+    ///
+    /// # Error
+    ///
+    /// Fails if the mail has neither a `Sender` nor a `From` header, if it
+    /// does not have any recipients or if any of the used headers has
+    /// an unexpected type.
+    pub fn smtp_envelope(&self) -> Result<(Mailbox, Vec1<Mailbox>), MailError> {
+        let headers = self.headers();
+        let reverse_path =
+            if let Some(sender) = headers.get_single(Sender) {
+                sender?.body().clone()
+            } else if let Some(from) = headers.get_single(_From) {
+                from?.body().0.first().clone()
+            } else {
+                return Err(OtherValidationError::NoFrom.into());
+            };
+
+        let mut forward_paths: Vec<Mailbox> = Vec::new();
+        {
+            let mut push_all = |mailboxes: &[Mailbox]| {
+                for mailbox in mailboxes {
+                    if !forward_paths.contains(mailbox) {
+                        forward_paths.push(mailbox.clone());
+                    }
+                }
+            };
+
+            if let Some(to) = headers.get_single(_To) {
+                push_all(&to?.body().0);
+            }
+            if let Some(cc) = headers.get_single(Cc) {
+                push_all(&cc?.body().0);
+            }
+            if let Some(bcc) = headers.get_single(Bcc) {
+                push_all(&bcc?.body().0);
+            }
+        }
+
+        let forward_paths = Vec1::from_vec(forward_paths)
+            .map_err(|_| OtherValidationError::NoRecipients)?;
+
+        Ok((reverse_path, forward_paths))
+    }
+
     /// Encode the mail using the given encoding buffer.
     ///
     /// After encoding succeeded the buffer should contain
     /// a fully encoded mail including all attachments, embedded
     /// images alternate bodies etc.
     ///
+    /// The `Bcc` header is never encoded, it is only used to
+    /// determine the recipients (see `smtp_envelope`).
+    ///
     /// # Error
     ///
     /// This can fail for a large number of reasons, e.g. some
@@ -536,89 +1830,443 @@ impl EncodableMail {
         self.encode(&mut buffer)?;
         Ok(buffer.into())
     }
-}
-
-fn top_level_validation(mail: &Mail) -> Result<(), HeaderValidationError> {
-    if mail.headers().contains(_From) {
-        Ok(())
-    } else {
-        Err(OtherValidationError::NoFrom.into())
-    }
-}
 
-/// inserts ContentType and ContentTransferEncoding into
-/// the headers of any contained `MailBody::SingleBody`,
-/// based on the `Resource` representing the body
-fn auto_gen_headers<C: Context>(
-    mail: &mut Mail,
-    encoded_resources: Vec<EncData>,
-    ctx: &C
-) {
-    {
-        let headers = mail.headers_mut();
-        if !headers.contains(Date) {
-            headers.insert(Date::body(DateTime::now()));
+    /// Returns the minimal mail type which can be used to encode the mail.
+    ///
+    /// This is decided by inspecting the mail:
+    ///
+    /// - A body transfer encoded with `8bit` or `binary` requires
+    ///   `MailType::Mime8BitEnabled`.
+    /// - A mailbox with a non us-ascii local-part in the `From`, `Sender`,
+    ///   `Reply-To`, `To` or `Cc` header requires `MailType::Internationalized`.
+    ///   (Non us-ascii domains are punycode encoded and other headers use
+    ///   encoded words, so they do not require it.)
+    ///
+    /// # Error
+    ///
+    /// Fails if any of the inspected headers has an unexpected type.
+    pub fn required_mail_type(&self) -> Result<MailType, MailError> {
+        if self.has_internationalized_mailbox()? {
+            return Ok(MailType::Internationalized);
         }
 
-        if !headers.contains(MessageId) {
-            headers.insert(MessageId::body(ctx.generate_message_id()));
+        let mut needs_8bit = false;
+        self.visit_mail_bodies(&mut |resource: &Resource| {
+            match assume_encoded(resource).encoding() {
+                TransferEncoding::_8Bit | TransferEncoding::Binary => needs_8bit = true,
+                _ => {}
+            }
+        });
+
+        if needs_8bit {
+            Ok(MailType::Mime8BitEnabled)
+        } else {
+            Ok(MailType::Ascii)
         }
     }
 
-    let mut iter = encoded_resources.into_iter();
-    mail.visit_mail_bodies_mut(&mut move |resource: &mut Resource| {
-        let enc_data = iter.next()
-            .expect("[BUG] mail structure modified while turing it into encoded mail");
-        mem::replace(resource, Resource::EncData(enc_data));
-    });
-
-    let mut boundary_count = 0;
-    recursive_auto_gen_headers(mail, &mut boundary_count, ctx);
-}
-
-/// returns the `EncData` from a resource
-///
-/// # Panics
-///
-/// Panics if the resource is not transfer encoded
-pub(crate) fn assume_encoded(resource: &Resource) -> &EncData {
-    match resource {
-        &Resource::EncData(ref ed) => ed,
-        _ => panic!("[BUG] auto gen/encode should only be called on all resources are loaded")
-    }
-}
+    fn has_internationalized_mailbox(&self) -> Result<bool, MailError> {
+        fn is_internationalized(mailbox: &Mailbox) -> bool {
+            !mailbox.email.local_part.as_str().is_ascii()
+        }
 
-fn recursive_auto_gen_headers<C: Context>(mail: &mut Mail, boundary_count: &mut usize, ctx: &C) {
-    let &mut Mail { ref mut headers, ref mut body } = mail;
-    match body {
-        &mut MailBody::SingleBody { ref mut body } => {
-            if let Some(Ok(disposition)) = headers.get_single_mut(ContentDisposition) {
-                let current_file_meta_mut = disposition.file_meta_mut();
-                let data = assume_encoded(body);
-                current_file_meta_mut.replace_empty_fields_with(data.file_meta())
+        let headers = self.headers();
+        if let Some(sender) = headers.get_single(Sender) {
+            if is_internationalized(sender?.body()) {
+                return Ok(true);
             }
-        },
-        &mut MailBody::MultipleBodies { ref mut bodies, .. } => {
-            let mut headers: &mut HeaderMap = headers;
-            let content_type: &mut Header<ContentType> = headers
-                .get_single_mut(ContentType)
-                .expect("[BUG] mail was already validated")
-                .expect("[BUG] mail was already validated");
-
-            let boundary = create_structured_random_boundary(*boundary_count);
-            *boundary_count += 1;
-            content_type.set_param(BOUNDARY, boundary);
+        }
 
-            for sub_mail in bodies {
-                recursive_auto_gen_headers(sub_mail, boundary_count, ctx);
+        let lists = vec![
+            headers.get_single(_From).map(|res| res.map(|header| &header.body().0)),
+            headers.get_single(ReplyTo).map(|res| res.map(|header| &header.body().0)),
+            headers.get_single(_To).map(|res| res.map(|header| &header.body().0)),
+            headers.get_single(Cc).map(|res| res.map(|header| &header.body().0))
+        ];
+        for list in lists {
+            if let Some(list) = list {
+                if list?.iter().any(is_internationalized) {
+                    return Ok(true);
+                }
             }
         }
+        Ok(false)
     }
-}
-
-pub(crate) fn validate_multipart_headermap(headers: &HeaderMap)
-    -> Result<(), MailError>
-{
+
+    /// Encodes the mail using the minimal mail type which can be used.
+    ///
+    /// See `required_mail_type`.
+    ///
+    /// # Error
+    ///
+    /// Fails if determining the required mail type fails or if encoding
+    /// the mail with it fails.
+    pub fn encode_auto(&self) -> Result<Vec<u8>, MailError> {
+        let mail_type = self.required_mail_type()?;
+        self.encode_into_bytes(mail_type)
+    }
+
+    /// Encodes the mail and writes it to the given writer.
+    ///
+    /// The mail is written part by part, i.e. the encoded data is written
+    /// to the writer after each singlepart body, so at most the headers
+    /// and body of one (sub-)mail are kept in an additional buffer instead
+    /// of the whole encoded mail. The writer is flushed at the end.
+    ///
+    /// # Error
+    ///
+    /// Besides the errors `encode` can return this fails with `MailError::Io`
+    /// if writing fails. If writing or encoding fails a part of the mail
+    /// might already have been written.
+    pub fn encode_to_writer<W: io::Write>(&self, mail_type: MailType, writer: &mut W)
+        -> Result<(), MailError>
+    {
+        let mut encoder = EncodingBuffer::new(mail_type);
+        ::encode::encode_mail_in_parts(self, true, &mut encoder, &mut |encoder: &mut EncodingBuffer| {
+            writer.write_all(&take_encoded(encoder))?;
+            Ok(())
+        })?;
+        writer.write_all(&take_encoded(&mut encoder))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the size of the encoded mail in bytes.
+    ///
+    /// This can be used to e.g. reject mails exceeding the `SIZE` limit of
+    /// a SMTP server before trying to send them. The size is computed by
+    /// encoding the mail, so it costs as much time as encoding it, but like
+    /// with `encode_to_writer` the whole encoded mail is never kept in
+    /// memory. If the encoded mail is needed anyway use `encode_into_bytes`
+    /// and take the length of the result instead.
+    pub fn encoded_size(&self, mail_type: MailType) -> Result<usize, MailError> {
+        let mut encoder = EncodingBuffer::new(mail_type);
+        let mut size = 0;
+        ::encode::encode_mail_in_parts(self, true, &mut encoder, &mut |encoder: &mut EncodingBuffer| {
+            size += take_encoded(encoder).len();
+            Ok(())
+        })?;
+        Ok(size + take_encoded(&mut encoder).len())
+    }
+
+    /// Encodes the mail as `MailType::Ascii` mail returning it as `String`.
+    ///
+    /// # Error
+    ///
+    /// Besides the errors `encode` can return this fails if the mail can not
+    /// be encoded as us-ascii mail, e.g. because it has a non us-ascii header
+    /// or a body which is transfer encoded with `8bit`/`binary` and contains
+    /// non us-ascii bytes.
+    pub fn encode_into_string(&self) -> Result<String, MailError> {
+        let bytes = self.encode_into_bytes(MailType::Ascii)?;
+        if !bytes.is_ascii() {
+            let err = EncodingError::from(EncodingErrorKind::InvalidTextEncoding {
+                got_encoding: "8bit",
+                expected_encoding: US_ASCII
+            }).with_mail_type_or_else(|| Some(MailType::Ascii));
+            return Err(err.into());
+        }
+        Ok(String::from_utf8(bytes).expect("[BUG] us-ascii is valid utf-8"))
+    }
+
+    /// Writes the decoded content of all singlepart bodies into separate files.
+    ///
+    /// The bodies are written (in visiting order) to `dir/part<N>.<ext>` where
+    /// the extension is derived from the media type (`bin` if unknown).
+    /// Additionally a `headers.txt` file is written listing the (encoded) headers
+    /// of all (sub-)mails and which file contains which body. The directory is created
+    /// if it doesn't exist.
+    ///
+    /// This is a tool for debugging/inspecting mails, the exported files are
+    /// _not_ a format which can be used to deliver/reconstruct the mail.
+    ///
+    /// Returns the paths of all written files, the `headers.txt` path is
+    /// the last one.
+    pub fn export_parts<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<PathBuf>, MailError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut paths = Vec::new();
+        let mut manifest = String::new();
+        export_parts(&self.0, dir, "", &mut paths, &mut manifest)?;
+
+        let manifest_path = dir.join("headers.txt");
+        fs::File::create(&manifest_path)?.write_all(manifest.as_bytes())?;
+        paths.push(manifest_path);
+        Ok(paths)
+    }
+}
+
+fn export_parts(
+    mail: &Mail,
+    dir: &Path,
+    indent: &str,
+    paths: &mut Vec<PathBuf>,
+    manifest: &mut String
+) -> Result<(), MailError> {
+    for (name, hbody) in mail.headers().iter() {
+        let encoded = encode_header_value(name, hbody)?;
+        let encoded = String::from_utf8_lossy(&encoded);
+        for line in encoded.split("\r\n").filter(|line| !line.is_empty()) {
+            manifest.push_str(&format!("{}{}\n", indent, line));
+        }
+    }
+
+    match mail.body {
+        MailBody::SingleBody { ref body } => {
+            let data = assume_encoded(body);
+            let file_name = format!("part{}.{}",
+                paths.len(), file_extension_for(data.media_type()));
+            manifest.push_str(&format!("{}Content-Type: {}\n", indent, data.media_type().as_str_repr()));
+            manifest.push_str(&format!("{}=> {}\n", indent, file_name));
+
+            let path = dir.join(file_name);
+            fs::File::create(&path)?.write_all(&data.decoded_buffer()?)?;
+            paths.push(path);
+        },
+        MailBody::MultipleBodies { ref bodies, .. } => {
+            let sub_indent = format!("{}    ", indent);
+            for body in bodies {
+                manifest.push_str(&format!("{}--\n", indent));
+                export_parts(body, dir, &sub_indent, paths, manifest)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Takes the encoded data out of the encoder, leaving an empty encoder.
+fn take_encoded(encoder: &mut EncodingBuffer) -> Vec<u8> {
+    let mail_type = encoder.mail_type();
+    mem::replace(encoder, EncodingBuffer::new(mail_type)).into()
+}
+
+fn file_extension_for(media_type: &MediaType) -> &'static str {
+    match (media_type.type_().as_str(), media_type.subtype().as_str()) {
+        ("text", "plain") => "txt",
+        ("text", "html") => "html",
+        ("text", "css") => "css",
+        ("text", "calendar") => "ics",
+        ("image", "png") => "png",
+        ("image", "jpeg") => "jpg",
+        ("image", "gif") => "gif",
+        ("image", "svg+xml") => "svg",
+        ("application", "pdf") => "pdf",
+        ("application", "json") => "json",
+        ("application", "zip") => "zip",
+        ("message", "rfc822") => "eml",
+        _ => "bin"
+    }
+}
+
+fn top_level_validation(mail: &Mail) -> Result<(), HeaderValidationError> {
+    if mail.headers().contains(_From) {
+        Ok(())
+    } else {
+        Err(OtherValidationError::NoFrom.into())
+    }
+}
+
+/// Inserts the first `From` mailbox as `Sender` if there are multiple `From`
+/// mailboxes and no `Sender` header.
+fn insert_sender_if_needed(mail: &mut Mail) -> Result<(), MailError> {
+    if mail.headers().contains(Sender) {
+        return Ok(());
+    }
+
+    let sender = match mail.headers().get_single(_From) {
+        Some(from) => {
+            let from = from?;
+            if from.0.len() < 2 {
+                return Ok(());
+            }
+            from.0.first().clone()
+        },
+        None => return Ok(())
+    };
+
+    mail.insert_header(Sender::body(sender));
+    Ok(())
+}
+
+fn validate_from_domains<C: Context>(mail: &Mail, ctx: &C) -> Result<(), MailError> {
+    let allowed = match ctx.allowed_from_domains() {
+        Some(allowed) => allowed,
+        None => return Ok(())
+    };
+
+    if let Some(from) = mail.headers().get_single(_From) {
+        for mailbox in from?.0.iter() {
+            let domain = mailbox.email.domain.as_str();
+            let is_allowed = allowed.iter()
+                .any(|allowed| allowed.as_str().eq_ignore_ascii_case(domain));
+            if !is_allowed {
+                return Err(OtherValidationError::FromDomainNotAllowed {
+                    domain: domain.to_owned()
+                }.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// parses a `','` separated list of plain addresses, see `Mail::insert_raw_headers`
+fn raw_mailbox_list(value: &str) -> Result<MailboxList, MailError> {
+    let mut mailboxes = Vec::new();
+    for address in value.split(',') {
+        mailboxes.push(Mailbox::from(Email::new(address.trim())?));
+    }
+    //UNWRAP_SAFE: split always returns at least one item
+    Ok(MailboxList(Vec1::from_vec(mailboxes).unwrap()))
+}
+
+/// inserts ContentType and ContentTransferEncoding into
+/// the headers of any contained `MailBody::SingleBody`,
+/// based on the `Resource` representing the body
+fn auto_gen_headers<C: Context>(
+    mail: &mut Mail,
+    encoded_resources: Vec<EncData>,
+    ctx: &C,
+    options: &EncodeOptions,
+    generated: &mut HeaderMap
+) -> Result<(), MailError> {
+    if !mail.headers().contains(Date) {
+        let date = Date::body(ctx.now());
+        generated.insert(date.clone());
+        mail.headers_mut().insert(date);
+    }
+
+    if !mail.headers().contains(MessageId) {
+        mail.ensure_message_id(ctx);
+        if let Some(Ok(message_id)) = mail.headers().get_single(MessageId) {
+            generated.insert(message_id.clone());
+        }
+    }
+
+    let mut iter = encoded_resources.into_iter();
+    mail.visit_mail_bodies_mut(&mut move |resource: &mut Resource| {
+        let enc_data = iter.next()
+            .expect("[BUG] mail structure modified while turing it into encoded mail");
+        mem::replace(resource, Resource::EncData(enc_data));
+    });
+
+    if let MailBody::SingleBody { ref body } = mail.body {
+        let data = assume_encoded(body);
+        generated.insert(ContentType::body(data.media_type().clone()));
+        generated.insert(ContentTransferEncoding::body(data.encoding()));
+    }
+
+    let mut boundary_count = 0;
+    recursive_auto_gen_headers(mail, &mut boundary_count, ctx, options)
+}
+
+/// Transcodes text data to utf-8 if the `charset-transcode` feature is enabled.
+///
+/// See `Data::transcode_to_utf8`.
+#[cfg(feature="charset-transcode")]
+fn transcode_data(data: Data) -> Result<Data, ResourceLoadingError> {
+    data.transcode_to_utf8()
+}
+
+#[cfg(not(feature="charset-transcode"))]
+fn transcode_data(data: Data) -> Result<Data, ResourceLoadingError> {
+    Ok(data)
+}
+
+/// Transcodes loaded text data to utf-8 if the `charset-transcode` feature is enabled.
+///
+/// This makes sure data is transcoded independent of the resource loader
+/// used. As loaded data is already transfer encoded it is decoded, transcoded
+/// and then transfer encoded again, data which doesn't need to be transcoded
+/// is kept as is.
+#[cfg(feature="charset-transcode")]
+fn transcode_loaded<C: Context>(loading: SendBoxFuture<EncData, ResourceLoadingError>, ctx: &C)
+    -> SendBoxFuture<EncData, ResourceLoadingError>
+{
+    let ctx = ctx.clone();
+    let fut = loading.and_then(move |enc_data| -> SendBoxFuture<EncData, ResourceLoadingError> {
+        match enc_data.transcode_to_utf8() {
+            Ok(Some(data)) => ctx.transfer_encode_resource(&data),
+            Ok(None) => Box::new(future::ok(enc_data)),
+            Err(err) => Box::new(future::err(err))
+        }
+    });
+    Box::new(fut)
+}
+
+#[cfg(not(feature="charset-transcode"))]
+fn transcode_loaded<C: Context>(loading: SendBoxFuture<EncData, ResourceLoadingError>, _ctx: &C)
+    -> SendBoxFuture<EncData, ResourceLoadingError>
+{
+    loading
+}
+
+/// returns the `EncData` from a resource
+///
+/// # Panics
+///
+/// Panics if the resource is not transfer encoded, the panic message
+/// includes the IRI of the resource (or notes that it is sourceless).
+pub(crate) fn assume_encoded(resource: &Resource) -> &EncData {
+    match resource {
+        &Resource::EncData(ref ed) => ed,
+        &Resource::Source(ref source) => panic!(
+            "[BUG] auto gen/encode should only be called on all resources are loaded (source: {})",
+            source.iri.as_str()),
+        &Resource::Data(_) => panic!(
+            "[BUG] auto gen/encode should only be called on all resources are loaded (sourceless)")
+    }
+}
+
+fn recursive_auto_gen_headers<C: Context>(
+    mail: &mut Mail,
+    boundary_count: &mut usize,
+    ctx: &C,
+    options: &EncodeOptions
+) -> Result<(), MailError> {
+    let &mut Mail { ref mut headers, ref mut body, .. } = mail;
+    match body {
+        &mut MailBody::SingleBody { ref mut body } => {
+            let data = assume_encoded(body);
+            if let Some(Ok(disposition)) = headers.get_single_mut(ContentDisposition) {
+                let current_file_meta_mut = disposition.file_meta_mut();
+                current_file_meta_mut.replace_empty_fields_with(data.file_meta())
+            }
+            if options.emit_content_md5 {
+                let digest = md5::compute(data.decoded_buffer()?);
+                let digest = base64::normal_encode(&digest[..]);
+                headers.insert(additional_headers::ContentMd5::auto_body(digest.as_str())?);
+            }
+        },
+        &mut MailBody::MultipleBodies { ref mut bodies, .. } => {
+            let mut headers: &mut HeaderMap = headers;
+            let content_type: &mut Header<ContentType> = headers
+                .get_single_mut(ContentType)
+                .expect("[BUG] mail was already validated")
+                .expect("[BUG] mail was already validated");
+
+            if content_type.get_param(BOUNDARY).is_none() {
+                let boundary = ctx.generate_boundary(*boundary_count);
+                *boundary_count += 1;
+                if !is_valid_boundary(boundary.as_str()) {
+                    return Err(OtherValidationError::InvalidBoundary {
+                        boundary: boundary.into()
+                    }.into());
+                }
+                content_type.set_param(BOUNDARY, boundary.as_str());
+            }
+
+            for sub_mail in bodies {
+                recursive_auto_gen_headers(sub_mail, boundary_count, ctx, options)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_multipart_headermap(headers: &HeaderMap)
+    -> Result<(), MailError>
+{
 
     if headers.contains(ContentTransferEncoding) {
         return Err(OtherValidationError::ContentTransferEncodingHeaderGiven.into());
@@ -628,6 +2276,12 @@ pub(crate) fn validate_multipart_headermap(headers: &HeaderMap)
         if !header_with_right_type.is_multipart() {
             return Err(OtherValidationError::SingleMultipartMixup.into());
         }
+        if let Some(boundary) = header_with_right_type.get_param(BOUNDARY) {
+            let boundary = boundary.to_content();
+            if !is_valid_boundary(&boundary) {
+                return Err(OtherValidationError::InvalidBoundary { boundary }.into());
+            }
+        }
     } else {
         return Err(OtherValidationError::MissingContentTypeHeader.into());
     }
@@ -659,7 +2313,7 @@ impl Deref for EncodableMail {
 
 impl Into<Mail> for EncodableMail {
     fn into(self) -> Mail {
-        let EncodableMail(mail) = self;
+        let EncodableMail(mail, _, _) = self;
         mail
     }
 }
@@ -688,7 +2342,9 @@ mod test {
                 Comments
             }
         };
-        use default_impl::test_context;
+        use media_type::TEXT;
+        use headers::header_components::{Disposition, Email, FileMeta};
+        use default_impl::{test_context, test_context_allowing_domains, test_source};
         use super::super::*;
         use super::{AssertDebug, AssertSend, AssertSync};
 
@@ -697,6 +2353,70 @@ mod test {
         impl AssertSync for Mail {}
 
 
+        test!(insert_raw_headers_inserts_typed_headers, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_raw_headers(vec![
+                ("from", "from@example.com"),
+                ("To", "to1@example.com, to2@example.com"),
+                ("SUBJECT", "the subject")
+            ])?;
+
+            assert_eq!(mail.subject(), Some("the subject"));
+            assert_eq!(mail.to_addresses().unwrap().0.len(), 2);
+
+            let enc_mail = mail.into_encodable_mail(ctx).wait()?;
+            let encoded = enc_mail.encode_into_string()?;
+            assert!(encoded.contains("From: "));
+            assert!(encoded.contains("Subject: the subject\r\n"));
+            assert!(encoded.contains("to2@example.com"));
+        });
+
+        #[test]
+        fn insert_raw_headers_rejects_line_breaks() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r1", &ctx);
+            assert_err!(mail.insert_raw_headers(vec![
+                ("Subject", "hy"),
+                ("Comments", "fine\r\nBcc: injected@example.com")
+            ]));
+            assert_not!(mail.headers().contains(Subject));
+            assert_not!(mail.headers().contains(Comments));
+        }
+
+        #[test]
+        fn insert_raw_headers_rejects_unsupported_headers() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r1", &ctx);
+            let err = assert_err!(mail.insert_raw_headers(vec![("X-Custom", "value")]));
+            match err {
+                MailError::Validation(HeaderValidationError::Custom(ref err)) => {
+                    match err.downcast_ref::<OtherValidationError>() {
+                        Some(&OtherValidationError::UnsupportedRawHeader { ref name }) => {
+                            assert_eq!(name, "X-Custom");
+                        },
+                        _ => panic!("unexpected error: {:?}", err)
+                    }
+                },
+                err => panic!("unexpected error: {:?}", err)
+            }
+        }
+
+        #[test]
+        fn mail_body_constructors_create_the_right_variants() {
+            let ctx = test_context();
+            let single = MailBody::single(Resource::plain_text("r1", &ctx));
+            assert_not!(single.is_multipart());
+
+            let multiple = MailBody::multiple(vec![ Mail::plain_text("r2", &ctx) ]);
+            if let MailBody::MultipleBodies { bodies, hidden_text } = multiple {
+                assert_eq!(bodies.len(), 1);
+                assert!(hidden_text.is_empty());
+            } else {
+                panic!("expected multiple bodies")
+            }
+        }
+
         #[test]
         fn visit_mail_bodies_does_not_skip() {
             let ctx = test_context();
@@ -712,28 +2432,33 @@ mod test {
                                         headers: HeaderMap::new(),
                                         body: MailBody::SingleBody {
                                             body: Resource::plain_text("r1", &ctx)
-                                        }
+                                        },
+                                        default_message_id: None
                                     },
                                     Mail {
                                         headers: HeaderMap::new(),
                                         body: MailBody::SingleBody {
                                             body: Resource::plain_text("r2", &ctx)
-                                        }
+                                        },
+                                        default_message_id: None
                                     }
                                 ],
                                 hidden_text: Default::default()
-                            }
+                            },
+                            default_message_id: None
                         },
                         Mail {
                             headers: HeaderMap::new(),
                             body: MailBody::SingleBody {
                                 body: Resource::plain_text("r3", &ctx)
-                            }
+                            },
+                            default_message_id: None
                         }
 
                     ],
                     hidden_text: Default::default()
-                }
+                },
+                default_message_id: None
             };
 
             let mut body_count = 0;
@@ -752,6 +2477,38 @@ mod test {
             assert_eq!(body_count, 3);
         }
 
+        #[test]
+        fn resources_does_not_skip() {
+            let ctx = test_context();
+            let mut attachment = Mail::plain_text("r3", &ctx);
+            attachment.insert_header(ContentDisposition::body(
+                Disposition::new(DispositionKind::Attachment, Default::default())));
+
+            let mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::new_multipart_mail(
+                        MediaType::parse("multipart/alternative").unwrap(),
+                        vec![
+                            Mail::plain_text("r1", &ctx),
+                            Mail::plain_text("r2", &ctx)
+                        ]
+                    ),
+                    attachment
+                ]
+            );
+
+            let contents = mail.resources()
+                .map(|resource| match *resource {
+                    Resource::Data(ref data) => data.buffer().to_vec(),
+                    ref other => panic!("unexpected body: {:?}", other)
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(contents, vec![b"r1".to_vec(), b"r2".to_vec(), b"r3".to_vec()]);
+            assert_eq!(mail.attachments_count(), 1);
+        }
+
         test!(insert_header_set_a_header, {
             let ctx = test_context();
             let mut mail = Mail::plain_text("r0", &ctx);
@@ -761,6 +2518,119 @@ mod test {
 
 
 
+        impl AssertDebug for HeaderSkeleton {}
+        impl AssertSend for HeaderSkeleton {}
+        impl AssertSync for HeaderSkeleton {}
+
+        test!(header_skeleton_preserves_structure, {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed")?,
+                vec![
+                    Mail::plain_text("r1", &ctx),
+                    Mail::plain_text("r2", &ctx)
+                ]
+            );
+            mail.insert_header(Subject::auto_body("skeleton")?);
+
+            let skeleton = mail.header_skeleton().clone();
+
+            assert!(skeleton.headers.contains(Subject));
+            assert!(skeleton.headers.contains(ContentType));
+            assert_eq!(skeleton.headers.len(), 2);
+            match skeleton.body {
+                SkeletonBody::Multiple { ref bodies } => {
+                    assert_eq!(bodies.len(), 2);
+                    for body in bodies {
+                        assert_eq!(body.headers.len(), 0);
+                        match body.body {
+                            SkeletonBody::Single { media_type: Some(ref media_type) } => {
+                                assert_eq!(media_type.type_(), TEXT);
+                            },
+                            ref other => panic!("unexpected body: {:?}", other)
+                        }
+                    }
+                },
+                ref other => panic!("unexpected body: {:?}", other)
+            }
+        });
+
+        #[test]
+        fn dedup_attachments_removes_same_attachments() {
+            let ctx = test_context();
+            let attachment = |file_name: &str| {
+                let data = Data::new(b"some bytes".to_vec(), Metadata {
+                    file_meta: Default::default(),
+                    media_type: MediaType::parse("application/octet-stream").unwrap(),
+                    content_id: ctx.generate_content_id()
+                });
+                let mut mail = Mail::new_singlepart_mail(Resource::Data(data));
+                let file_meta = FileMeta {
+                    file_name: Some(file_name.to_owned()),
+                    ..Default::default()
+                };
+                mail.insert_header(ContentDisposition::body(
+                    Disposition::new(DispositionKind::Attachment, file_meta)));
+                mail
+            };
+
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::plain_text("r1", &ctx),
+                    attachment("a.bin"),
+                    attachment("a.bin"),
+                    attachment("b.bin")
+                ]
+            );
+
+            mail.dedup_attachments();
+
+            if let MailBody::MultipleBodies { ref bodies, .. } = *mail.body() {
+                assert_eq!(bodies.len(), 3);
+                let file_names = bodies[1..].iter()
+                    .map(|body| body.headers()
+                        .get_single(ContentDisposition).unwrap().unwrap()
+                        .file_meta().file_name.clone().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(file_names, vec!["a.bin", "b.bin"]);
+            } else {
+                unreachable!()
+            }
+        }
+
+        test!(html_text_creates_html_body, {
+            let ctx = test_context();
+            let mut mail = Mail::html_text("<p>r1</p>", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = String::from_utf8(enc_mail.encode_into_bytes(MailType::Ascii)?).unwrap();
+            assert!(encoded.contains("Content-Type: text/html"));
+            assert!(encoded.contains("Content-Transfer-Encoding: "));
+        });
+
+        test!(body_mut_allows_adding_sub_bodies, {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed")?,
+                vec![ Mail::plain_text("r1", &ctx) ]
+            );
+
+            if let MailBody::MultipleBodies { ref mut bodies, .. } = *mail.body_mut() {
+                bodies.push(Mail::plain_text("r2", &ctx));
+            } else {
+                unreachable!()
+            }
+
+            let mut count = 0;
+            mail.visit_mail_bodies(&mut |_: &Resource| count += 1);
+            assert_eq!(count, 2);
+            mail.generally_validate_mail()?;
+        });
+
         test!(insert_headers_sets_all_headers, {
             let ctx = test_context();
             let mut mail = Mail::plain_text("r0", &ctx);
@@ -773,49 +2643,250 @@ mod test {
             assert!(mail.headers().contains(Comments));
         });
 
-    }
+        #[test]
+        #[should_panic(expected = "source: path:logo.png")]
+        fn assume_encoded_panic_includes_the_source_iri() {
+            let resource = Resource::Source(test_source("path:logo.png"));
+            assume_encoded(&resource);
+        }
 
-    mod EncodableMail {
-        #![allow(non_snake_case)]
-        use chrono::{Utc, TimeZone};
-        use headers::{
-            headers::{
-                _From, ContentType, ContentTransferEncoding,
-                Date, Subject
-            }
-        };
-        use default_impl::test_context;
-        use super::super::*;
-        use super::{AssertDebug, AssertSend, AssertSync};
+        test!(mails_are_compared_structurally, {
+            let ctx = test_context();
+            let create = |text: &str| -> Result<Mail, MailError> {
+                let mut mail = Mail::new_multipart_mail(
+                    MediaType::parse("multipart/mixed")?,
+                    vec![
+                        Mail::plain_text("r1", &ctx),
+                        Mail::plain_text(text, &ctx)
+                    ]
+                );
+                mail.insert_header(Subject::auto_body("subject")?);
+                Ok(mail)
+            };
 
-        impl AssertDebug for EncodableMail {}
-        impl AssertSend for EncodableMail {}
-        impl AssertSync for EncodableMail {}
+            let mail = create("r2")?;
+            assert_eq!(mail, create("r2")?);
+            assert_ne!(mail, create("r3")?);
+
+            let mut other = create("r2")?;
+            other.insert_header(Subject::auto_body("other subject")?);
+            assert_ne!(mail, other);
+        });
 
         #[test]
-        fn sets_generated_headers_for_outer_mail() {
+        fn singlepart_from_bytes_creates_a_data_body() {
             let ctx = test_context();
-            let resource = Resource::plain_text("r9", &ctx);
-            let mail = Mail {
-                headers: headers!{
-                    _From: ["random@this.is.no.mail"],
-                    Subject: "hoho"
-                }.unwrap(),
-                body: MailBody::SingleBody { body: resource }
+            let media_type = MediaType::parse("application/pdf").unwrap();
+            let mail = Mail::singlepart_from_bytes(media_type, b"%PDF-1.4".to_vec(), &ctx);
+
+            assert_not!(mail.has_multipart_body());
+            match *mail.body() {
+                MailBody::SingleBody { body: Resource::Data(ref data) } => {
+                    assert_eq!(&**data.buffer(), b"%PDF-1.4");
+                    assert_eq!(data.media_type().subtype().as_str(), "pdf");
+                },
+                ref other => panic!("unexpected body: {:?}", other)
+            }
+        }
+
+        #[test]
+        fn new_singlepart_mail_uses_the_disposition_of_the_source() {
+            let source = |use_disposition| Source {
+                use_disposition,
+                ..test_source("path:logo.png")
             };
 
-            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let mail = Mail::new_singlepart_mail(Resource::Source(source(None)));
+            assert_not!(mail.headers().contains(ContentDisposition));
 
-            let headers: &HeaderMap = enc_mail.headers();
-            assert!(headers.contains(_From));
-            assert!(headers.contains(Subject));
-            assert!(headers.contains(Date));
-            // ContenType/TransferEncoding are added on the fly when encoding
-            // for leaf bodies
-            assert_not!(headers.contains(ContentType));
-            assert_not!(headers.contains(ContentTransferEncoding));
-            assert!(headers.contains(MessageId));
-            assert_eq!(headers.len(), 4);
+            let mail = Mail::new_singlepart_mail(
+                Resource::Source(source(Some(DispositionKind::Attachment))));
+            let disposition = mail.headers().get_single(ContentDisposition).unwrap().unwrap();
+            assert_eq!(disposition.kind(), DispositionKind::Attachment);
+        }
+
+        test!(from_and_to_addresses_read_the_headers, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r0", &ctx);
+            assert!(mail.from_addresses().is_none());
+            assert!(mail.to_addresses().is_none());
+
+            mail.insert_headers(headers! {
+                _From: ["from@example.com"],
+                _To: ["to1@example.com", "to2@example.com"]
+            }?);
+
+            assert_eq!(mail.from_addresses().unwrap().0.len(), 1);
+            let to = mail.to_addresses().unwrap();
+            assert_eq!(to.0.len(), 2);
+            assert_eq!(to.0.first().email.domain, Domain::from_unchecked("example.com".to_owned()));
+        });
+
+        test!(ensure_message_id_does_not_override_existing_ids, {
+            let ctx = test_context_allowing_domains(&["example.com"]);
+            let mut mail = Mail::plain_text("r0", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["from@example.com"]
+            }?);
+
+            mail.ensure_message_id(&ctx);
+            let message_id = mail.headers().get_single(MessageId).unwrap()?.as_str().to_owned();
+            assert!(message_id.ends_with("@example.com"));
+
+            mail.ensure_message_id(&ctx);
+            let message_id2 = mail.headers().get_single(MessageId).unwrap()?.as_str().to_owned();
+            assert_eq!(message_id, message_id2);
+        });
+
+        test!(set_reply_to_thread_sets_in_reply_to_and_references, {
+            let ctx = test_context();
+            let parent = ctx.generate_message_id();
+            let first = ctx.generate_message_id();
+            let second = ctx.generate_message_id();
+
+            let mut mail = Mail::plain_text("r0", &ctx);
+            mail.set_reply_to_thread(&parent, &[first.clone(), second.clone()]);
+
+            let in_reply_to = mail.headers().get_single(InReplyTo).unwrap()?;
+            assert_eq!(in_reply_to.0.to_vec(), vec![parent.clone()]);
+            let references = mail.headers().get_single(References).unwrap()?;
+            assert_eq!(references.0.to_vec(), vec![first, second, parent]);
+        });
+
+        test!(set_reply_to_thread_without_references, {
+            let ctx = test_context();
+            let parent = ctx.generate_message_id();
+
+            let mut mail = Mail::plain_text("r0", &ctx);
+            mail.set_reply_to_thread(&parent, &[]);
+
+            let in_reply_to = mail.headers().get_single(InReplyTo).unwrap()?;
+            assert_eq!(in_reply_to.0.to_vec(), vec![parent.clone()]);
+            let references = mail.headers().get_single(References).unwrap()?;
+            assert_eq!(references.0.to_vec(), vec![parent]);
+            mail.generally_validate_mail()?;
+        });
+
+        test!(set_reply_to_sets_the_mailboxes, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r0", &ctx);
+            assert!(mail.reply_to().is_none());
+
+            let mailboxes = vec![
+                Mailbox::from(Email::new("a@example.com")?),
+                Mailbox::from(Email::new("b@example.com")?)
+            ];
+            mail.set_reply_to(mailboxes)?;
+
+            let reply_to = mail.reply_to().unwrap();
+            assert_eq!(reply_to.0.len(), 2);
+            assert_eq!(reply_to.0.last().email.domain, Domain::from_unchecked("example.com".to_owned()));
+        });
+
+        test!(set_reply_to_rejects_empty_lists, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r0", &ctx);
+
+            let err = assert_err!(mail.set_reply_to(Vec::new()));
+            assert_eq!(
+                err.to_string(),
+                OtherValidationError::EmptyReplyTo.to_string()
+            );
+            assert!(mail.reply_to().is_none());
+        });
+
+        test!(insert_headers_reporting_reports_replaced_headers, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r0", &ctx);
+            mail.insert_headers(headers! {
+                Subject: "first",
+                Comments: "first comment"
+            }?);
+
+            let report = mail.insert_headers_reporting(headers! {
+                Subject: "second",
+                Comments: "second comment",
+                _From: ["from@example.com"]
+            }?);
+
+            let replaced = report.replaced.iter().map(|name| name.as_str()).collect::<Vec<_>>();
+            assert_eq!(replaced, vec!["Subject"]);
+            assert!(report.dropped.is_empty());
+            assert_eq!(mail.subject(), Some("second"));
+        });
+
+        test!(insert_headers_reporting_reports_nothing_for_new_headers, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r0", &ctx);
+
+            let report = mail.insert_headers_reporting(headers! {
+                Subject: "first",
+                _From: ["from@example.com"]
+            }?);
+
+            assert_eq!(report, InsertHeadersReport::default());
+        });
+
+        test!(subject_returns_set_subject, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r0", &ctx);
+            assert_eq!(mail.subject(), None);
+
+            mail.set_subject("the subject")?;
+            assert_eq!(mail.subject(), Some("the subject"));
+
+            mail.set_subject(String::from("another subject"))?;
+            assert_eq!(mail.subject(), Some("another subject"));
+        });
+
+    }
+
+    mod EncodableMail {
+        #![allow(non_snake_case)]
+        use chrono::{Utc, TimeZone};
+        use headers::{
+            headers::{
+                _From, _To, Cc, Bcc, Sender,
+                ContentType, ContentTransferEncoding,
+                Date, Subject
+            },
+            header_components::{TransferEncoding, Disposition}
+        };
+        use default_impl::{
+            test_context, test_context_allowing_domains, configurable_test_context, test_source
+        };
+        use super::super::*;
+        use super::{AssertDebug, AssertSend, AssertSync};
+
+        impl AssertDebug for EncodableMail {}
+        impl AssertSend for EncodableMail {}
+        impl AssertSync for EncodableMail {}
+
+        #[test]
+        fn sets_generated_headers_for_outer_mail() {
+            let ctx = test_context();
+            let resource = Resource::plain_text("r9", &ctx);
+            let mail = Mail {
+                headers: headers!{
+                    _From: ["random@this.is.no.mail"],
+                    Subject: "hoho"
+                }.unwrap(),
+                body: MailBody::SingleBody { body: resource },
+                default_message_id: None
+            };
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let headers: &HeaderMap = enc_mail.headers();
+            assert!(headers.contains(_From));
+            assert!(headers.contains(Subject));
+            assert!(headers.contains(Date));
+            // ContenType/TransferEncoding are added on the fly when encoding
+            // for leaf bodies
+            assert_not!(headers.contains(ContentType));
+            assert_not!(headers.contains(ContentTransferEncoding));
+            assert!(headers.contains(MessageId));
+            assert_eq!(headers.len(), 4);
         }
 
         #[test]
@@ -832,11 +2903,13 @@ mod test {
                     bodies: vec![
                         Mail {
                             headers: HeaderMap::new(),
-                            body: MailBody::SingleBody { body: resource }
+                            body: MailBody::SingleBody { body: resource },
+                            default_message_id: None
                         }
                     ],
                     hidden_text: Default::default()
-                }
+                },
+                default_message_id: None
             };
 
             let mail = mail.into_encodable_mail(ctx).wait().unwrap();
@@ -863,7 +2936,8 @@ mod test {
                     _From: ["random@this.is.no.mail", "u.p.s@s.p.u"],
                     Subject: "hoho"
                 }.unwrap(),
-                body: MailBody::SingleBody { body: Resource::plain_text("r9", &ctx) }
+                body: MailBody::SingleBody { body: Resource::plain_text("r9", &ctx) },
+                default_message_id: None
             };
 
             assert_err!(mail.into_encodable_mail(ctx).wait());
@@ -876,7 +2950,8 @@ mod test {
                 headers: headers!{
                     Subject: "hoho"
                 }.unwrap(),
-                body: MailBody::SingleBody { body: Resource::plain_text("r9", &ctx) }
+                body: MailBody::SingleBody { body: Resource::plain_text("r9", &ctx) },
+                default_message_id: None
             };
 
             assert_err!(mail.into_encodable_mail(ctx).wait());
@@ -901,6 +2976,1026 @@ mod test {
             assert_eq!(&**used_date.body(), &provided_date);
         });
 
+        test!(set_date_is_not_overridden, {
+            let ctx = test_context();
+            let provided_date = Utc.ymd(2011, 3, 7).and_hms(8, 15, 0);
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            mail.set_date(DateTime::new(provided_date.clone()));
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let used_date = enc_mail.headers()
+                .get_single(Date)
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(&**used_date.body(), &provided_date);
+        });
+
+        test!(set_date_checked_accepts_sane_date, {
+            let ctx = test_context();
+            let provided_date = Utc.ymd(1992, 5, 25).and_hms(23, 41, 12);
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            mail.set_date_checked(DateTime::new(provided_date.clone()))?;
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let used_date = enc_mail.headers()
+                .get_single(Date)
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(&**used_date.body(), &provided_date);
+        });
+
+        #[test]
+        fn set_date_checked_rejects_absurd_dates() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+
+            let too_old = Utc.ymd(1800, 1, 1).and_hms(0, 0, 0);
+            assert_err!(mail.set_date_checked(DateTime::new(too_old)));
+
+            let too_new = Utc::now() + chrono::Duration::days(400);
+            assert_err!(mail.set_date_checked(DateTime::new(too_new)));
+
+            assert_not!(mail.headers().contains(Date));
+        }
+
+        test!(emit_content_md5_adds_digest_of_the_decoded_body, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("abc", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let options = EncodeOptions { emit_content_md5: true, ..Default::default() };
+            let enc_mail = assert_ok!(mail.into_encodable_mail_with_options(ctx, options).wait());
+            assert!(enc_mail.headers().contains(additional_headers::ContentMd5));
+
+            let encoded = String::from_utf8(enc_mail.encode_into_bytes(MailType::Ascii)?).unwrap();
+            // base64 of the md5 digest of "abc"
+            assert!(encoded.contains("Content-MD5: kAFQmDzST7DWlj99KOF/cg==\r\n"));
+        });
+
+        test!(lenient_encoding_skips_inline_bodies_which_failed_to_load, {
+            let ctx = test_context();
+            let mut image = Mail::new_singlepart_mail(
+                Resource::Source(test_source("path:this/does/not/exist.png")));
+            image.insert_header(ContentDisposition::body(
+                Disposition::new(DispositionKind::Inline, Default::default())));
+
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/related").unwrap(),
+                vec![ Mail::plain_text("r9", &ctx), image ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            assert_err!(mail.clone().into_encodable_mail(ctx.clone()).wait());
+
+            let enc_mail = assert_ok!(
+                mail.into_encodable_mail_lenient(ctx, FailurePolicy::SkipInline).wait());
+
+            if let MailBody::MultipleBodies { ref bodies, .. } = *enc_mail.body() {
+                assert_eq!(bodies.len(), 1);
+                assert_not!(bodies[0].headers().contains(ContentDisposition));
+            } else {
+                unreachable!()
+            }
+            assert_ok!(enc_mail.encode_into_bytes(MailType::Ascii));
+        });
+
+        test!(lenient_encoding_removes_cid_references_to_skipped_bodies, {
+            let ctx = test_context();
+            let content_id = ctx.generate_content_id();
+            let mut image = Mail::new_singlepart_mail(
+                Resource::Source(test_source("path:this/does/not/exist.png")));
+            image.insert_header(ContentDisposition::body(
+                Disposition::new(DispositionKind::Inline, Default::default())));
+            image.insert_header(ContentId::body(content_id.clone()));
+
+            let html = format!(
+                "<p><img src=\"cid:{cid}\"><img src=\"cid:{cid}.2\"></p>",
+                cid=content_id.as_str());
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/related").unwrap(),
+                vec![ Mail::html_text(html, &ctx), image ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let enc_mail = assert_ok!(
+                mail.into_encodable_mail_lenient(ctx, FailurePolicy::SkipInline).wait());
+
+            if let MailBody::MultipleBodies { ref bodies, .. } = *enc_mail.body() {
+                assert_eq!(bodies.len(), 1);
+                let html = match *bodies[0].body() {
+                    MailBody::SingleBody { ref body } => assume_encoded(body).decoded_buffer()?,
+                    _ => unreachable!()
+                };
+                let expected = format!("<p><img src=\"cid:{}.2\"></p>", content_id.as_str());
+                assert_eq!(String::from_utf8(html).unwrap(), expected);
+            } else {
+                unreachable!()
+            }
+        });
+
+        #[test]
+        fn remove_cid_reference_removes_img_elements_and_other_references() {
+            let html = concat!(
+                "<p><IMG alt=\"logo\" src=\"cid:logo@x.test\"/><img src='cid:logo@x.test2'>",
+                "<div style=\"background: url(cid:logo@x.test)\">text</div></p>");
+
+            let cleaned = remove_cid_reference(html.as_bytes(), "logo@x.test");
+
+            assert_eq!(
+                String::from_utf8(cleaned).unwrap(),
+                "<p><img src='cid:logo@x.test2'><div style=\"background: url()\">text</div></p>");
+        }
+
+        test!(mime_version_can_be_changed_or_omitted, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let options = EncodeOptions { mime_version: Some("1.1".into()), ..Default::default() };
+            let enc_mail = assert_ok!(mail.clone().into_encodable_mail_with_options(ctx.clone(), options).wait());
+            let encoded = String::from_utf8(enc_mail.encode_into_bytes(MailType::Ascii)?).unwrap();
+            assert!(encoded.starts_with("MIME-Version: 1.1\r\n"));
+
+            let options = EncodeOptions { mime_version: None, ..Default::default() };
+            let enc_mail = assert_ok!(mail.into_encodable_mail_with_options(ctx, options).wait());
+            let encoded = String::from_utf8(enc_mail.encode_into_bytes(MailType::Ascii)?).unwrap();
+            assert_not!(encoded.contains("MIME-Version"));
+        });
+
+        test!(invalid_mime_version_is_rejected, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let options = EncodeOptions { mime_version: Some(String::from("1.0\r\nX-Injected: yes").into()), ..Default::default() };
+            let enc_mail = assert_ok!(mail.into_encodable_mail_with_options(ctx, options).wait());
+            let err = assert_err!(enc_mail.encode_into_bytes(MailType::Ascii));
+            if let MailError::Validation(_) = err {
+            } else {
+                panic!("unexpected error: {:?}", err);
+            }
+        });
+
+        fn mailbox(email: &str) -> Mailbox {
+            Mailbox::from(::headers::header_components::Email::new(email).unwrap())
+        }
+
+        test!(smtp_envelope_uses_sender_and_all_recipients, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["from1@example.com", "from2@example.com"],
+                Sender: "sender@example.com",
+                _To: ["to1@example.com", "to2@example.com"],
+                Cc: ["to2@example.com", "cc@example.com"]
+            }?);
+            mail.insert_header(Bcc::body(OptMailboxList(vec![mailbox("bcc@example.com")])));
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let (reverse_path, forward_paths) = enc_mail.smtp_envelope()?;
+
+            assert_eq!(reverse_path, mailbox("sender@example.com"));
+            assert_eq!(forward_paths.to_vec(), vec![
+                mailbox("to1@example.com"),
+                mailbox("to2@example.com"),
+                mailbox("cc@example.com"),
+                mailbox("bcc@example.com")
+            ]);
+        });
+
+        test!(bcc_header_is_not_encoded, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["from@example.com"],
+                _To: ["to@example.com"]
+            }?);
+            mail.insert_header(Bcc::body(OptMailboxList(vec![mailbox("bcc@example.com")])));
+            mail.generally_validate_mail()?;
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = String::from_utf8(enc_mail.encode_into_bytes(MailType::Ascii)?).unwrap();
+
+            assert!(encoded.contains("To: "));
+            assert_not!(encoded.contains("Bcc:"));
+            assert_not!(encoded.contains("bcc@example.com"));
+            assert!(enc_mail.headers().contains(Bcc));
+        });
+
+        test!(smtp_envelope_falls_back_to_first_from_mailbox, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["from@example.com"],
+                _To: ["to@example.com"]
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let (reverse_path, forward_paths) = enc_mail.smtp_envelope()?;
+
+            assert_eq!(reverse_path, mailbox("from@example.com"));
+            assert_eq!(forward_paths.to_vec(), vec![mailbox("to@example.com")]);
+        });
+
+        test!(smtp_envelope_fails_without_recipients, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["from@example.com"]
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            assert_err!(enc_mail.smtp_envelope());
+        });
+
+        test!(cloned_mails_encode_to_the_same_bytes, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let cloned = enc_mail.clone();
+
+            assert_eq!(
+                cloned.encode_into_bytes(MailType::Ascii)?,
+                enc_mail.encode_into_bytes(MailType::Ascii)?
+            );
+            assert_eq!(
+                cloned.encode_into_bytes(MailType::Mime8BitEnabled)?,
+                enc_mail.encode_into_bytes(MailType::Mime8BitEnabled)?
+            );
+        });
+
+        test!(encoded_size_is_the_length_of_the_encoded_mail, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let size = enc_mail.encoded_size(MailType::Ascii)?;
+            assert_eq!(size, enc_mail.encode_into_bytes(MailType::Ascii)?.len());
+        });
+
+        test!(encoded_size_of_multipart_mail_is_the_length_of_the_encoded_mail, {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::plain_text("r1", &ctx),
+                    Mail::plain_text("r2", &ctx)
+                ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let size = enc_mail.encoded_size(MailType::Ascii)?;
+            assert_eq!(size, enc_mail.encode_into_bytes(MailType::Ascii)?.len());
+        });
+
+        test!(auto_submitted_is_encoded, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                Subject: "hoho"
+            }?);
+            mail.set_auto_submitted(AutoSubmitted::AutoGenerated);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = enc_mail.encode_into_bytes(MailType::Ascii)?;
+            let encoded = String::from_utf8(encoded).unwrap();
+
+            assert!(encoded.contains("Auto-Submitted: auto-generated\r\n"));
+        });
+
+        test!(priority_headers_are_encoded, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            mail.set_priority(Priority::Low);
+            mail.set_priority(Priority::High);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = enc_mail.encode_into_bytes(MailType::Ascii)?;
+            let encoded = String::from_utf8(encoded).unwrap();
+
+            assert!(encoded.contains("X-Priority: 1\r\n"));
+            assert!(encoded.contains("Importance: high\r\n"));
+            assert!(encoded.contains("X-MSMail-Priority: High\r\n"));
+            assert_not!(encoded.contains("X-Priority: 5"));
+        });
+
+        test!(encode_into_string_encodes_ascii_mail, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                Subject: "hoho"
+            }?);
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let string = enc_mail.encode_into_string()?;
+            assert_eq!(string.as_bytes(), &*enc_mail.encode_into_bytes(MailType::Ascii)?);
+            assert!(string.contains("Subject: hoho\r\n"));
+        });
+
+        test!(message_id_uses_the_from_domain, {
+            let ctx = test_context_allowing_domains(&["this.is.no.mail"]);
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let message_id = enc_mail.headers().get_single(MessageId).unwrap()?;
+            assert!(message_id.as_str().ends_with("@this.is.no.mail"));
+        });
+
+        test!(message_id_does_not_use_a_not_allowed_from_domain, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let message_id = enc_mail.headers().get_single(MessageId).unwrap()?;
+            assert!(message_id.as_str().ends_with("@fooblabar.test"));
+        });
+
+        test!(encode_to_writer_writes_the_encoded_mail, {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                Subject: "hoho"
+            }?);
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let mut out = Vec::new();
+            enc_mail.encode_to_writer(MailType::Ascii, &mut out)?;
+            assert_eq!(out, enc_mail.encode_into_bytes(MailType::Ascii)?);
+        });
+
+        test!(encode_to_writer_writes_multipart_mails_part_by_part, {
+            struct CountingWriter {
+                out: Vec<u8>,
+                writes: usize
+            }
+
+            impl io::Write for CountingWriter {
+                fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                    self.writes += 1;
+                    self.out.write(buf)
+                }
+
+                fn flush(&mut self) -> io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::plain_text("r1", &ctx),
+                    Mail::plain_text("r2", &ctx)
+                ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let mut writer = CountingWriter { out: Vec::new(), writes: 0 };
+            enc_mail.encode_to_writer(MailType::Ascii, &mut writer)?;
+            assert_eq!(writer.out, enc_mail.encode_into_bytes(MailType::Ascii)?);
+            assert_eq!(writer.writes, 3);
+        });
+
+        #[test]
+        fn required_mail_type_of_ascii_mail_is_ascii() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            assert_eq!(assert_ok!(enc_mail.required_mail_type()), MailType::Ascii);
+            assert_eq!(
+                assert_ok!(enc_mail.encode_auto()),
+                assert_ok!(enc_mail.encode_into_bytes(MailType::Ascii))
+            );
+        }
+
+        #[test]
+        fn required_mail_type_respects_8bit_bodies() {
+            let ctx = test_context();
+            let data = Data::plain_text("Grüße", ctx.generate_content_id());
+            let enc_data = EncData::new(
+                data.buffer().clone(), data.metadata().clone(), TransferEncoding::_8Bit);
+            let mut mail = Mail::new_singlepart_mail(Resource::EncData(enc_data));
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            assert_eq!(assert_ok!(enc_mail.required_mail_type()), MailType::Mime8BitEnabled);
+            assert_ok!(enc_mail.encode_auto());
+        }
+
+        #[test]
+        fn encode_auto_returns_the_encoding_error() {
+            let ctx = test_context();
+            let mut buffer = vec![b'a'; 1000];
+            buffer.extend_from_slice("Grüße".as_bytes());
+            let data = Data::plain_text(String::from_utf8(buffer).unwrap(), ctx.generate_content_id());
+            let enc_data = EncData::new(
+                data.buffer().clone(), data.metadata().clone(), TransferEncoding::_8Bit);
+            let mut mail = Mail::new_singlepart_mail(Resource::EncData(enc_data));
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            assert_eq!(assert_ok!(enc_mail.required_mail_type()), MailType::Mime8BitEnabled);
+            let err = assert_err!(enc_mail.encode_auto());
+            if let MailError::Encoding(_) = err {
+            } else {
+                panic!("unexpected error: {:?}", err);
+            }
+        }
+
+        #[test]
+        fn required_mail_type_respects_internationalized_headers() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                _To: ["jörg@example.com"]
+            }.unwrap());
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            assert_eq!(assert_ok!(enc_mail.required_mail_type()), MailType::Internationalized);
+        }
+
+        #[test]
+        fn encode_into_string_fails_for_non_ascii_mails() {
+            let ctx = test_context();
+            let data = Data::plain_text("Grüße", ctx.generate_content_id());
+            let enc_data = EncData::new(
+                data.buffer().clone(), data.metadata().clone(), TransferEncoding::_8Bit);
+            let mut mail = Mail::new_singlepart_mail(Resource::EncData(enc_data));
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            assert_err!(enc_mail.encode_into_string());
+        }
+
+        #[test]
+        fn binary_bodies_require_a_8bit_mail_type() {
+            let ctx = test_context();
+            let data = Data::plain_text("Grüße", ctx.generate_content_id());
+            let enc_data = EncData::new(
+                data.buffer().clone(), data.metadata().clone(), TransferEncoding::Binary);
+            let mut mail = Mail::new_singlepart_mail(Resource::EncData(enc_data));
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let err = assert_err!(enc_mail.encode_into_bytes(MailType::Ascii));
+            match err {
+                MailError::Validation(HeaderValidationError::Custom(ref err)) => {
+                    match err.downcast_ref::<OtherValidationError>() {
+                        Some(&OtherValidationError::TransferEncodingRequires8Bit { ref encoding }) => {
+                            assert_eq!(encoding, "binary");
+                        },
+                        _ => panic!("unexpected error: {:?}", err)
+                    }
+                },
+                err => panic!("unexpected error: {:?}", err)
+            }
+            assert_ok!(enc_mail.encode_into_bytes(MailType::Mime8BitEnabled));
+        }
+
+        #[test]
+        fn multipart_mail_without_content_type_is_rejected() {
+            let ctx = test_context();
+            let mut mail = Mail {
+                headers: HeaderMap::new(),
+                body: MailBody::multiple(vec![ Mail::plain_text("r1", &ctx) ]),
+                default_message_id: None
+            };
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+
+            let err = assert_err!(mail.into_encodable_mail(ctx).wait());
+            if let MailError::Validation(_) = err {
+            } else {
+                panic!("unexpected error: {:?}", err);
+            }
+        }
+
+        test!(preset_boundary_is_used, {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![ Mail::plain_text("r1", &ctx) ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            mail.set_boundary(SoftAsciiString::from_unchecked("snapshot-boundary"))?;
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = enc_mail.encode_into_string()?;
+            assert!(encoded.contains("boundary=snapshot-boundary\r\n"));
+            assert!(encoded.contains("\r\n--snapshot-boundary\r\n"));
+            assert!(encoded.contains("\r\n--snapshot-boundary--\r\n"));
+        });
+
+        test!(hidden_text_is_written_before_the_first_boundary, {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![ Mail::plain_text("r1", &ctx) ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            mail.set_boundary(SoftAsciiString::from_unchecked("snapshot-boundary"))?;
+            mail.set_hidden_text(SoftAsciiString::from_unchecked("This is a MIME mail.\r\nUpgrade!"))?;
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = enc_mail.encode_into_string()?;
+            let preamble_pos = encoded.find("\r\n\r\nThis is a MIME mail.\r\nUpgrade!\r\n")
+                .expect("preamble missing");
+            let boundary_pos = encoded.find("\r\n--snapshot-boundary\r\n").unwrap();
+            assert!(preamble_pos < boundary_pos);
+        });
+
+        #[test]
+        fn hidden_text_containing_the_boundary_is_rejected() {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![ Mail::plain_text("r1", &ctx) ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+            mail.set_boundary(SoftAsciiString::from_unchecked("snapshot-boundary")).unwrap();
+            mail.set_hidden_text(SoftAsciiString::from_unchecked(
+                "preamble\r\n--snapshot-boundary\r\nContent-Type: text/html\r\n")).unwrap();
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            assert_err!(enc_mail.encode_into_bytes(MailType::Ascii));
+        }
+
+        #[test]
+        fn hidden_text_can_not_be_set_on_singlepart_mails() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r1", &ctx);
+            assert_err!(mail.set_hidden_text(SoftAsciiString::from_unchecked("preamble")));
+        }
+
+        #[test]
+        fn set_attachment_uses_the_given_file_name() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r1", &ctx);
+            assert_ok!(mail.set_attachment(Some("notes ä.txt".to_owned())));
+
+            let disposition = mail.headers().get_single(ContentDisposition).unwrap().unwrap();
+            assert_eq!(disposition.kind(), DispositionKind::Attachment);
+            assert_eq!(disposition.file_meta().file_name, Some("notes ä.txt".to_owned()));
+        }
+
+        #[test]
+        fn set_attachment_falls_back_to_the_resources_file_name() {
+            let ctx = test_context();
+            let resource = Resource::from_bytes_with_name(
+                MediaType::parse("application/octet-stream").unwrap(),
+                b"abc".to_vec(),
+                "data.bin",
+                &ctx
+            );
+            let mut mail = Mail::new_singlepart_mail(resource);
+            assert_ok!(mail.set_attachment(None));
+
+            let disposition = mail.headers().get_single(ContentDisposition).unwrap().unwrap();
+            assert_eq!(disposition.file_meta().file_name, Some("data.bin".to_owned()));
+        }
+
+        #[test]
+        fn set_attachment_fails_for_multipart_mails() {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![ Mail::plain_text("r1", &ctx) ]
+            );
+            assert_err!(mail.set_attachment(None));
+        }
+
+        #[test]
+        fn set_boundary_rejects_invalid_boundaries() {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![ Mail::plain_text("r1", &ctx) ]
+            );
+            assert_err!(mail.set_boundary(SoftAsciiString::from_unchecked("has\"quote")));
+            assert_err!(mail.set_boundary(SoftAsciiString::from_unchecked("a".repeat(71))));
+
+            let mut mail = Mail::plain_text("r1", &ctx);
+            assert_err!(mail.set_boundary(SoftAsciiString::from_unchecked("simple")));
+        }
+
+        test!(export_parts_writes_all_bodies, {
+            let ctx = test_context();
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::plain_text("r1", &ctx),
+                    Mail::plain_text("r2", &ctx)
+                ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }?);
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+
+            let dir = ::std::env::temp_dir()
+                .join(format!("mail-core-export-parts-{}", ::rand::random::<u32>()));
+            let paths = enc_mail.export_parts(&dir)?;
+
+            assert_eq!(paths, vec![
+                dir.join("part0.txt"),
+                dir.join("part1.txt"),
+                dir.join("headers.txt")
+            ]);
+            assert_eq!(fs::read(&paths[0])?, b"r1".to_vec());
+            assert_eq!(fs::read(&paths[1])?, b"r2".to_vec());
+            let manifest = String::from_utf8(fs::read(&paths[2])?).unwrap();
+            assert!(manifest.contains("From: "));
+            assert!(manifest.contains("random@this.is.no.mail"));
+            assert!(!manifest.contains("MailboxList"));
+            assert!(manifest.contains("Content-Type: text/plain; charset=utf-8\n"));
+            assert!(manifest.contains("=> part1.txt"));
+
+            fs::remove_dir_all(&dir)?;
+        });
+
+        #[test]
+        fn generated_date_uses_context_now() {
+            let date = DateTime::new(Utc.ymd(2013, 8, 6).and_hms(7, 11, 0));
+            let mut ctx = configurable_test_context();
+            ctx.now = Some(date.clone());
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail"]
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let used_date = enc_mail.headers()
+                .get_single(Date)
+                .unwrap()
+                .unwrap();
+            assert_eq!(&**used_date.body(), &*date);
+        }
+
+        fn sender_line(enc_mail: &EncodableMail) -> Option<String> {
+            enc_mail.encode_into_string().unwrap()
+                .lines()
+                .find(|line| line.starts_with("Sender:"))
+                .map(|line| line.to_owned())
+        }
+
+        #[test]
+        fn sender_is_not_added_for_single_from_mailbox() {
+            let mut ctx = configurable_test_context();
+            ctx.auto_insert_sender = true;
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail"]
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            assert_not!(enc_mail.headers().contains(Sender));
+        }
+
+        #[test]
+        fn explicit_sender_is_kept() {
+            let mut ctx = configurable_test_context();
+            ctx.auto_insert_sender = true;
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail", "second@this.is.no.mail"],
+                Sender: "second@this.is.no.mail"
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let line = sender_line(&enc_mail).unwrap();
+            assert!(line.contains("second@this.is.no.mail"));
+        }
+
+        #[test]
+        fn sender_is_added_for_multiple_from_mailboxes() {
+            let mut ctx = configurable_test_context();
+            ctx.auto_insert_sender = true;
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail", "second@this.is.no.mail"]
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let line = sender_line(&enc_mail).unwrap();
+            assert!(line.contains("first@this.is.no.mail"));
+        }
+
+        #[test]
+        fn generated_headers_contain_auto_inserted_sender() {
+            let mut ctx = configurable_test_context();
+            ctx.auto_insert_sender = true;
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail", "second@this.is.no.mail"]
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            assert!(enc_mail.generated_headers().contains(Sender));
+        }
+
+        #[test]
+        fn generated_headers_contain_only_generated_headers() {
+            let ctx = test_context();
+            let provided_date = Utc.ymd(1992, 5, 25).and_hms(23, 41, 12);
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail"],
+                Subject: "hy",
+                Date: provided_date
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let generated = enc_mail.generated_headers();
+            assert!(generated.contains(MessageId));
+            assert!(generated.contains(ContentType));
+            assert!(generated.contains(ContentTransferEncoding));
+            assert_not!(generated.contains(Date));
+            assert_not!(generated.contains(Subject));
+            assert_not!(generated.contains(_From));
+            assert_not!(generated.contains(Sender));
+        }
+
+        #[test]
+        fn sender_is_not_added_by_default() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r1", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["first@this.is.no.mail", "second@this.is.no.mail"]
+            }.unwrap());
+
+            assert_err!(mail.into_encodable_mail(ctx).wait());
+        }
+
+        #[test]
+        fn boundaries_are_generated_by_the_context() {
+            let mut ctx = configurable_test_context();
+            ctx.generate_boundary = Some(|count| {
+                SoftAsciiString::from_unchecked(format!("fixed-{}", count))
+            });
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::new_multipart_mail(
+                        MediaType::parse("multipart/alternative").unwrap(),
+                        vec![ Mail::plain_text("r1", &ctx) ]
+                    )
+                ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"]
+            }.unwrap());
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = enc_mail.encode_into_string().unwrap();
+            assert!(encoded.contains("\r\n--fixed-0\r\n"));
+            assert!(encoded.contains("\r\n--fixed-1\r\n"));
+        }
+
+        #[test]
+        fn from_domain_returns_domain_of_first_mailbox() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            assert!(mail.from_domain().is_none());
+            mail.insert_headers(headers! {
+                _From: ["someone@allowed.test", "other@not.allowed.test"]
+            }.unwrap());
+            assert_eq!(mail.from_domain(), Some(&Domain::from_unchecked("allowed.test".to_owned())));
+        }
+
+        #[test]
+        fn from_domain_in_allowed_domains_is_accepted() {
+            let mut ctx = configurable_test_context();
+            ctx.allowed_from_domains = Some(Arc::new(vec![Domain::from_unchecked("allowed.test".to_owned())]));
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["someone@allowed.test"]
+            }.unwrap());
+            assert_ok!(mail.into_encodable_mail(ctx).wait());
+        }
+
+        #[test]
+        fn from_domain_not_in_allowed_domains_is_rejected() {
+            let mut ctx = configurable_test_context();
+            ctx.allowed_from_domains = Some(Arc::new(vec![Domain::from_unchecked("allowed.test".to_owned())]));
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["someone@allowed.test", "other@not.allowed.test"]
+            }.unwrap());
+            assert_err!(mail.into_encodable_mail(ctx).wait());
+        }
+
+        #[test]
+        fn from_domains_are_compared_ascii_case_insensitive() {
+            let mut ctx = configurable_test_context();
+            ctx.allowed_from_domains = Some(Arc::new(vec![Domain::from_unchecked("Allowed.Test".to_owned())]));
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["someone@allowed.TEST"]
+            }.unwrap());
+            assert_ok!(mail.into_encodable_mail(ctx).wait());
+        }
+
+        #[test]
+        fn not_allowed_from_domain_error_contains_the_domain() {
+            let mut ctx = configurable_test_context();
+            ctx.allowed_from_domains = Some(Arc::new(vec![Domain::from_unchecked("allowed.test".to_owned())]));
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["other@not.allowed.test"]
+            }.unwrap());
+            let err = assert_err!(mail.into_encodable_mail(ctx).wait());
+            match err {
+                MailError::Validation(HeaderValidationError::Custom(ref err)) => {
+                    match err.downcast_ref::<OtherValidationError>() {
+                        Some(&OtherValidationError::FromDomainNotAllowed { ref domain }) => {
+                            assert_eq!(domain, "not.allowed.test");
+                        },
+                        _ => panic!("unexpected error: {:?}", err)
+                    }
+                },
+                err => panic!("unexpected error: {:?}", err)
+            }
+        }
+
+        #[test]
+        fn any_from_domain_is_accepted_without_allowed_domains() {
+            let ctx = test_context();
+            let mut mail = Mail::plain_text("r9", &ctx);
+            mail.insert_headers(headers! {
+                _From: ["other@not.allowed.test"]
+            }.unwrap());
+            assert_ok!(mail.into_encodable_mail(ctx).wait());
+        }
+
+        test!(content_location_can_be_used_in_sub_bodies, {
+            let ctx = test_context();
+            let mut sub_body = Mail::plain_text("r9", &ctx);
+            sub_body.set_content_location(&IRI::new("http://example.test/text.txt").unwrap())?;
+
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![sub_body]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                Subject: "hoho"
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            let encoded = enc_mail.encode_into_bytes(MailType::Ascii)?;
+            let encoded = String::from_utf8(encoded).unwrap();
+
+            let first_boundary = encoded.find("\r\n--").unwrap();
+            let location = encoded.find("Content-Location: http://example.test/text.txt\r\n")
+                .expect("Content-Location header is missing");
+            assert!(location > first_boundary);
+        });
+
+        test!(uses_transfer_encoding_hints_of_bodies, {
+            let ctx = test_context();
+            let text = Data::plain_text("Hy there", ctx.generate_content_id())
+                .with_transfer_encoding_hint(TransferEncodingHint::Use7Bit);
+            let html = Data::new("<p>Hy there</p>".as_bytes().to_vec(), Metadata {
+                file_meta: Default::default(),
+                media_type: MediaType::parse("text/html; charset=utf-8").unwrap(),
+                content_id: ctx.generate_content_id()
+            }).with_transfer_encoding_hint(TransferEncodingHint::UseQuotedPrintable);
+
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/alternative").unwrap(),
+                vec![
+                    Mail::new_singlepart_mail(Resource::Data(text)),
+                    Mail::new_singlepart_mail(Resource::Data(html))
+                ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                Subject: "hoho"
+            }?);
+
+            let enc_mail = assert_ok!(mail.into_encodable_mail(ctx).wait());
+            if let MailBody::MultipleBodies { ref bodies, .. } = *enc_mail.body() {
+                let encodings = bodies.iter()
+                    .map(|body| match *body.body() {
+                        MailBody::SingleBody { ref body } => assume_encoded(body).encoding(),
+                        _ => unreachable!()
+                    })
+                    .collect::<Vec<_>>();
+
+                assert_eq!(encodings, vec![
+                    TransferEncoding::_7Bit,
+                    TransferEncoding::QuotedPrintable
+                ]);
+            } else {
+                unreachable!()
+            }
+        });
+
+        test!(force_base64_bodies_encodes_all_bodies_with_base64, {
+            let ctx = test_context();
+            let text = Data::plain_text("Hy there", ctx.generate_content_id())
+                .with_transfer_encoding_hint(TransferEncodingHint::Use7Bit);
+            let already_encoded = Data::plain_text("Hy there", ctx.generate_content_id())
+                .transfer_encode(TransferEncodingHint::UseQuotedPrintable);
+
+            let mut mail = Mail::new_multipart_mail(
+                MediaType::parse("multipart/mixed").unwrap(),
+                vec![
+                    Mail::new_singlepart_mail(Resource::Data(text)),
+                    Mail::new_singlepart_mail(Resource::EncData(already_encoded))
+                ]
+            );
+            mail.insert_headers(headers! {
+                _From: ["random@this.is.no.mail"],
+                Subject: "hoho"
+            }?);
+
+            let options = EncodeOptions { force_base64_bodies: true, ..Default::default() };
+            let enc_mail = assert_ok!(mail.into_encodable_mail_with_options(ctx, options).wait());
+            assert!(enc_mail.encode_options().force_base64_bodies);
+
+            if let MailBody::MultipleBodies { ref bodies, .. } = *enc_mail.body() {
+                for body in bodies {
+                    match *body.body() {
+                        MailBody::SingleBody { ref body } => {
+                            let enc_data = assume_encoded(body);
+                            assert_eq!(enc_data.encoding(), TransferEncoding::Base64);
+                            assert_eq!(enc_data.decoded_buffer()?, b"Hy there".to_vec());
+                        },
+                        _ => unreachable!()
+                    }
+                }
+            } else {
+                unreachable!()
+            }
+
+            let encoded = String::from_utf8(enc_mail.encode_into_bytes(MailType::Ascii)?).unwrap();
+            assert_eq!(encoded.matches("Content-Transfer-Encoding: base64").count(), 2);
+        });
+
     }
 
 }
\ No newline at end of file
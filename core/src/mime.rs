@@ -1,5 +1,12 @@
 //! Module containing some utilities for MIME usage/creation.
 use rand::{self, Rng};
+use soft_ascii_string::SoftAsciiString;
+
+use media_type::MULTIPART;
+use headers::{
+    header_components::MediaType,
+    error::ComponentCreationError
+};
 
 
 
@@ -25,6 +32,21 @@ static BOUNDARY_CHARS: &[char] = &[
 /// Prevent collisions with Base64/Quoted-Printable
 static ANTI_COLLISION_CHARS: &str = "=_^";
 
+/// The maximal length of a boundary as defined in rfc2046.
+const BOUNDARY_MAX_LENGTH: usize = 70;
+
+/// Returns true if the given boundary is valid wrt. rfc2046.
+///
+/// I.e. it is between 1 and 70 chars long, only consists of
+/// `bchars` and does not end with a `' '`. Note that this does
+/// not check if the boundary needs to be quoted.
+pub fn is_valid_boundary(boundary: &str) -> bool {
+    !boundary.is_empty()
+        && boundary.len() <= BOUNDARY_MAX_LENGTH
+        && !boundary.ends_with(' ')
+        && boundary.chars().all(|ch| ch == ' ' || BOUNDARY_CHARS.contains(&ch))
+}
+
 /// Generate a boundary from a counter, "=_^" and a random sequence of boundary chars.
 ///
 /// # Usage Note
@@ -74,6 +96,42 @@ pub fn create_structured_random_boundary(count: usize) -> String {
     out
 }
 
+/// Generate a random boundary, e.g. for building a multipart body by hand.
+///
+/// This is `create_structured_random_boundary(0)`, i.e. the boundary starts
+/// with `=_^` (which can't appear in base64 or quoted-printable encoded
+/// bodies) and is 66 chars long. The random part makes collisions between
+/// (nested) boundaries created this way practically impossible.
+///
+/// _Be aware that it might be required to quote the boundary._
+pub fn create_random_boundary() -> SoftAsciiString {
+    SoftAsciiString::from_unchecked(create_structured_random_boundary(0))
+}
+
+/// The `tspecials` of rfc2045, which (together with space and CTLs) can't be used in a token.
+static TSPECIALS: &str = "()<>@,;:\\\"/[]?=";
+
+/// Returns true if the given string is a valid token wrt. rfc2045.
+pub(crate) fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text.chars().all(|ch| ch.is_ascii() && !ch.is_ascii_control()
+            && ch != ' ' && !TSPECIALS.contains(ch))
+}
+
+/// Creates a `multipart/<subtype>` media type (without a boundary parameter).
+///
+/// # Error
+///
+/// Returns a `ComponentCreationError` (for the component `"MediaType"`)
+/// if the subtype is not a valid token wrt. rfc2045, e.g. if it contains
+/// a space or a `/`.
+pub fn gen_multipart_media_type(subtype: &str) -> Result<MediaType, ComponentCreationError> {
+    if !is_token(subtype) {
+        return Err(ComponentCreationError::new_with_str("MediaType", subtype));
+    }
+    MediaType::new(MULTIPART, subtype)
+        .map_err(|_| ComponentCreationError::new_with_str("MediaType", subtype))
+}
 
 #[cfg(test)]
 mod test {
@@ -102,6 +160,12 @@ mod test {
             assert!(out.len() > 22 && out.len() <= MULTIPART_BOUNDARY_MAX_LENGTH);
         }
 
+        #[test]
+        fn generated_boundary_is_valid() {
+            let out = create_structured_random_boundary(12);
+            assert!(is_valid_boundary(&out));
+        }
+
         #[test]
         fn boundary_does_not_contain_space_or_slach_or_quotes() {
             // while it could contain them it's recommended not to do it
@@ -118,4 +182,59 @@ mod test {
             assert_ne!(out.as_bytes()[out.len()-1], b' ');
         }
     }
+
+    mod create_random_boundary {
+        use super::super::*;
+
+        #[test]
+        fn boundaries_are_distinct_and_have_max_length() {
+            let first = create_random_boundary();
+            let second = create_random_boundary();
+            assert_ne!(first, second);
+            assert!(first.as_str().starts_with("=_^"));
+            assert_eq!(first.len(), MULTIPART_BOUNDARY_MAX_LENGTH);
+            assert_eq!(second.len(), MULTIPART_BOUNDARY_MAX_LENGTH);
+            assert!(is_valid_boundary(first.as_str()));
+        }
+    }
+
+    mod gen_multipart_media_type {
+        use super::super::*;
+
+        #[test]
+        fn accepts_valid_subtypes() {
+            let media_type = gen_multipart_media_type("x.made-up-thing").unwrap();
+            assert_eq!(media_type.as_str_repr(), "multipart/x.made-up-thing");
+        }
+
+        #[test]
+        fn rejects_subtypes_with_spaces() {
+            assert!(gen_multipart_media_type("my type").is_err());
+        }
+
+        #[test]
+        fn rejects_subtypes_with_slash() {
+            assert!(gen_multipart_media_type("mixed/more").is_err());
+        }
+    }
+
+    mod is_valid_boundary {
+        use super::super::*;
+
+        #[test]
+        fn accepts_valid_boundaries() {
+            assert!(is_valid_boundary("simple"));
+            assert!(is_valid_boundary("with space:and=more?"));
+            assert!(is_valid_boundary(&"a".repeat(70)));
+        }
+
+        #[test]
+        fn rejects_invalid_boundaries() {
+            assert!(!is_valid_boundary(""));
+            assert!(!is_valid_boundary(&"a".repeat(71)));
+            assert!(!is_valid_boundary("ends with space "));
+            assert!(!is_valid_boundary("has\"quote"));
+            assert!(!is_valid_boundary("non-ascii-ä"));
+        }
+    }
 }
\ No newline at end of file
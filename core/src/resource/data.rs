@@ -11,7 +11,15 @@ use serde::{
     de::{Deserializer}
 };
 
-use internals::bind::{base64, quoted_printable};
+#[cfg(feature="charset-transcode")]
+use encoding_rs::{Encoding, UTF_8};
+use media_type::{TEXT, CHARSET};
+
+use internals::{
+    bind::{base64, quoted_printable},
+    error::EncodingError
+};
+use ::default_impl::fix_newlines;
 use headers::header_components::{
     MediaType,
     FileMeta,
@@ -19,6 +27,9 @@ use headers::header_components::{
     ContentId
 };
 
+#[cfg(feature="charset-transcode")]
+use ::error::{ResourceLoadingError, ResourceLoadingErrorKind};
+
 
 
 /// POD type containing FileMeta, Content-Type and Content-Id
@@ -85,7 +96,15 @@ pub struct Data {
     buffer: Arc<[u8]>,
     #[cfg_attr(feature="serde", serde(flatten))]
     #[cfg_attr(feature="serde", serde(with="arc_serde"))]
-    meta: Arc<Metadata>
+    meta: Arc<Metadata>,
+    #[cfg_attr(feature="serde", serde(default="no_transfer_encoding_hint"))]
+    transfer_encoding_hint: TransferEncodingHint
+}
+
+/// The hint used by `Data::new` (`TransferEncodingHint::default` is `UseBase64`).
+#[cfg(feature="serde")]
+fn no_transfer_encoding_hint() -> TransferEncodingHint {
+    TransferEncodingHint::NoHint
 }
 
 
@@ -98,10 +117,36 @@ impl Data {
     ) -> Self {
         Data {
             buffer: buffer.into(),
-            meta: meta.into()
+            meta: meta.into(),
+            transfer_encoding_hint: TransferEncodingHint::NoHint
         }
     }
 
+    /// Returns a version of this instance with the given transfer encoding hint.
+    ///
+    /// The hint is used by the default `Context`/`ResourceLoaderComponent`
+    /// implementations when transfer encoding this data, which allows
+    /// choosing the transfer encoding for specific bodies e.g. using
+    /// quoted-printable for the html body of an `multipart/alternative`
+    /// mail but base64 for all other bodies.
+    ///
+    /// The hint is stored in the data (and not e.g. set per sub-body
+    /// when composing a multipart mail) as bodies are just `Resource`s
+    /// and there is no per body state the encoding could be attached to.
+    /// A `Source` has no hint as its loaded data is created by the
+    /// resource loader, which can set a hint on it if needed.
+    pub fn with_transfer_encoding_hint(mut self, hint: TransferEncodingHint) -> Self {
+        self.transfer_encoding_hint = hint;
+        self
+    }
+
+    /// Returns the transfer encoding hint for this instance.
+    ///
+    /// This is `TransferEncodingHint::NoHint` if no hint was set.
+    pub fn transfer_encoding_hint(&self) -> TransferEncodingHint {
+        self.transfer_encoding_hint
+    }
+
     pub fn plain_text(text: impl Into<String>, cid: ContentId) -> Data {
         let text = text.into();
         let buf = text.into_bytes();
@@ -113,6 +158,49 @@ impl Data {
         Self::new(buf, meta)
     }
 
+    pub fn html_text(html: impl Into<String>, cid: ContentId) -> Data {
+        let html = html.into();
+        let buf = html.into_bytes();
+        let meta = Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse("text/html; charset=utf-8").unwrap(),
+            content_id: cid
+        };
+        Self::new(buf, meta)
+    }
+
+    /// Returns a version of this instance with the media type replaced.
+    ///
+    /// This is useful if the data was created with a generic media type
+    /// like `application/octet-stream` and the actual media type is only
+    /// known later on (e.g. after sniffing it). The buffer is not copied
+    /// and the file meta as well as the content id stay the same.
+    pub fn with_media_type(mut self, media_type: MediaType) -> Self {
+        Arc::make_mut(&mut self.meta).media_type = media_type;
+        self
+    }
+
+    /// Returns a version of this instance with given charset if it is text without charset.
+    ///
+    /// If the media type is a `text/*` type without a `charset` parameter
+    /// the parameter is added with given charset, in all other cases the
+    /// data is returned unchanged. The buffer is not inspected, i.e. the
+    /// charset is neither detected nor checked, so the caller has to know
+    /// it's the right one.
+    ///
+    /// The charset affects the transfer encoding chosen through
+    /// `recommended_transfer_encoding`, e.g. text with an us-ascii
+    /// charset can use `7bit` while text without charset can't.
+    pub fn ensure_text_charset(mut self, charset: &str) -> Self {
+        let needs_charset = self.media_type().type_() == TEXT
+            && self.media_type().get_param(CHARSET).is_none();
+
+        if needs_charset {
+            Arc::make_mut(&mut self.meta).media_type.set_param(CHARSET, charset);
+        }
+        self
+    }
+
     /// Access the raw data buffer of this instance.
     pub fn buffer(&self) -> &Arc<[u8]> {
         &self.buffer
@@ -138,6 +226,31 @@ impl Data {
         &self.meta.content_id
     }
 
+    /// Transcodes the data of a `text/*` resource to utf-8.
+    ///
+    /// If the media type of this data is a `text/*` type with a charset
+    /// which is neither utf-8 nor us-ascii a new `Data` instance is returned
+    /// which contains the data transcoded to utf-8 and has its `charset`
+    /// parameter changed to `utf-8`. In all other cases a clone of this
+    /// instance is returned.
+    ///
+    /// This is only available with the `charset-transcode` feature.
+    ///
+    /// # Error
+    ///
+    /// Fails if the charset is unknown or the data is not valid data for
+    /// the given charset.
+    #[cfg(feature="charset-transcode")]
+    pub fn transcode_to_utf8(&self) -> Result<Data, ResourceLoadingError> {
+        match transcoding_encoding(self.media_type())? {
+            Some(encoding) => {
+                let data = transcode_with(encoding, self.buffer(), self.metadata())?;
+                Ok(data.with_transfer_encoding_hint(self.transfer_encoding_hint()))
+            },
+            None => Ok(self.clone())
+        }
+    }
+
     /// Transfer encode the given data.
     ///
     /// This function will be called by the context implementation when
@@ -217,11 +330,81 @@ impl EncData {
     }
 
 
+    /// Returns the data with the file name of the file meta replaced.
+    ///
+    /// Like `Data::with_media_type` this only clones the metadata if
+    /// it's shared, the buffer is never cloned.
+    pub fn with_file_name(mut self, file_name: String) -> Self {
+        Arc::make_mut(&mut self.meta).file_meta.file_name = Some(file_name);
+        self
+    }
+
+    /// Returns the data with the content id replaced.
+    ///
+    /// Like `with_file_name` this only clones the metadata if it's
+    /// shared, the buffer is never cloned.
+    pub fn with_content_id(mut self, content_id: ContentId) -> Self {
+        Arc::make_mut(&mut self.meta).content_id = content_id;
+        self
+    }
+
     /// Access the transfer encoding used to encode the buffer.
     pub fn encoding(&self) -> TransferEncoding {
         self.encoding
     }
 
+    /// Returns the data with the transfer encoding undone.
+    ///
+    /// # Error
+    ///
+    /// Fails if the buffer isn't validly encoded with the
+    /// transfer encoding it's marked to be encoded with.
+    pub fn decoded_buffer(&self) -> Result<Vec<u8>, EncodingError> {
+        use self::TransferEncoding::*;
+        match self.encoding {
+            Base64 => base64::normal_decode(&*self.buffer),
+            QuotedPrintable => quoted_printable::normal_decode(&*self.buffer),
+            _7Bit | _8Bit | Binary => Ok(self.buffer.to_vec())
+        }
+    }
+
+    /// Returns the data decoded and transcoded to utf-8 if it needs transcoding.
+    ///
+    /// This works like `Data::transcode_to_utf8` but returns `None` if the
+    /// data doesn't need to be transcoded. The returned data still has to
+    /// be transfer encoded again.
+    #[cfg(feature="charset-transcode")]
+    pub(crate) fn transcode_to_utf8(&self) -> Result<Option<Data>, ResourceLoadingError> {
+        use failure::Fail;
+
+        let encoding = match transcoding_encoding(self.media_type())? {
+            Some(encoding) => encoding,
+            None => return Ok(None)
+        };
+        let buffer = self.decoded_buffer()
+            .map_err(|err| err.context(ResourceLoadingErrorKind::CharsetTranscodingFailed))?;
+        transcode_with(encoding, &buffer, self.metadata()).map(Some)
+    }
+
+    /// Returns a base64 transfer encoded version of this instance.
+    ///
+    /// If the data already is base64 encoded it's returned as is.
+    ///
+    /// # Error
+    ///
+    /// Fails if decoding the current transfer encoding fails,
+    /// see `decoded_buffer`.
+    pub fn into_base64(self) -> Result<EncData, EncodingError> {
+        if self.encoding == TransferEncoding::Base64 {
+            return Ok(self);
+        }
+
+        let enc_data = base64::normal_encode(self.decoded_buffer()?)
+            .into_bytes();
+
+        Ok(EncData::new(enc_data, self.meta, TransferEncoding::Base64))
+    }
+
     /// Access the content id.
     ///
     /// The content id is for the data itself so it should not
@@ -240,8 +423,57 @@ impl EncData {
     }
 }
 
+/// Returns the encoding text with given media type has to be transcoded from.
+///
+/// Returns `None` if it isn't text or already is utf-8/us-ascii.
+#[cfg(feature="charset-transcode")]
+fn transcoding_encoding(media_type: &MediaType)
+    -> Result<Option<&'static Encoding>, ResourceLoadingError>
+{
+    if media_type.type_() != TEXT {
+        return Ok(None);
+    }
+
+    let charset = match media_type.get_param(CHARSET) {
+        Some(charset) => charset.to_content(),
+        None => return Ok(None)
+    };
+    if charset.eq_ignore_ascii_case("us-ascii") {
+        return Ok(None);
+    }
+
+    let encoding = Encoding::for_label(charset.as_bytes())
+        .ok_or(ResourceLoadingErrorKind::CharsetTranscodingFailed)?;
+
+    if encoding == UTF_8 {
+        Ok(None)
+    } else {
+        Ok(Some(encoding))
+    }
+}
+
+/// Transcodes the buffer from given encoding to utf-8, updating the charset.
+#[cfg(feature="charset-transcode")]
+fn transcode_with(encoding: &'static Encoding, buffer: &[u8], meta: &Metadata)
+    -> Result<Data, ResourceLoadingError>
+{
+    let (text, had_errors) = encoding.decode_without_bom_handling(buffer);
+    if had_errors {
+        return Err(ResourceLoadingErrorKind::CharsetTranscodingFailed.into());
+    }
+
+    let mut media_type = meta.media_type.clone();
+    media_type.set_param(CHARSET, "utf-8");
+
+    Ok(Data::new(text.into_owned().into_bytes(), Metadata {
+        file_meta: meta.file_meta.clone(),
+        media_type,
+        content_id: meta.content_id.clone()
+    }))
+}
+
 /// Hint to change how data should be transfer encoded.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub enum TransferEncodingHint {
     /// Use Base64 encoding.
@@ -250,6 +482,25 @@ pub enum TransferEncodingHint {
     /// Use Quoted-Printable encoding.
     UseQuotedPrintable,
 
+    /// Use 7Bit encoding (i.e. no encoding at all).
+    ///
+    /// This is only possible if the data is us-ascii, does not contain
+    /// `'\0'` or orphan `'\r'`, `'\n'` and has no line longer than 998
+    /// bytes. If this is not the case Quoted-Printable encoding will
+    /// be used instead.
+    Use7Bit,
+
+    /// Use 7Bit encoding after repairing the data if necessary.
+    ///
+    /// This is like `Use7Bit` but orphan `'\r'` and `'\n'` are replaced
+    /// with `"\r\n"` and `'\0'` bytes are dropped before checking if
+    /// the data is 7bit compatible. This is meant to tolerate slightly
+    /// malformed text, e.g. produced by templates, **it changes the data**.
+    /// If the repaired data still can't be represented as 7bit (i.e. it
+    /// is not us-ascii or has too long lines) the original data is
+    /// Quoted-Printable encoded.
+    Use7BitLossy,
+
     // /// Do not assume Mime8Bit is available.
     // ///
     // /// As such do not encode ascii/utf-8 "as is" (e.g. not encoding them).
@@ -272,6 +523,30 @@ impl Default for TransferEncodingHint {
     }
 }
 
+/// Returns the transfer encoding recommended for data with given media type.
+///
+/// This is `7bit` for `text/*` with a `us-ascii` charset, `quoted-printable`
+/// for any other `text/*` and `base64` for anything else. It only depends on
+/// the media type, i.e. it can be used to predict the encoding before the
+/// data is loaded. Note that `7bit` is only usable if the data is actually
+/// 7bit compatible, which is why `TransferEncodingHint::Use7Bit` falls back
+/// to `quoted-printable` if it is not.
+pub fn recommended_transfer_encoding(media_type: &MediaType) -> TransferEncoding {
+    if media_type.type_() != TEXT {
+        return TransferEncoding::Base64;
+    }
+
+    let is_ascii = media_type.get_param(CHARSET)
+        .map(|charset| charset.to_content().eq_ignore_ascii_case("us-ascii"))
+        .unwrap_or(false);
+
+    if is_ascii {
+        TransferEncoding::_7Bit
+    } else {
+        TransferEncoding::QuotedPrintable
+    }
+}
+
 /// Transfer encodes Data.
 ///
 /// Util we have a reasonable "non latin letter text" heuristic
@@ -290,11 +565,73 @@ fn transfer_encode(
 
     match encoding_hint {
         UseQuotedPrintable => tenc_quoted_printable(data),
+        Use7Bit => {
+            if is_7bit_compatible(data.buffer()) {
+                tenc_7bit(data)
+            } else {
+                tenc_quoted_printable(data)
+            }
+        },
+        Use7BitLossy => {
+            let repaired = repair_7bit(data.buffer());
+            if is_7bit_compatible(&repaired) {
+                EncData::new(repaired, data.metadata().clone(), TransferEncoding::_7Bit)
+            } else {
+                tenc_quoted_printable(data)
+            }
+        },
         UseBase64 | NoHint => tenc_base64(data),
         __NonExhaustive { .. } => panic!("__NonExhaustive encoding should not be passed to any place")
     }
 }
 
+/// The hard line length limit (excluding `"\r\n"`).
+pub(crate) const MAX_LINE_LENGTH: usize = 998;
+
+/// Checks if the data can be represented "as is" using 7Bit encoding.
+pub(crate) fn is_7bit_compatible(buffer: &[u8]) -> bool {
+    let mut line_length = 0;
+    let mut last_was_cr = false;
+    for &bch in buffer {
+        match bch {
+            b'\r' => {
+                if last_was_cr { return false; }
+                last_was_cr = true;
+                continue;
+            },
+            b'\n' => {
+                if !last_was_cr { return false; }
+                line_length = 0;
+            },
+            0 => return false,
+            bch if bch >= 0x80 => return false,
+            _ => {
+                if last_was_cr { return false; }
+                line_length += 1;
+                if line_length > MAX_LINE_LENGTH {
+                    return false;
+                }
+            }
+        }
+        last_was_cr = false;
+    }
+    !last_was_cr
+}
+
+/// Drops `'\0'` bytes and replaces orphan `'\r'`/`'\n'` with `"\r\n"`.
+fn repair_7bit(buffer: &[u8]) -> Vec<u8> {
+    let without_nul = buffer.iter()
+        .cloned()
+        .filter(|&bch| bch != 0)
+        .collect();
+    fix_newlines(without_nul)
+}
+
+fn tenc_7bit(data: &Data) -> EncData {
+    EncData::new(data.buffer().clone(), data.metadata().clone(),
+        TransferEncoding::_7Bit)
+}
+
 fn tenc_base64(data: &Data) -> EncData {
     let enc_data = base64::normal_encode(data.buffer())
         .into_bytes();
@@ -303,6 +640,11 @@ fn tenc_base64(data: &Data) -> EncData {
         TransferEncoding::Base64)
 }
 
+/// Quoted-Printable encodes the data.
+///
+/// `normal_encode` inserts soft line breaks so that no encoded line
+/// is longer then 76 chars (including the trailing `'='` of a soft
+/// line break), i.e. the result never breaks the hard line length limit.
 fn tenc_quoted_printable(data: &Data) -> EncData {
     let enc_data = quoted_printable::normal_encode(data.buffer())
         .into_bytes();
@@ -313,6 +655,168 @@ fn tenc_quoted_printable(data: &Data) -> EncData {
 
 
 
+#[cfg(test)]
+mod test {
+    use context::Context;
+    use default_impl::test_context;
+    use super::*;
+
+    fn data_with_media_type(media_type: &str) -> Data {
+        let ctx = test_context();
+        Data::new(b"abc".to_vec(), Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse(media_type).unwrap(),
+            content_id: ctx.generate_content_id()
+        })
+    }
+
+    #[test]
+    fn ensure_text_charset_adds_missing_charset() {
+        let data = data_with_media_type("text/plain").ensure_text_charset("us-ascii");
+        let charset = data.media_type().get_param(CHARSET).unwrap().to_content();
+        assert_eq!(&*charset, "us-ascii");
+        assert_eq!(recommended_transfer_encoding(data.media_type()), TransferEncoding::_7Bit);
+    }
+
+    #[test]
+    fn ensure_text_charset_keeps_existing_charset() {
+        let data = data_with_media_type("text/plain; charset=utf-8").ensure_text_charset("us-ascii");
+        let charset = data.media_type().get_param(CHARSET).unwrap().to_content();
+        assert_eq!(&*charset, "utf-8");
+    }
+
+    #[test]
+    fn ensure_text_charset_ignores_non_text() {
+        let data = data_with_media_type("image/png").ensure_text_charset("us-ascii");
+        assert!(data.media_type().get_param(CHARSET).is_none());
+    }
+
+    #[test]
+    fn with_media_type_replaces_the_media_type() {
+        let ctx = test_context();
+        let data = Data::new(b"\x89PNG".to_vec(), Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse("application/octet-stream").unwrap(),
+            content_id: ctx.generate_content_id()
+        });
+        let cid = data.content_id().clone();
+
+        let data = data.with_media_type(MediaType::parse("image/png").unwrap());
+
+        assert_eq!(data.media_type().type_().as_str(), "image");
+        assert_eq!(data.media_type().subtype().as_str(), "png");
+        assert_eq!(data.content_id(), &cid);
+        assert_eq!(&**data.buffer(), b"\x89PNG");
+    }
+
+    fn text_data(text: &[u8]) -> Data {
+        let ctx = test_context();
+        Data::new(text.to_vec(), Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse("text/plain; charset=us-ascii").unwrap(),
+            content_id: ctx.generate_content_id()
+        })
+    }
+
+    #[test]
+    fn lossy_7bit_repairs_orphan_newlines() {
+        for input in &[&b"foo\rbar"[..], &b"foo\nbar"[..], &b"foo\0\r\nbar"[..]] {
+            let enc_data = text_data(input).transfer_encode(TransferEncodingHint::Use7BitLossy);
+            assert_eq!(enc_data.encoding(), TransferEncoding::_7Bit);
+            assert_eq!(&**enc_data.transfer_encoded_buffer(), b"foo\r\nbar");
+        }
+    }
+
+    #[test]
+    fn strict_7bit_does_not_repair_orphan_newlines() {
+        for input in &[&b"foo\rbar"[..], &b"foo\nbar"[..]] {
+            let enc_data = text_data(input).transfer_encode(TransferEncodingHint::Use7Bit);
+            assert_ne!(enc_data.encoding(), TransferEncoding::_7Bit);
+            assert_eq!(enc_data.decoded_buffer().unwrap(), input.to_vec());
+        }
+    }
+
+    #[test]
+    fn lossy_7bit_falls_back_to_quoted_printable_for_non_ascii() {
+        let enc_data = text_data("Grüße\n".as_bytes())
+            .transfer_encode(TransferEncodingHint::Use7BitLossy);
+        assert_eq!(enc_data.encoding(), TransferEncoding::QuotedPrintable);
+    }
+
+    #[test]
+    fn recommended_transfer_encoding_depends_on_the_media_type() {
+        let recommended = |media_type: &str| recommended_transfer_encoding(&MediaType::parse(media_type).unwrap());
+        assert_eq!(recommended("text/plain; charset=us-ascii"), TransferEncoding::_7Bit);
+        assert_eq!(recommended("text/plain; charset=US-ASCII"), TransferEncoding::_7Bit);
+        assert_eq!(recommended("text/html; charset=utf-8"), TransferEncoding::QuotedPrintable);
+        assert_eq!(recommended("text/plain"), TransferEncoding::QuotedPrintable);
+        assert_eq!(recommended("image/png"), TransferEncoding::Base64);
+        assert_eq!(recommended("application/octet-stream"), TransferEncoding::Base64);
+    }
+
+    #[test]
+    fn quoted_printable_lines_are_at_most_76_chars_long() {
+        let input = "ä".repeat(100) + "\r\n" + &"a".repeat(200);
+        let enc_data = text_data(input.as_bytes())
+            .transfer_encode(TransferEncodingHint::UseQuotedPrintable);
+
+        assert_eq!(enc_data.encoding(), TransferEncoding::QuotedPrintable);
+        for line in enc_data.transfer_encoded_buffer().split(|&bch| bch == b'\n') {
+            let line = if line.ends_with(b"\r") { &line[..line.len()-1] } else { line };
+            assert!(line.len() <= 76, "line too long: {:?}", String::from_utf8_lossy(line));
+        }
+        assert_eq!(enc_data.decoded_buffer().unwrap(), input.as_bytes().to_vec());
+    }
+
+    #[cfg(feature="charset-transcode")]
+    #[test]
+    fn transcode_latin1_to_utf8() {
+        let ctx = test_context();
+        let data = Data::new(b"Gr\xfc\xdfe".to_vec(), Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse("text/plain; charset=iso-8859-1").unwrap(),
+            content_id: ctx.generate_content_id()
+        });
+
+        let data = data.transcode_to_utf8().unwrap();
+
+        assert_eq!(&**data.buffer(), "Grüße".as_bytes());
+        let charset = data.media_type().get_param(CHARSET).unwrap().to_content();
+        assert_eq!(&*charset, "utf-8");
+    }
+
+    #[cfg(feature="charset-transcode")]
+    #[test]
+    fn does_not_change_utf8_text() {
+        let ctx = test_context();
+        let data = Data::plain_text("Grüße", ctx.generate_content_id());
+        let transcoded = data.transcode_to_utf8().unwrap();
+
+        assert!(Arc::ptr_eq(data.buffer(), transcoded.buffer()));
+    }
+
+    #[cfg(feature="charset-transcode")]
+    #[test]
+    fn transcode_transfer_encoded_latin1_to_utf8() {
+        let ctx = test_context();
+        let enc_data = Data::new(b"Gr\xfc\xdfe".to_vec(), Metadata {
+            file_meta: Default::default(),
+            media_type: MediaType::parse("text/plain; charset=iso-8859-1").unwrap(),
+            content_id: ctx.generate_content_id()
+        }).transfer_encode(TransferEncodingHint::UseBase64);
+
+        let data = enc_data.transcode_to_utf8().unwrap().unwrap();
+
+        assert_eq!(&**data.buffer(), "Grüße".as_bytes());
+        assert_eq!(data.content_id(), enc_data.content_id());
+        let charset = data.media_type().get_param(CHARSET).unwrap().to_content();
+        assert_eq!(&*charset, "utf-8");
+
+        let utf8 = data.transfer_encode(Default::default());
+        assert!(utf8.transcode_to_utf8().unwrap().is_none());
+    }
+}
+
 mod arc_buffer_serde {
     use super::*;
 
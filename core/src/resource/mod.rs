@@ -1,6 +1,25 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::Duration
+};
+
+use futures::{
+    future::{self, Either},
+    Future
+};
+
+use failure::Fail;
+use headers::header_components::{MediaType, FileMeta};
+
 // a module level circ. dep. but fine as only
 // used for more ergonomic helper constructors
 use ::context::Context;
+use ::utils::{self, SendBoxFuture};
+use ::error::{ResourceLoadingError, ResourceLoadingErrorKind, PreferredEncodingError};
+use ::IRI;
+#[cfg(feature="charset-transcode")]
+use ::error::ResourceError;
 
 #[cfg(feature="serde")]
 use serde::{Serialize, Deserialize};
@@ -178,4 +197,694 @@ impl Resource {
     pub fn plain_text(content: impl Into<String>, ctx: &impl Context) -> Resource {
         Resource::Data(Data::plain_text(content, ctx.generate_content_id()))
     }
-}
\ No newline at end of file
+
+    /// Creates a new html `Resource` with `text/html; charset=utf-8` media type.
+    ///
+    /// The `Context` is used to generate a `ContentId`.
+    pub fn html_text(content: impl Into<String>, ctx: &impl Context) -> Resource {
+        Resource::Data(Data::html_text(content, ctx.generate_content_id()))
+    }
+
+    /// Creates a new `Resource` from bytes with given media type and file name.
+    ///
+    /// The file name is used as `filename` parameter of the `Content-Disposition`
+    /// header if the resource is used as a attachment (or inline embedding) and
+    /// no file name was set in the header.
+    ///
+    /// The `Context` is used to generate a `ContentId`.
+    pub fn from_bytes_with_name(
+        media_type: MediaType,
+        data: Vec<u8>,
+        name: impl Into<String>,
+        ctx: &impl Context
+    ) -> Resource {
+        let meta = Metadata {
+            file_meta: FileMeta {
+                file_name: Some(name.into()),
+                ..Default::default()
+            },
+            media_type,
+            content_id: ctx.generate_content_id()
+        };
+        Resource::Data(Data::new(data, meta))
+    }
+
+    /// Creates a `Source` resource for the file at given path.
+    ///
+    /// This creates a source with a `path:` IRI and no explicit media type,
+    /// i.e. the media type is sniffed when loading it. The path is used as
+    /// is (without any percent encoding) as the `FsResourceLoader` uses the
+    /// tail of `path:` IRIs as path. How the path is interpreted is up to
+    /// the resource loader of the context, e.g. the `FsResourceLoader`
+    /// resolves relative paths relative to its root (and, if enabled,
+    /// rejects paths outside of it).
+    ///
+    /// As IRIs are always utf-8 non utf-8 paths are lossy converted, which
+    /// most likely means loading them will fail with `NotFound`.
+    pub fn from_path(path: impl AsRef<Path>) -> Resource {
+        let path = path.as_ref().to_string_lossy();
+        //UNWRAP_SAFE: "path" is a valid scheme
+        let iri = IRI::from_parts("path", &path).unwrap();
+        Resource::Source(Source {
+            iri,
+            use_media_type: Default::default(),
+            use_file_name: None,
+            use_disposition: None
+        })
+    }
+
+    /// Creates a `Source` resource for the file at given path with given media type.
+    ///
+    /// Like `from_path` but uses `UseMediaType::Default` with given media type,
+    /// so that the resource loader doesn't need to sniff it (which is often
+    /// not reliable, e.g. for text). Additionally the file name of the path
+    /// (if it has any) is used as `use_file_name`, so that the resource has
+    /// a sensible file name if used as attachment.
+    pub fn from_path_with_media_type(path: impl AsRef<Path>, media_type: MediaType) -> Resource {
+        let path = path.as_ref();
+        let file_name = path.file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+
+        let mut resource = Resource::from_path(path);
+        if let Resource::Source(ref mut source) = resource {
+            source.use_media_type = UseMediaType::Default(media_type);
+            source.use_file_name = file_name;
+        }
+        resource
+    }
+
+    /// Returns true if the resource is a `Source` which still needs to be loaded.
+    ///
+    /// Resources are plain values (not shared state), so unlike in older
+    /// versions there is no "loading" or "failed" state: a `Source` is
+    /// replaced with the loaded `EncData` when the mail is turned into a
+    /// encodable mail and loading failures are reported by that future.
+    pub fn is_not_loaded(&self) -> bool {
+        match *self {
+            Resource::Source(_) => true,
+            _ => false
+        }
+    }
+
+    /// Returns true if the resource is `Data` or `EncData`.
+    pub fn is_loaded(&self) -> bool {
+        !self.is_not_loaded()
+    }
+
+    /// Returns true if the resource is transfer encoded (`EncData`).
+    pub fn is_transfer_encoded(&self) -> bool {
+        match *self {
+            Resource::EncData(_) => true,
+            _ => false
+        }
+    }
+
+    /// Returns true if both resources share the same data, like `Arc::ptr_eq`.
+    ///
+    /// This is the case if one resource is a clone of the other (or both are
+    /// clones of the same resource), i.e. both the buffer and the metadata are
+    /// the same `Arc`s. This is much cheaper than comparing the resources with
+    /// `==` and can e.g. be used to detect a resource which was embedded
+    /// multiple times. `Source` resources do not share any data, so this
+    /// always returns false for them.
+    pub fn ptr_eq(&self, other: &Resource) -> bool {
+        match (self, other) {
+            (&Resource::Data(ref left), &Resource::Data(ref right)) =>
+                Arc::ptr_eq(left.buffer(), right.buffer())
+                    && Arc::ptr_eq(left.metadata(), right.metadata()),
+            (&Resource::EncData(ref left), &Resource::EncData(ref right)) =>
+                Arc::ptr_eq(left.transfer_encoded_buffer(), right.transfer_encoded_buffer())
+                    && Arc::ptr_eq(left.metadata(), right.metadata()),
+            _ => false
+        }
+    }
+
+    /// Returns true if both resources are loaded and have the same media type and (decoded) data.
+    ///
+    /// Unlike `==` this also compares `Data` with `EncData` instances, i.e. a
+    /// `Data` instance has the same content as the `EncData` instance created
+    /// by transfer encoding it (independent of the used transfer encoding).
+    /// `Source` resources are not loaded and as such always return false.
+    pub fn has_same_content(&self, other: &Resource) -> bool {
+        use self::Resource::*;
+        match (self, other) {
+            (&Data(ref left), &Data(ref right)) =>
+                left.media_type() == right.media_type()
+                    && left.buffer() == right.buffer(),
+            (&EncData(ref left), &EncData(ref right)) =>
+                left.media_type() == right.media_type()
+                    && match (left.decoded_buffer(), right.decoded_buffer()) {
+                        (Ok(left), Ok(right)) => left == right,
+                        _ => false
+                    },
+            (&Data(ref data), &EncData(ref enc_data))
+            | (&EncData(ref enc_data), &Data(ref data)) =>
+                data.media_type() == enc_data.media_type()
+                    && enc_data.decoded_buffer()
+                        .map(|decoded| &*decoded == &**data.buffer())
+                        .unwrap_or(false),
+            _ => false
+        }
+    }
+
+    /// Returns the media type of the resource if it's known without loading it.
+    ///
+    /// For `Data` and `EncData` this is the media type of the data. For a
+    /// `Source` this is the media type given through `UseMediaType::Default`,
+    /// note that the resource loader might still use a different media type
+    /// if it has one stored with the data. If the media type can only be
+    /// determined by loading (and sniffing) the resource `None` is returned.
+    pub fn declared_media_type(&self) -> Option<&MediaType> {
+        match *self {
+            Resource::Source(Source { use_media_type: UseMediaType::Default(ref media_type), .. }) =>
+                Some(media_type),
+            Resource::Source(_) => None,
+            Resource::Data(ref data) => Some(data.media_type()),
+            Resource::EncData(ref enc_data) => Some(enc_data.media_type())
+        }
+    }
+
+    /// Creates a `Resource` from a future resolving to the bytes of the resource.
+    ///
+    /// This is for cases where the media type and file name are known
+    /// up front but the data is provided asynchronously (e.g. a download).
+    /// Once the future resolved the returned future resolves to a
+    /// `Resource::Data` instance with the given media type and file name
+    /// (and a content id generated by the `Context`).
+    ///
+    /// Errors of the given future are wrapped in a `ResourceLoadingError`
+    /// of kind `LoadingFailed`.
+    pub fn from_future_with_meta<F>(
+        media_type: MediaType,
+        name: Option<String>,
+        fut: F,
+        ctx: &impl Context
+    ) -> SendBoxFuture<Resource, ResourceLoadingError>
+        where F: Future<Item=Vec<u8>> + Send + 'static,
+              F::Error: Fail
+    {
+        let content_id = ctx.generate_content_id();
+        let fut = fut
+            .map_err(|err| ResourceLoadingError::from(
+                err.context(ResourceLoadingErrorKind::LoadingFailed)))
+            .map(move |buffer| {
+                let size = buffer.len();
+                let meta = Metadata {
+                    file_meta: FileMeta {
+                        file_name: name,
+                        size: Some(size),
+                        ..Default::default()
+                    },
+                    media_type,
+                    content_id
+                };
+                Resource::Data(Data::new(buffer, meta))
+            });
+
+        Box::new(fut)
+    }
+
+    /// Loads and transfer encodes the resource, failing if it takes longer than `timeout`.
+    ///
+    /// This works like the `Context` loading methods used by `load_now` but
+    /// without blocking and races the loading against the timeout. If the
+    /// timeout fires first the loading future is dropped (canceling the
+    /// loading) and the returned future resolves to an error of kind
+    /// `TimedOut`, with the source IRI set for `Source` resources.
+    ///
+    /// As resources are plain values the resource itself isn't changed
+    /// by a timeout, so loading it can just be retried later on, e.g.
+    /// with a longer timeout.
+    ///
+    /// Note that the timeout is implemented using `utils::timeout`, which
+    /// uses a single timer thread shared by all timeouts. Each call sends
+    /// a message to that thread, it's not meant for a large number of
+    /// concurrent timeouts.
+    pub fn load_with_timeout<C>(&self, ctx: &C, timeout: Duration)
+        -> SendBoxFuture<EncData, ResourceLoadingError>
+        where C: Context
+    {
+        let (fut, iri) = match *self {
+            Resource::Source(ref source) => (load_source(source, ctx), Some(source.iri.clone())),
+            Resource::Data(ref data) => (ctx.transfer_encode_resource(data), None),
+            Resource::EncData(ref enc_data) => return Box::new(future::ok(enc_data.clone()))
+        };
+
+        let fut = fut
+            .select2(utils::timeout(timeout))
+            .then(move |res| match res {
+                Ok(Either::A((enc_data, _timeout))) => Ok(enc_data),
+                Err(Either::A((err, _timeout))) => Err(err),
+                // the timer can't fail, but if it would it's as good as a time out
+                Ok(Either::B(((), _loading))) | Err(Either::B(((), _loading))) => {
+                    Err(ResourceLoadingError::from((iri, ResourceLoadingErrorKind::TimedOut)))
+                }
+            });
+
+        Box::new(fut)
+    }
+
+    /// Loads the `primary` source falling back to `fallback` if it fails or takes too long.
+    ///
+    /// The returned future races the loading of the primary source against
+    /// the timeout. If the primary source failed to load or the timeout
+    /// fires first the primary loading future is dropped and the fallback
+    /// source is loaded instead (without any timeout). This covers use-cases
+    /// like having a fast (but sometimes unavailable) remote source and a
+    /// slow but reliable local one.
+    ///
+    /// The future resolves to a `Resource::EncData` instance which can be
+    /// used to create a mail.
+    ///
+    /// Note that the timeout is implemented using `utils::timeout`, which
+    /// uses a single timer thread shared by all timeouts. Each call sends
+    /// a message to that thread, it's not meant for a large number of
+    /// concurrent timeouts.
+    pub fn load_with_timeout_and_fallback<C>(
+        primary: &Source,
+        timeout: Duration,
+        fallback: &Source,
+        ctx: &C
+    ) -> SendBoxFuture<Resource, ResourceLoadingError>
+        where C: Context
+    {
+        let fallback = fallback.clone();
+        let fallback_ctx = ctx.clone();
+        let fut = load_source(primary, ctx)
+            .select2(utils::timeout(timeout))
+            .then(move |res| match res {
+                Ok(Either::A((enc_data, _timeout))) => {
+                    Either::A(future::ok(enc_data))
+                },
+                // timed out or failed to load the primary source
+                _ => {
+                    Either::B(load_source(&fallback, &fallback_ctx))
+                }
+            })
+            .map(Resource::EncData);
+
+        Box::new(fut)
+    }
+
+    /// Loads and transfer encodes the resource, blocking until it's done.
+    ///
+    /// `Source` resources are loaded using `Context::load_resource` (see
+    /// `load_source`), `Data`
+    /// resources are transfer encoded using `Context::transfer_encode_resource`
+    /// and for `EncData` resources a clone of the data is returned.
+    ///
+    /// **This blocks the current thread**, it's meant for e.g. cli tools or
+    /// tests which don't care about concurrency. It must not be used inside
+    /// of an async executor (e.g. a future or a tokio task), as it might
+    /// dead lock or at least blocks the executor. Use the `Context` methods
+    /// returning futures instead.
+    pub fn load_now(&self, ctx: &impl Context) -> Result<EncData, ResourceLoadingError> {
+        match *self {
+            Resource::Source(ref source) => load_source(source, ctx).wait(),
+            Resource::Data(ref data) => ctx.transfer_encode_resource(data).wait(),
+            Resource::EncData(ref enc_data) => Ok(enc_data.clone())
+        }
+    }
+
+    /// Sets the transfer encoding which should be used for this resource.
+    ///
+    /// This sets the `TransferEncodingHint` of `Data` resources, which is
+    /// used when transfer encoding the data, e.g. allowing to force base64
+    /// for text which would otherwise be quoted-printable encoded.
+    ///
+    /// # Error
+    ///
+    /// - `AlreadyEncoded` for `EncData` resources
+    /// - `NotLoaded` for `Source` resources, as it's up to the resource
+    ///   loader how to transfer encode them
+    /// - `Not7BitCompatible` if `Use7Bit` is passed in but the data can't
+    ///   be represented as 7bit (instead of silently falling back to
+    ///   quoted-printable encoding)
+    pub fn set_preferred_encoding(&mut self, hint: TransferEncodingHint)
+        -> Result<(), PreferredEncodingError>
+    {
+        match *self {
+            Resource::Data(ref mut data) => {
+                if hint == TransferEncodingHint::Use7Bit && !is_7bit_compatible(data.buffer()) {
+                    return Err(PreferredEncodingError::Not7BitCompatible);
+                }
+                *data = data.clone().with_transfer_encoding_hint(hint);
+                Ok(())
+            },
+            Resource::Source(_) => Err(PreferredEncodingError::NotLoaded),
+            Resource::EncData(_) => Err(PreferredEncodingError::AlreadyEncoded)
+        }
+    }
+
+    /// Transcodes the data of a `text/*` resource to utf-8.
+    ///
+    /// This uses `Data::transcode_to_utf8`, which only changes text data
+    /// with a charset other than utf-8 or us-ascii. This does nothing for
+    /// the other variants. Calling this is normally not needed, as with the
+    /// `charset-transcode` feature all text bodies are transcoded when the
+    /// mail is turned into an encodable mail, independent of the resource
+    /// loader which loaded them.
+    ///
+    /// This is only available with the `charset-transcode` feature.
+    #[cfg(feature="charset-transcode")]
+    pub fn transcode_to_utf8(&mut self) -> Result<(), ResourceError> {
+        if let Resource::Data(ref mut data) = *self {
+            *data = data.transcode_to_utf8()?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads the source using `Context::load_resource`.
+///
+/// If the source has a `use_file_name` it's used as file name of the loaded
+/// data, independent of the file name the resource loader did set (if any).
+/// This makes sure the file name is consistent across resource loaders.
+pub(crate) fn load_source(source: &Source, ctx: &impl Context)
+    -> SendBoxFuture<EncData, ResourceLoadingError>
+{
+    let fut = ctx.load_resource(source);
+    match source.use_file_name {
+        Some(ref file_name) => {
+            let file_name = file_name.clone();
+            Box::new(fut.map(move |enc_data| enc_data.with_file_name(file_name)))
+        },
+        None => fut
+    }
+}
+
+/// Compares resources of the same variant by their media type and data.
+///
+/// `Data` instances are equal if they have the same media type and data,
+/// `EncData` instances if they have the same media type, transfer encoding
+/// and transfer encoded data. Other metadata like the content id is not
+/// compared. Not loaded resources are compared by their `Source`. Resources
+/// of different variants are never equal, use `Resource::has_same_content`
+/// to compare e.g. a `Data` instance with a `EncData` instance.
+impl PartialEq for Resource {
+    fn eq(&self, other: &Resource) -> bool {
+        use self::Resource::*;
+        match (self, other) {
+            (&Source(ref left), &Source(ref right)) => left == right,
+            (&Data(ref left), &Data(ref right)) =>
+                left.media_type() == right.media_type()
+                    && left.buffer() == right.buffer(),
+            (&EncData(ref left), &EncData(ref right)) =>
+                left.media_type() == right.media_type()
+                    && left.encoding() == right.encoding()
+                    && left.transfer_encoded_buffer() == right.transfer_encoded_buffer(),
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::{future, Future};
+    use headers::header_components::{FileMeta, MediaType, TransferEncoding};
+
+    use context::{Context, ResourceLoaderComponent};
+    use default_impl::{test_context_with_loader, test_source};
+    use error::ResourceLoadingError;
+    use utils::SendBoxFuture;
+    use super::*;
+
+    /// Loader which never resolves for the `never` scheme
+    /// and returns the tail as data for any other scheme.
+    #[derive(Debug)]
+    struct NeverOrTailLoader;
+
+    impl ResourceLoaderComponent for NeverOrTailLoader {
+        fn load_resource(&self, source: &Source, ctx: &impl Context)
+            -> SendBoxFuture<EncData, ResourceLoadingError>
+        {
+            if source.iri.scheme() == "never" {
+                return Box::new(future::empty());
+            }
+
+            let tail = source.iri.tail().to_owned();
+            let data = Data::new(tail.clone().into_bytes(), Metadata {
+                file_meta: FileMeta {
+                    file_name: Some(tail),
+                    ..Default::default()
+                },
+                media_type: MediaType::parse("text/plain; charset=us-ascii").unwrap(),
+                content_id: ctx.generate_content_id()
+            });
+
+            Box::new(future::ok(data.transfer_encode(Default::default())))
+        }
+    }
+
+    #[test]
+    fn resources_are_compared_by_media_type_and_data() {
+        let ctx = ::default_impl::test_context();
+        let text = Resource::plain_text("abc", &ctx);
+        assert_eq!(text, Resource::plain_text("abc", &ctx));
+        assert_ne!(text, Resource::plain_text("abcd", &ctx));
+        assert_ne!(text, Resource::html_text("abc", &ctx));
+
+        let enc_data = match text {
+            Resource::Data(ref data) => data.transfer_encode(Default::default()),
+            _ => unreachable!()
+        };
+        assert_ne!(text, Resource::EncData(enc_data.clone()));
+        assert!(text.has_same_content(&Resource::EncData(enc_data.clone())));
+        assert!(Resource::EncData(enc_data).has_same_content(&text));
+        assert!(!text.has_same_content(&Resource::plain_text("abcd", &ctx)));
+
+        let source = Resource::Source(test_source("tail:abc"));
+        assert_eq!(source, Resource::Source(test_source("tail:abc")));
+        assert_ne!(source, Resource::Source(test_source("tail:abcd")));
+        assert_ne!(source, text);
+    }
+
+    #[test]
+    fn from_path_creates_path_source() {
+        let resource = Resource::from_path("dir/some file.txt");
+        if let Resource::Source(source) = resource {
+            assert_eq!(source.iri.as_str(), "path:dir/some file.txt");
+            assert_eq!(source.use_media_type, UseMediaType::Auto);
+            assert_eq!(source.use_file_name, None);
+        } else {
+            panic!("unexpected resource: {:?}", resource);
+        }
+    }
+
+    #[test]
+    fn from_path_with_media_type_sets_media_type_and_file_name() {
+        let media_type = MediaType::parse("text/csv; charset=utf-8").unwrap();
+        let resource = Resource::from_path_with_media_type("dir/data.csv", media_type.clone());
+        assert_eq!(resource.declared_media_type(), Some(&media_type));
+        if let Resource::Source(source) = resource {
+            assert_eq!(source.iri.as_str(), "path:dir/data.csv");
+            assert_eq!(source.use_file_name, Some("data.csv".to_owned()));
+        } else {
+            panic!("unexpected resource: {:?}", resource);
+        }
+    }
+
+    #[test]
+    fn ptr_eq_detects_shared_resources() {
+        let ctx = ::default_impl::test_context();
+        let resource = Resource::plain_text("abc", &ctx);
+        assert!(resource.ptr_eq(&resource.clone()));
+        assert!(!resource.ptr_eq(&Resource::plain_text("abc", &ctx)));
+
+        let enc_data = resource.load_now(&ctx).unwrap();
+        let encoded = Resource::EncData(enc_data.clone());
+        assert!(encoded.ptr_eq(&Resource::EncData(enc_data)));
+        assert!(!encoded.ptr_eq(&resource));
+
+        let source = Resource::Source(test_source("tail:abc"));
+        assert!(!source.ptr_eq(&source.clone()));
+    }
+
+    #[test]
+    fn declared_media_type_does_not_need_loading() {
+        let ctx = ::default_impl::test_context();
+        let resource = Resource::Source(test_source("tail:abc"));
+        assert!(resource.declared_media_type().is_none());
+
+        let media_type = MediaType::parse("image/png").unwrap();
+        let resource = Resource::Source(Source {
+            use_media_type: UseMediaType::Default(media_type),
+            ..test_source("tail:abc")
+        });
+        let declared = resource.declared_media_type().unwrap();
+        assert_eq!(declared.type_().as_str(), "image");
+        assert_eq!(declared.subtype().as_str(), "png");
+
+        let resource = Resource::plain_text("abc", &ctx);
+        let declared = resource.declared_media_type().unwrap();
+        assert_eq!(declared.type_().as_str(), "text");
+        assert_eq!(declared.subtype().as_str(), "plain");
+    }
+
+    #[test]
+    fn from_bytes_with_name_sets_the_file_name() {
+        let ctx = ::default_impl::test_context();
+        let media_type = MediaType::parse("application/octet-stream").unwrap();
+        let resource = Resource::from_bytes_with_name(
+            media_type, b"abc".to_vec(), "data.bin", &ctx);
+
+        if let Resource::Data(data) = resource {
+            assert_eq!(data.file_meta().file_name, Some("data.bin".to_owned()));
+            assert_eq!(&**data.buffer(), b"abc");
+        } else {
+            panic!("unexpected resource: {:?}", resource);
+        }
+    }
+
+    #[test]
+    fn from_future_with_meta_uses_the_given_meta() {
+        let ctx = ::default_impl::test_context();
+        let media_type = MediaType::parse("image/png").unwrap();
+        let bytes = future::ok::<_, ::std::io::Error>(b"png data".to_vec());
+
+        let resource = Resource::from_future_with_meta(
+            media_type, Some("image.png".to_owned()), bytes, &ctx).wait().unwrap();
+
+        if let Resource::Data(data) = resource {
+            assert_eq!(data.file_meta().file_name, Some("image.png".to_owned()));
+            assert_eq!(data.media_type().subtype().as_str(), "png");
+            assert_eq!(&**data.buffer(), b"png data");
+        } else {
+            panic!("unexpected resource: {:?}", resource);
+        }
+    }
+
+    #[test]
+    fn from_future_with_meta_propagates_errors() {
+        let ctx = ::default_impl::test_context();
+        let media_type = MediaType::parse("image/png").unwrap();
+        let err = ::std::io::Error::new(::std::io::ErrorKind::Other, "download failed");
+        let bytes = future::err::<Vec<u8>, _>(err);
+
+        let err = Resource::from_future_with_meta(media_type, None, bytes, &ctx)
+            .wait().unwrap_err();
+        assert_eq!(err.kind(), ResourceLoadingErrorKind::LoadingFailed);
+    }
+
+    #[test]
+    fn state_helpers_reflect_the_variant() {
+        let ctx = ::default_impl::test_context();
+        let source = Resource::Source(test_source("mem:abc"));
+        assert!(source.is_not_loaded());
+        assert!(!source.is_loaded());
+        assert!(!source.is_transfer_encoded());
+
+        let data = Resource::plain_text("abc", &ctx);
+        assert!(!data.is_not_loaded());
+        assert!(data.is_loaded());
+        assert!(!data.is_transfer_encoded());
+
+        let enc_data = Resource::EncData(resource_enc_data(&ctx));
+        assert!(enc_data.is_loaded());
+        assert!(enc_data.is_transfer_encoded());
+    }
+
+    #[test]
+    fn load_now_loads_sources_and_encodes_data() {
+        let ctx = test_context_with_loader(NeverOrTailLoader);
+
+        let enc_data = Resource::Source(test_source("mem:tail")).load_now(&ctx).unwrap();
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"tail".to_vec());
+
+        let enc_data = Resource::plain_text("text", &ctx).load_now(&ctx).unwrap();
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"text".to_vec());
+
+        let cid = enc_data.content_id().clone();
+        let enc_data = Resource::EncData(enc_data).load_now(&ctx).unwrap();
+        assert_eq!(enc_data.content_id(), &cid);
+    }
+
+    #[test]
+    fn use_file_name_overrides_the_file_name_of_the_loader() {
+        let ctx = test_context_with_loader(NeverOrTailLoader);
+
+        let enc_data = Resource::Source(test_source("mem:tail")).load_now(&ctx).unwrap();
+        assert_eq!(enc_data.file_meta().file_name, Some("tail".to_owned()));
+
+        let resource = Resource::Source(Source {
+            use_file_name: Some("override.txt".to_owned()),
+            ..test_source("mem:tail")
+        });
+        let enc_data = resource.load_now(&ctx).unwrap();
+        assert_eq!(enc_data.file_meta().file_name, Some("override.txt".to_owned()));
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"tail".to_vec());
+    }
+
+    #[test]
+    fn set_preferred_encoding_is_used_when_encoding() {
+        let ctx = ::default_impl::test_context();
+        let mut resource = Resource::plain_text("abc", &ctx);
+        resource.set_preferred_encoding(TransferEncodingHint::UseBase64).unwrap();
+
+        let enc_data = resource.load_now(&ctx).unwrap();
+        assert_eq!(enc_data.encoding(), TransferEncoding::Base64);
+    }
+
+    #[test]
+    fn set_preferred_encoding_rejects_invalid_cases() {
+        let ctx = ::default_impl::test_context();
+        let mut resource = Resource::plain_text("a\x00c", &ctx);
+        assert_eq!(
+            resource.set_preferred_encoding(TransferEncodingHint::Use7Bit),
+            Err(PreferredEncodingError::Not7BitCompatible)
+        );
+
+        let mut resource = Resource::Source(test_source("mem:abc"));
+        assert_eq!(
+            resource.set_preferred_encoding(TransferEncodingHint::UseBase64),
+            Err(PreferredEncodingError::NotLoaded)
+        );
+
+        let mut resource = Resource::EncData(resource_enc_data(&ctx));
+        assert_eq!(
+            resource.set_preferred_encoding(TransferEncodingHint::UseBase64),
+            Err(PreferredEncodingError::AlreadyEncoded)
+        );
+    }
+
+    fn resource_enc_data(ctx: &impl Context) -> EncData {
+        Data::plain_text("abc", ctx.generate_content_id())
+            .transfer_encode(Default::default())
+    }
+
+    #[test]
+    fn load_with_timeout_fails_with_timed_out() {
+        let ctx = test_context_with_loader(NeverOrTailLoader);
+
+        let resource = Resource::Source(test_source("never:primary"));
+        let err = resource.load_with_timeout(&ctx, Duration::from_millis(10))
+            .wait().unwrap_err();
+        assert_eq!(err.kind(), ResourceLoadingErrorKind::TimedOut);
+        assert_eq!(err.source_iri().map(|iri| iri.as_str()), Some("never:primary"));
+
+        let resource = Resource::Source(test_source("mem:tail"));
+        let enc_data = resource.load_with_timeout(&ctx, Duration::from_secs(10))
+            .wait().unwrap();
+        assert_eq!(enc_data.decoded_buffer().unwrap(), b"tail".to_vec());
+    }
+
+    #[test]
+    fn uses_fallback_if_primary_times_out() {
+        let ctx = test_context_with_loader(NeverOrTailLoader);
+
+        let resource = Resource::load_with_timeout_and_fallback(
+            &test_source("never:primary"),
+            Duration::from_millis(10),
+            &test_source("mem:fallback"),
+            &ctx
+        ).wait().unwrap();
+
+        if let Resource::EncData(enc_data) = resource {
+            assert_eq!(enc_data.file_meta().file_name, Some("fallback".to_owned()));
+        } else {
+            panic!("unexpected resource: {:?}", resource);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 
 use headers::{
-    header_components::MediaType
+    header_components::{MediaType, DispositionKind}
 };
 use ::{
     iri::IRI
@@ -12,7 +12,7 @@ use serde::{Serialize, Deserialize};
 /// POD containing the IRI which should be used to laod a resource well as
 /// an optional file name to use and a description about how the content type
 /// should be handled.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub struct Source {
 
@@ -37,7 +37,15 @@ pub struct Source {
     /// Note that file names are optional and don't need to be provided at all.
     /// But it is strongly recommended to provide them for anything used as
     /// attachment but normally irrelevant for anything else.
-    pub use_file_name: Option<String>
+    pub use_file_name: Option<String>,
+
+    /// Allows specifying if the resource should be an inline part or an attachment.
+    ///
+    /// If set a `Content-Disposition` header with this disposition kind is
+    /// inserted when creating a mail for the resource (e.g. through
+    /// `Mail::new_singlepart_mail`). Use `None` to not add any disposition.
+    #[cfg_attr(feature="serde", serde(default))]
+    pub use_disposition: Option<DispositionKind>
 }
 
 /// Specifies how the content type should be handled when loading the data.
@@ -47,7 +55,7 @@ pub struct Source {
 /// which can fail. Nevertheless how any of the aspects are handled in detail
 /// depends on the context implementation.
 ///
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub enum UseMediaType {
     /// Sniff content type if no content type was given from any other place.
@@ -4,9 +4,20 @@
 //! to be put in.
 use std::marker::Send;
 use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use std::thread;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Mutex, mpsc::{self, RecvTimeoutError, SendError}};
 
 use chrono;
 use futures::Future;
+use futures::sync::oneshot;
+
+use headers::{
+    header_components::Domain,
+    error::ComponentCreationError
+};
 
 
 /// Type alias for an boxed future which is Send + 'static.
@@ -17,6 +28,94 @@ pub fn now() -> chrono::DateTime<chrono::Utc> {
     chrono::Utc::now()
 }
 
+/// Sender to the timer thread used by `timeout`, `None` until it's started.
+static TIMER: Mutex<Option<mpsc::Sender<TimerEntry>>> = Mutex::new(None);
+
+/// Returns a future which resolves once the given duration has passed.
+///
+/// This is a minimal timer implementation, all timeouts are handled by
+/// a single timer thread which is started on the first call and then
+/// shared by all calls. Each call costs a heap allocation, a message to
+/// the timer thread and a (short) lock of the mutex guarding the timer
+/// thread. This is fine for e.g. a timeout per loaded resource, but if
+/// a large number of timeouts is needed a "real" timer (e.g. the one of
+/// tokio) should be used instead.
+pub fn timeout(duration: Duration) -> SendBoxFuture<(), ()> {
+    let (sender, receiver) = oneshot::channel();
+    let entry = TimerEntry { deadline: Instant::now() + duration, sender };
+
+    let mut timer = TIMER.lock()
+        .expect("[BUG] the timer lock is never poisoned");
+    let unsent = match *timer {
+        Some(ref timer) => match timer.send(entry) {
+            Ok(()) => None,
+            // the timer thread is gone (it can only stop by panicking), so start a new one
+            Err(SendError(entry)) => Some(entry)
+        },
+        None => Some(entry)
+    };
+    if let Some(entry) = unsent {
+        let (timer_sender, timer_receiver) = mpsc::channel();
+        thread::spawn(move || run_timer(timer_receiver));
+        timer_sender.send(entry)
+            .expect("[BUG] the timer thread was just started");
+        *timer = Some(timer_sender);
+    }
+
+    Box::new(receiver.map_err(|_| ()))
+}
+
+/// A pending timeout, ordered so that the earliest deadline is the greatest.
+struct TimerEntry {
+    deadline: Instant,
+    sender: oneshot::Sender<()>
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+fn run_timer(receiver: mpsc::Receiver<TimerEntry>) {
+    let mut pending = BinaryHeap::new();
+    loop {
+        let now = Instant::now();
+        while pending.peek().map(|entry: &TimerEntry| entry.deadline <= now).unwrap_or(false) {
+            //UNWRAP_SAFE: we just peeked the entry
+            let entry = pending.pop().unwrap();
+            // if the receiver was dropped no one cares about the timeout anymore
+            let _ = entry.sender.send(());
+        }
+
+        let received = match pending.peek() {
+            Some(entry) => receiver.recv_timeout(entry.deadline - now),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match received {
+            Ok(entry) => pending.push(entry),
+            Err(RecvTimeoutError::Timeout) => {},
+            // the sender is kept in `TIMER` for as long as this thread runs
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+    }
+}
+
 /// Trait to allow const `bool` values in generics.
 pub trait ConstSwitch: Debug + Copy + Send + Sync + 'static {
     const ENABLED: bool;
@@ -31,3 +130,105 @@ impl ConstSwitch for Enabled { const ENABLED: bool = true; }
 pub struct Disabled;
 impl ConstSwitch for Disabled { const ENABLED: bool = false; }
 
+/// The maximal length of a domain name (without a trailing `'.'`).
+const MAX_DOMAIN_LENGTH: usize = 253;
+/// The maximal length of a single label of a domain name.
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Parses a `Domain` validating it's a valid (host) domain name.
+///
+/// Currently `Domain` doesn't implement `FromStr`, so this can be used
+/// instead of `Domain::from_unchecked` e.g. to create a context.
+///
+/// The domain has to be at most 253 chars long and consist of labels
+/// separated by `'.'`. Each label has to be 1 to 63 chars long, consist
+/// of ascii letters, digits and `'-'` and can not start or end with `'-'`.
+///
+/// Internationalized domain names have to be punycode encoded, i.e.
+/// `"xn--mnchen-3ya.example"` is accepted but `"münchen.example"` is not.
+///
+/// # Error
+///
+/// Returns a `ComponentCreationError` (for the component `"Domain"`)
+/// if the domain is not valid.
+pub fn parse_domain(domain: &str) -> Result<Domain, ComponentCreationError> {
+    if is_valid_domain(domain) {
+        Ok(Domain::from_unchecked(domain.to_owned()))
+    } else {
+        Err(ComponentCreationError::new_with_str("Domain", domain))
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= MAX_DOMAIN_LENGTH
+        && domain.split('.').all(is_valid_label)
+}
+
+fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= MAX_LABEL_LENGTH
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.bytes().all(|bch| bch.is_ascii_alphanumeric() || bch == b'-')
+}
+
+#[cfg(test)]
+mod test {
+
+    mod timeout {
+        use std::time::{Duration, Instant};
+        use futures::Future;
+        use super::super::*;
+
+        #[test]
+        fn resolves_after_the_duration() {
+            let start = Instant::now();
+            assert_ok!(timeout(Duration::from_millis(20)).wait());
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        }
+
+        #[test]
+        fn shorter_timeouts_resolve_first() {
+            let long = timeout(Duration::from_secs(10));
+            let start = Instant::now();
+            assert_ok!(timeout(Duration::from_millis(10)).wait());
+            assert!(start.elapsed() < Duration::from_secs(10));
+            drop(long);
+        }
+    }
+
+    mod parse_domain {
+        use super::super::*;
+
+        #[test]
+        fn accepts_valid_domains() {
+            assert_ok!(parse_domain("example.com"));
+            assert_ok!(parse_domain("localhost"));
+            assert_ok!(parse_domain("mail-1.sub.example.com"));
+            assert_ok!(parse_domain("xn--mnchen-3ya.example"));
+        }
+
+        #[test]
+        fn rejects_invalid_chars_and_empty_labels() {
+            assert_err!(parse_domain(""));
+            assert_err!(parse_domain("münchen.example"));
+            assert_err!(parse_domain("exa_mple.com"));
+            assert_err!(parse_domain("example..com"));
+            assert_err!(parse_domain("example.com."));
+            assert_err!(parse_domain("-example.com"));
+            assert_err!(parse_domain("example-.com"));
+        }
+
+        #[test]
+        fn rejects_too_long_labels_and_domains() {
+            let label = "a".repeat(63);
+            assert_ok!(parse_domain(&format!("{}.com", label)));
+            assert_err!(parse_domain(&format!("a{}.com", label)));
+
+            let domain = vec![label.as_str(); 4].join(".");
+            assert_eq!(domain.len(), 255);
+            assert_err!(parse_domain(&domain));
+        }
+    }
+}
@@ -11,7 +11,9 @@ use mail_core::{
     Source,
     context::Context,
 };
-use mail_core::context::CompositeContext;
+use mail_core::context::{CompositeContext, ResourceLoaderComponent};
+use mail_core::error::ResourceLoadingErrorKind;
+use mail_core::utils::{Enabled, Disabled};
 use mail_core::default_impl::{FsResourceLoader, simple_cpu_pool, HashedIdGen, simple_context};
 
 fn dumy_ctx(resource_loader: FsResourceLoader) -> simple_context::Context {
@@ -33,6 +35,7 @@ fn loaded_resource(path: &str, media_type: &str, name: Option<&str>) -> EncData
         iri: IRI::from_parts("path", path).unwrap(),
         use_media_type: UseMediaType::Default(MediaType::parse(media_type).unwrap()),
         use_file_name: name.map(|s|s.to_owned()),
+        use_disposition: None,
     };
 
     ctx.load_resource(&source).wait().unwrap()
@@ -53,3 +56,50 @@ fn use_name_is_used() {
     assert_eq!(enc_data.file_meta().file_name, Some("That Image".to_owned()));
 }
 
+fn sandboxed_source(path: &str) -> Source {
+    Source {
+        iri: IRI::from_parts("path", path).unwrap(),
+        use_media_type: UseMediaType::Default(MediaType::parse("text/plain").unwrap()),
+        use_file_name: None,
+        use_disposition: None,
+    }
+}
+
+#[test]
+fn sandbox_allows_paths_in_root() {
+    let resource_loader: FsResourceLoader<Enabled, Disabled, Enabled> = FsResourceLoader::new(
+        env::current_dir().unwrap().join(Path::new("./test_resources/"))
+    );
+    let ctx = dumy_ctx(FsResourceLoader::new("./"));
+
+    let enc_data = resource_loader.load_resource(&sandboxed_source("text.txt"), &ctx)
+        .wait().unwrap();
+    assert_eq!(enc_data.file_meta().file_name, Some("text.txt".to_owned()));
+}
+
+#[test]
+fn sandbox_rejects_paths_escaping_the_root() {
+    let resource_loader: FsResourceLoader<Enabled, Disabled, Enabled> = FsResourceLoader::new(
+        env::current_dir().unwrap().join(Path::new("./test_resources/"))
+    );
+    let ctx = dumy_ctx(FsResourceLoader::new("./"));
+
+    let err = resource_loader.load_resource(&sandboxed_source("../Cargo.toml"), &ctx)
+        .wait().unwrap_err();
+    assert_eq!(err.kind(), ResourceLoadingErrorKind::NotFound);
+    assert_eq!(err.source_iri().map(|iri| iri.as_str()), Some("path:../Cargo.toml"));
+}
+
+#[test]
+fn sandbox_does_not_leak_existence_of_files_outside_the_root() {
+    let resource_loader: FsResourceLoader<Enabled, Disabled, Enabled> = FsResourceLoader::new(
+        env::current_dir().unwrap().join(Path::new("./test_resources/"))
+    );
+    let ctx = dumy_ctx(FsResourceLoader::new("./"));
+
+    let existing = resource_loader.load_resource(&sandboxed_source("../Cargo.toml"), &ctx)
+        .wait().unwrap_err();
+    let missing = resource_loader.load_resource(&sandboxed_source("../Cargo.toml.missing"), &ctx)
+        .wait().unwrap_err();
+    assert_eq!(existing.kind(), missing.kind());
+}
@@ -0,0 +1,22 @@
+#![cfg(feature="default_impl_tokio")]
+extern crate mail_core;
+extern crate futures;
+extern crate tokio;
+
+use futures::{future, Future};
+use tokio::runtime::Builder;
+
+use mail_core::context::OffloaderComponent;
+use mail_core::default_impl::TokioOffloader;
+
+#[test]
+fn offloading_a_closure_works() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    let offloader = TokioOffloader::new(runtime.handle().clone());
+
+    let res = offloader
+        .offload(future::lazy(|| -> Result<u32, ()> { Ok(33u32) }))
+        .wait();
+
+    assert_eq!(res, Ok(33u32));
+}